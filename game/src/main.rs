@@ -19,6 +19,208 @@ impl Gear for CamSwitch {
     fn as_any_mut(&mut self) -> &mut dyn Any { self }
 }
 
+#[derive(Default)]
+pub struct CarController {
+    /// Camera that follows the car from behind.
+    chase_camera: Option<u64>,
+    vehicle: Option<VehicleHandle>,
+    throttle: f32,
+    steering: f32,
+    brake: bool,
+}
+
+impl CarController {
+    fn spawn(&self, game: &mut Game) -> VehicleHandle {
+        let physics = &mut game.physics;
+        let mut ground = RigidBody::new(vec![CollisionBox::BoundingBox(BoundingBox::new(
+            Point3::new(-200.0, -1.0, -200.0),
+            Point3::new(200.0, 0.0, 200.0),
+        ))]);
+        ground.movable = false;
+        physics.add_body(ground);
+
+        let mut chassis = RigidBody::new(vec![CollisionBox::BoundingBox(BoundingBox::new(
+            Point3::new(-0.9, -0.3, -2.0),
+            Point3::new(0.9, 0.3, 2.0),
+        ))]);
+        chassis.set_mass(1200.0);
+        chassis.set_position(vec3(0.0, 1.5, 0.0));
+        let chassis = physics.add_body(chassis);
+
+        let mut vehicle = Vehicle::new(chassis);
+        let front_left = vehicle.add_wheel(Wheel::new(vec3(-0.8, -0.3, -1.4), 0.35).steerable(true));
+        let front_right = vehicle.add_wheel(Wheel::new(vec3(0.8, -0.3, -1.4), 0.35).steerable(true));
+        let rear_left = vehicle.add_wheel(Wheel::new(vec3(-0.8, -0.3, 1.4), 0.35).driven(true));
+        let rear_right = vehicle.add_wheel(Wheel::new(vec3(0.8, -0.3, 1.4), 0.35).driven(true));
+        vehicle.add_anti_roll_bar(front_left, front_right, 8000.0);
+        vehicle.add_anti_roll_bar(rear_left, rear_right, 6000.0);
+        let vehicle = physics.add_vehicle(vehicle);
+
+        let car = game.hierarchy.spawn("car", NodeTransform::default());
+        game.hierarchy.attach(car, Attachment::Body(chassis));
+        if let Some(camera) = self.chase_camera {
+            let chase = game.hierarchy.spawn_child(car, "chase camera", NodeTransform::from_translation(vec3(0.0, 2.5, 8.0)));
+            game.hierarchy.attach(chase, Attachment::Camera(camera));
+        }
+
+        vehicle
+    }
+}
+
+impl Gear for CarController {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        if let GearEvent::KeyboardInput(key, state) = event {
+            let pressed = *state == ElementState::Pressed;
+            match key {
+                KeyCode::KeyI => self.throttle = if pressed { 1.0 } else { 0.0 },
+                KeyCode::KeyK => self.throttle = if pressed { -1.0 } else { 0.0 },
+                KeyCode::KeyJ => self.steering = if pressed { -1.0 } else { 0.0 },
+                KeyCode::KeyL => self.steering = if pressed { 1.0 } else { 0.0 },
+                KeyCode::Space => self.brake = pressed,
+                _ => {}
+            }
+        }
+
+        if let GearEvent::Update() = event {
+            let handle = match self.vehicle {
+                Some(handle) => handle,
+                None => *self.vehicle.insert(self.spawn(game)),
+            };
+
+            if let Some(vehicle) = game.physics.vehicle_mut(handle) {
+                vehicle.set_throttle(self.throttle);
+                vehicle.set_steering(self.steering);
+                vehicle.set_brake(if self.brake { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+pub struct LightSpinner {
+    light: Option<LightHandle>,
+    /// Degrees the light turns each frame, tweakable in the debug UI.
+    speed: f32,
+}
+
+impl Gear for LightSpinner {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        if let GearEvent::Update() = event {
+            let handle = *self.light.get_or_insert_with(|| {
+                game.lights.add_light(Light::point(vec3(0.0, 15.0, 10.0), [1.0, 1.0, 1.0]).with_intensity(3.0))
+            });
+
+            if let Some(light) = game.lights.light_mut(handle) {
+                light.position = Quaternion::from_axis_angle(Vector3::unit_y(), Deg(self.speed)) * light.position;
+            }
+        }
+
+        if let GearEvent::Ui(ctx) = event {
+            egui::Window::new("Light").show(ctx, |ui| {
+                ui.add(egui::Slider::new(&mut self.speed, -5.0..=5.0).text("degrees per frame"));
+            });
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Toggles the screen-space ambient occlusion with `F4`.
+pub struct EffectToggle {
+    ssao: Option<EffectHandle>,
+}
+
+impl Gear for EffectToggle {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        let handle = *self.ssao.get_or_insert_with(|| {
+            game.post_processing.insert_effect(0, Ssao::new())
+        });
+
+        if let GearEvent::KeyboardInput(KeyCode::F4, ElementState::Pressed) = event {
+            game.post_processing.toggle_effect(handle);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Switches multisample anti-aliasing between 4x and off with `F5`.
+pub struct MsaaToggle;
+
+impl Gear for MsaaToggle {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        if let GearEvent::KeyboardInput(KeyCode::F5, ElementState::Pressed) = event {
+            let sample_count = if game.render_settings.sample_count > 1 { 1 } else { 4 };
+            game.render_settings = game.render_settings.with_sample_count(sample_count);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Toggles fullscreen with `F11` and frees or recaptures the cursor with `Escape`.
+pub struct WindowControls;
+
+impl Gear for WindowControls {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        match event {
+            GearEvent::KeyboardInput(KeyCode::F11, ElementState::Pressed) => game.window.toggle_fullscreen(),
+            GearEvent::KeyboardInput(KeyCode::Escape, ElementState::Pressed) => {
+                if game.window.is_cursor_captured() {
+                    game.window.release_cursor();
+                } else {
+                    game.window.capture_cursor();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Shows `character.glb` from the asset roots, if there is one, and cross-fades it between
+/// its `idle` and `walk` clips with `F6`.
+#[derive(Default)]
+pub struct CharacterAnimation {
+    character: Option<InstanceHandle>,
+    walking: bool,
+    /// Whether the asset roots were already looked through for the model.
+    searched: bool,
+}
+
+impl Gear for CharacterAnimation {
+    fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+        // Looked up once rather than on every event while the file is missing
+        if !self.searched && matches!(event, GearEvent::Update()) {
+            self.searched = true;
+            if game.assets.resolve("character.glb").is_ok() {
+                let model = game.scene.load_model("character.glb");
+                let instance = ModelInstance::new(model).with_position(vec3(0.0, 0.0, 10.0)).with_animation(AnimationPlayer::new("idle"));
+                self.character = Some(game.scene.add_instance(instance));
+            }
+        }
+
+        if let GearEvent::KeyboardInput(KeyCode::F6, ElementState::Pressed) = event {
+            self.walking = !self.walking;
+            let clip = if self.walking { "walk" } else { "idle" };
+            let player = self.character.and_then(|handle| game.scene.instance_mut(handle)).and_then(|instance| instance.animation.as_mut());
+            if let Some(player) = player {
+                player.cross_fade(clip, 0.3);
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
 fn custom_handle(camera: &mut Camera, event: &GearEvent, game: &mut Game) {
     if let GearEvent::KeyboardInput(..) = event {
         if camera.get_id() == game.cameras.active_camera_id().expect("no camera found") {
@@ -29,13 +231,51 @@ fn custom_handle(camera: &mut Camera, event: &GearEvent, game: &mut Game) {
 
 pub fn main() {
     let camera1 = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
-    let camera2 = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
+    let camera2 = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(-10.0));
 
     let mut camera3 = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
     camera3.set_handle(custom_handle);
 
-    Game::new()
+    let mut game = Game::new();
+    game.window = WindowConfig::new("Rusty Gears").with_size(1280, 720);
+    game.render_settings = RenderSettings::new().with_sample_count(4);
+    if cfg!(debug_assertions) {
+        game.assets.set_shader_dir(Some(concat!(env!("CARGO_MANIFEST_DIR"), "/../src/wgpu").into()));
+        game.assets.set_hot_reload(true);
+    }
+    game.ui.enabled = cfg!(debug_assertions);
+    game.lights.add_light(Light::directional(vec3(-0.3, -1.0, -0.4), [1.0, 0.95, 0.85]).with_intensity(2.0).with_shadows(ShadowSettings::default()));
+    if std::path::Path::new("res/sky.hdr").exists() {
+        game.environment.set_skybox(Skybox::equirectangular("sky.hdr"));
+    }
+    game.environment.set_fog(Fog::exponential([0.55, 0.6, 0.68], 0.004).with_height(0.0, 0.08, 0.02));
+    // A 10x10 grid of trucks, each tilted away from the center
+    let semi = game.scene.load_model("semi.obj");
+    game.scene.set_lods(semi, LodSettings::new().with_generated(LodGeneration::default()));
+    for z in 0..10 {
+        for x in 0..10 {
+            let position = vec3(30.0 * (x as f32 - 5.0), 0.0, 30.0 * (z as f32 - 5.0));
+            let rotation = if position.is_zero() {
+                Quaternion::one()
+            } else {
+                Quaternion::from_axis_angle(position.normalize(), Deg(45.0))
+            };
+            game.scene.add_instance(ModelInstance::new(semi).with_position(position).with_rotation(rotation));
+        }
+    }
+    game.post_processing.set_tone_mapper(ToneMapper::Aces);
+    game.post_processing.set_bloom(Bloom::new().with_threshold(1.2).with_intensity(0.08));
+    game.post_processing.add_effect(Fxaa::new());
+    game.post_processing.add_effect(Vignette::new());
+
+    game
         .add_gear(CamSwitch)
+        .add_gear(LightSpinner { light: None, speed: 1.0 })
+        .add_gear(CarController { chase_camera: Some(camera2.get_id()), ..Default::default() })
+        .add_gear(EffectToggle { ssao: None })
+        .add_gear(MsaaToggle)
+        .add_gear(WindowControls)
+        .add_gear(CharacterAnimation::default())
         .add_camera(camera1)
         .add_camera(camera2)
         .add_camera(camera3)
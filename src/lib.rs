@@ -9,18 +9,48 @@ use physics::*;
 pub use physics::{
     PhysicsWorld,
     RigidBody,
+    BodyHandle,
+    WorldBounds,
+    BoundsPolicy,
+    BoundsEvent,
+    ForceFieldHandle,
+    ForceField,
+    Falloff,
+    PointAttractor,
+    Explosion,
+    WindVolume,
+    BuoyancyVolume,
+    RaycastHit,
+    Vehicle,
+    VehicleHandle,
+    Wheel,
+    WheelContact,
+    Engine,
+    TorqueCurve,
+    TireModel,
+    AntiRollBar,
+    ContactPoint,
+};
+
+pub use physics::collision_box::{
+    CollisionBox,
+    BoundingBox,
+    Sphere,
 };
 
 pub use cgmath::{
+    Point3,
     Vector3,
     vec3,
     Rotation3,
     Quaternion,
     Deg,
     Zero,
-    One
+    One,
+    InnerSpace
 };
 
+pub use egui;
 pub use time::Time;
 pub use rustygears::*;
 pub use winit::*;
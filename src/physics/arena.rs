@@ -0,0 +1,214 @@
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Index {
+    index: u32,
+    generation: u32,
+}
+
+impl Index {
+    pub(crate) fn slot(&self) -> usize {
+        self.index as usize
+    }
+}
+
+enum Slot<T> {
+    Occupied { generation: u32, value: T },
+    Free { generation: u32, next_free: Option<u32> },
+}
+
+/// Generational arena.
+///
+/// Removed slots are reused, but every reuse bumps the slot generation so
+/// stale `Index` values stop resolving instead of pointing at a new value.
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    free_head: Option<u32>,
+    len: usize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Arena {
+            slots: Vec::new(),
+            free_head: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn insert(&mut self, value: T) -> Index {
+        self.len += 1;
+
+        if let Some(free) = self.free_head {
+            let slot = &mut self.slots[free as usize];
+            let (generation, next_free) = match slot {
+                Slot::Free { generation, next_free } => (*generation, *next_free),
+                Slot::Occupied { .. } => unreachable!("free list points at an occupied slot"),
+            };
+            *slot = Slot::Occupied { generation, value };
+            self.free_head = next_free;
+            return Index { index: free, generation };
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot::Occupied { generation: 0, value });
+        Index { index, generation: 0 }
+    }
+
+    pub fn remove(&mut self, index: Index) -> Option<T> {
+        if !self.contains(index) {
+            return None;
+        }
+
+        let free = Slot::Free {
+            generation: index.generation.wrapping_add(1),
+            next_free: self.free_head,
+        };
+
+        match std::mem::replace(&mut self.slots[index.slot()], free) {
+            Slot::Occupied { value, .. } => {
+                self.free_head = Some(index.index);
+                self.len -= 1;
+                Some(value)
+            }
+            Slot::Free { .. } => unreachable!(),
+        }
+    }
+
+    pub fn contains(&self, index: Index) -> bool {
+        self.get(index).is_some()
+    }
+
+    pub fn get(&self, index: Index) -> Option<&T> {
+        match self.slots.get(index.slot()) {
+            Some(Slot::Occupied { generation, value }) if *generation == index.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, index: Index) -> Option<&mut T> {
+        match self.slots.get_mut(index.slot()) {
+            Some(Slot::Occupied { generation, value }) if *generation == index.generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns mutable references to two distinct values at once.
+    pub fn get2_mut(&mut self, a: Index, b: Index) -> (Option<&mut T>, Option<&mut T>) {
+        if a.slot() == b.slot() {
+            return if self.contains(a) { (self.get_mut(a), None) } else { (None, self.get_mut(b)) };
+        }
+
+        let (low, high, swapped) = if a.slot() < b.slot() { (a, b, false) } else { (b, a, true) };
+        let (head, tail) = self.slots.split_at_mut(high.slot());
+
+        let low_value = match &mut head[low.slot()] {
+            Slot::Occupied { generation, value } if *generation == low.generation => Some(value),
+            _ => None,
+        };
+        let high_value = match &mut tail[0] {
+            Slot::Occupied { generation, value } if *generation == high.generation => Some(value),
+            _ => None,
+        };
+
+        if swapped { (high_value, low_value) } else { (low_value, high_value) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Index, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| match slot {
+            Slot::Occupied { generation, value } => Some((Index { index: i as u32, generation: *generation }, value)),
+            Slot::Free { .. } => None,
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Index, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(i, slot)| match slot {
+            Slot::Occupied { generation, value } => Some((Index { index: i as u32, generation: *generation }, value)),
+            Slot::Free { .. } => None,
+        })
+    }
+}
+
+impl<T: Send> Arena<T> {
+    pub fn par_values_mut(&mut self) -> impl ParallelIterator<Item = &mut T> {
+        self.slots.par_iter_mut().filter_map(|slot| match slot {
+            Slot::Occupied { value, .. } => Some(value),
+            Slot::Free { .. } => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_index_is_rejected_after_reuse() {
+        let mut arena = Arena::new();
+        let old = arena.insert("old");
+        assert_eq!(arena.remove(old), Some("old"));
+
+        let new = arena.insert("new");
+        assert_eq!(old.slot(), new.slot());
+        assert_ne!(old, new);
+
+        assert_eq!(arena.get(old), None);
+        assert_eq!(arena.get_mut(old), None);
+        assert_eq!(arena.remove(old), None);
+        assert_eq!(arena.get(new), Some(&"new"));
+        assert_eq!(arena.len(), 1);
+    }
+
+    #[test]
+    fn get2_mut_returns_both_distinct_values() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        let b = arena.insert(2);
+
+        let (x, y) = arena.get2_mut(b, a);
+        *x.unwrap() += 10;
+        *y.unwrap() += 20;
+
+        assert_eq!(arena.get(a), Some(&21));
+        assert_eq!(arena.get(b), Some(&12));
+    }
+
+    #[test]
+    fn get2_mut_with_same_slot_returns_one_value() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+
+        let (x, y) = arena.get2_mut(a, a);
+        assert_eq!(x, Some(&mut 1));
+        assert_eq!(y, None);
+
+        arena.remove(a);
+        let reused = arena.insert(2);
+        let (x, y) = arena.get2_mut(a, reused);
+        assert_eq!(x, None);
+        assert_eq!(y, Some(&mut 2));
+    }
+
+    #[test]
+    fn get2_mut_rejects_stale_second_index() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        let stale = arena.insert(2);
+        arena.remove(stale);
+        arena.insert(3);
+
+        let (x, y) = arena.get2_mut(a, stale);
+        assert_eq!(x, Some(&mut 1));
+        assert_eq!(y, None);
+    }
+}
@@ -11,9 +11,6 @@ use cgmath::{
 };
 use super::collision_box::*;
 
-use std::sync::Mutex;
-use std::sync::Arc;
-
 #[derive(Clone)]
 pub struct RigidBody {
     pub position: Vector3<f32>,
@@ -37,10 +34,8 @@ pub struct RigidBody {
     pub friction_coefficient: f32,
 }
 
-pub type BodyRef = Arc<Mutex<RigidBody>>;
-
 impl RigidBody {
-    pub fn new(collision_box: Vec<CollisionBox>) -> BodyRef {
+    pub fn new(collision_box: Vec<CollisionBox>) -> RigidBody {
         let mut body: RigidBody = RigidBody {
             position: Vector3::zero(),
             correction: Vector3::zero(),
@@ -63,10 +58,10 @@ impl RigidBody {
 
         body.set_position(body.position);
         body.update_inertia_tensor();
-        Arc::new(Mutex::new(body))
+        body
     }
 
-    //pub fn with_bboxes(model: &Model) -> BodyRef {
+    //pub fn with_bboxes(model: &Model) -> RigidBody {
     //    let collision_box = model.get_meshes().iter().map(|mesh| {
     //        CollisionBox::BoundingBox(mesh.calculate_bounding_box())
     //    }).collect();
@@ -74,7 +69,7 @@ impl RigidBody {
     //    RigidBody::new(collision_box)
     //}
     //
-    //pub fn with_spheres(model: &Model) -> BodyRef {
+    //pub fn with_spheres(model: &Model) -> RigidBody {
     //    let collision_box = model.get_meshes().iter().map(|mesh| {
     //        CollisionBox::Sphere(mesh.calculate_sphere())
     //    }).collect();
@@ -82,7 +77,7 @@ impl RigidBody {
     //    RigidBody::new(collision_box)
     //}
     //
-    //pub fn with_single_bbox(model: &Model) -> BodyRef {
+    //pub fn with_single_bbox(model: &Model) -> RigidBody {
     //    let mut min = Vector3::from_value(f32::MAX);
     //    let mut max = Vector3::from_value(f32::MIN);
     //
@@ -108,7 +103,7 @@ impl RigidBody {
     //}
     //
     //
-    //pub fn with_single_sphere(model: &Model) -> BodyRef {
+    //pub fn with_single_sphere(model: &Model) -> RigidBody {
     //    let mut center = Vector3::zero();
     //    let mut total_vertices = 0;
     //
@@ -179,6 +174,12 @@ impl RigidBody {
         self.forces += force;
     }
 
+    /// Applies `force` at a world-space `point`, adding the torque it produces around the body center.
+    pub fn apply_force_at_point(&mut self, force: Vector3<f32>, point: Vector3<f32>) {
+        self.apply_force(force);
+        self.apply_torque((point - self.position).cross(force));
+    }
+
     pub fn apply_torque(&mut self, torque: Vector3<f32>) {
         self.torque += torque;
     }
@@ -0,0 +1,156 @@
+use cgmath::{
+    Vector3,
+    InnerSpace,
+    Zero,
+};
+use super::world::BodyHandle;
+
+/// Region of space in which bodies are simulated.
+#[derive(Clone, Copy, Debug)]
+pub enum WorldBounds {
+    Sphere { center: Vector3<f32>, radius: f32 },
+    Aabb { min: Vector3<f32>, max: Vector3<f32> },
+}
+
+impl WorldBounds {
+    pub fn sphere(center: Vector3<f32>, radius: f32) -> Self {
+        WorldBounds::Sphere { center, radius }
+    }
+
+    pub fn aabb(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        WorldBounds::Aabb { min, max }
+    }
+
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        match self {
+            WorldBounds::Sphere { center, radius } => (point - center).magnitude() <= *radius,
+            WorldBounds::Aabb { min, max } => {
+                point.x >= min.x && point.x <= max.x &&
+                point.y >= min.y && point.y <= max.y &&
+                point.z >= min.z && point.z <= max.z
+            }
+        }
+    }
+
+    /// Returns the point inside the bounds closest to `point`.
+    pub fn clamp(&self, point: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            WorldBounds::Sphere { center, radius } => {
+                let offset = point - center;
+                if offset.magnitude() <= *radius {
+                    point
+                } else {
+                    center + offset.normalize() * *radius
+                }
+            }
+            WorldBounds::Aabb { min, max } => Vector3::new(
+                point.x.max(min.x).min(max.x),
+                point.y.max(min.y).min(max.y),
+                point.z.max(min.z).min(max.z),
+            ),
+        }
+    }
+
+    /// Returns the bounds moved inwards by `margin` on every side.
+    pub(crate) fn shrink(&self, margin: f32) -> Self {
+        match *self {
+            WorldBounds::Sphere { center, radius } => WorldBounds::Sphere { center, radius: (radius - margin).max(0.0) },
+            WorldBounds::Aabb { min, max } => {
+                let center = (min + max) * 0.5;
+                let margin = Vector3::new(margin, margin, margin);
+                WorldBounds::Aabb {
+                    min: Vector3::new((min.x + margin.x).min(center.x), (min.y + margin.y).min(center.y), (min.z + margin.z).min(center.z)),
+                    max: Vector3::new((max.x - margin.x).max(center.x), (max.y - margin.y).max(center.y), (max.z - margin.z).max(center.z)),
+                }
+            }
+        }
+    }
+
+    /// Outward direction at the boundary closest to `point`, used to stop clamped bodies.
+    pub(crate) fn outward_normal(&self, point: Vector3<f32>) -> Vector3<f32> {
+        let inside = self.clamp(point);
+        let offset = point - inside;
+        if offset.magnitude2() > 0.0 {
+            offset.normalize()
+        } else {
+            Vector3::zero()
+        }
+    }
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        WorldBounds::sphere(Vector3::zero(), 1000.0)
+    }
+}
+
+/// What the world does with a body that leaves its bounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BoundsPolicy {
+    /// The body is removed from the world and its handle becomes invalid.
+    #[default]
+    Remove,
+    /// The body is moved back just inside the boundary and loses its outward velocity.
+    Clamp,
+    /// The body is moved to the given position and stopped.
+    Teleport(Vector3<f32>),
+    /// The body is stopped where it is and made immovable.
+    Freeze,
+}
+
+/// Reported when a body leaves the world bounds.
+///
+/// A body is reported again only after it has moved back well inside the bounds,
+/// so a clamped body resting against the boundary does not produce an event every frame.
+#[derive(Clone, Copy, Debug)]
+pub struct BoundsEvent {
+    pub handle: BodyHandle,
+    /// Position at which the body was found outside the bounds.
+    pub position: Vector3<f32>,
+    /// Policy that was applied to the body.
+    pub policy: BoundsPolicy,
+}
+
+pub type BoundsHandler = Box<dyn FnMut(&BoundsEvent) + Send + Sync>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_moves_points_onto_the_boundary() {
+        let sphere = WorldBounds::sphere(Vector3::zero(), 10.0);
+        assert_eq!(sphere.clamp(Vector3::new(0.0, 20.0, 0.0)), Vector3::new(0.0, 10.0, 0.0));
+        assert_eq!(sphere.clamp(Vector3::new(1.0, 2.0, 3.0)), Vector3::new(1.0, 2.0, 3.0));
+
+        let aabb = WorldBounds::aabb(Vector3::new(-1.0, 0.0, -1.0), Vector3::new(1.0, 5.0, 1.0));
+        assert_eq!(aabb.clamp(Vector3::new(3.0, -2.0, 0.5)), Vector3::new(1.0, 0.0, 0.5));
+        assert!(aabb.contains(aabb.clamp(Vector3::new(-7.0, 9.0, 4.0))));
+    }
+
+    #[test]
+    fn outward_normal_points_away_from_the_bounds() {
+        let sphere = WorldBounds::sphere(Vector3::zero(), 10.0);
+        let normal = sphere.outward_normal(Vector3::new(0.0, 0.0, -15.0));
+        assert!((normal - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-6);
+
+        let aabb = WorldBounds::aabb(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        assert_eq!(aabb.outward_normal(Vector3::new(0.0, 4.0, 0.0)), Vector3::unit_y());
+        assert_eq!(aabb.outward_normal(Vector3::new(0.5, 0.0, 0.0)), Vector3::zero());
+    }
+
+    #[test]
+    fn shrink_never_inverts_the_bounds() {
+        let aabb = WorldBounds::aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(10.0, 1.0, 10.0));
+        let WorldBounds::Aabb { min, max } = aabb.shrink(2.0) else {
+            panic!("shrinking changed the kind of bounds");
+        };
+        assert_eq!(min, Vector3::new(2.0, 0.5, 2.0));
+        assert_eq!(max, Vector3::new(8.0, 0.5, 8.0));
+
+        let WorldBounds::Sphere { radius, .. } = WorldBounds::sphere(Vector3::zero(), 1.0).shrink(3.0) else {
+            panic!("shrinking changed the kind of bounds");
+        };
+        assert_eq!(radius, 0.0);
+    }
+}
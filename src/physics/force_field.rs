@@ -0,0 +1,498 @@
+use cgmath::{
+    Vector3,
+    Matrix,
+    Matrix3,
+    EuclideanSpace,
+    InnerSpace,
+    Zero,
+};
+use std::f32::consts::PI;
+use super::body::*;
+use super::bounds::*;
+use super::collision_box::*;
+
+/// How the strength of a radial field decreases with distance from its center.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Falloff {
+    Constant,
+    #[default]
+    Linear,
+    InverseSquare,
+}
+
+impl Falloff {
+    fn factor(&self, distance: f32, radius: f32) -> f32 {
+        if distance > radius {
+            return 0.0;
+        }
+        match self {
+            Falloff::Constant => 1.0,
+            Falloff::Linear => 1.0 - distance / radius,
+            Falloff::InverseSquare => 1.0 / (1.0 + distance * distance),
+        }
+    }
+}
+
+/// Pulls (or with negative strength pushes) bodies toward a point.
+#[derive(Clone, Copy, Debug)]
+pub struct PointAttractor {
+    pub position: Vector3<f32>,
+    pub strength: f32,
+    pub radius: f32,
+    pub falloff: Falloff,
+    /// Scales the force by body mass, so every body accelerates equally like under gravity.
+    pub mass_proportional: bool,
+}
+
+/// Radial push applied at the point of each body closest to the center, so off-center
+/// hits also spin the body. The field removes itself once `duration` has elapsed.
+#[derive(Clone, Copy, Debug)]
+pub struct Explosion {
+    pub center: Vector3<f32>,
+    pub strength: f32,
+    pub radius: f32,
+    pub falloff: Falloff,
+    pub duration: f32,
+}
+
+/// Drags bodies inside `region` toward the wind velocity.
+#[derive(Clone, Copy, Debug)]
+pub struct WindVolume {
+    pub region: WorldBounds,
+    pub velocity: Vector3<f32>,
+    pub drag: f32,
+}
+
+/// Box of fluid spanning `min`..`max`, with its surface at `max.y`.
+#[derive(Clone, Copy, Debug)]
+pub struct BuoyancyVolume {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+    pub fluid_density: f32,
+    pub linear_drag: f32,
+    pub angular_drag: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ForceField {
+    Attractor(PointAttractor),
+    Explosion(Explosion),
+    Wind(WindVolume),
+    Buoyancy(BuoyancyVolume),
+}
+
+impl From<PointAttractor> for ForceField {
+    fn from(field: PointAttractor) -> Self {
+        ForceField::Attractor(field)
+    }
+}
+
+impl From<Explosion> for ForceField {
+    fn from(field: Explosion) -> Self {
+        ForceField::Explosion(field)
+    }
+}
+
+impl From<WindVolume> for ForceField {
+    fn from(field: WindVolume) -> Self {
+        ForceField::Wind(field)
+    }
+}
+
+impl From<BuoyancyVolume> for ForceField {
+    fn from(field: BuoyancyVolume) -> Self {
+        ForceField::Buoyancy(field)
+    }
+}
+
+impl PointAttractor {
+    pub fn new(position: Vector3<f32>, strength: f32, radius: f32) -> Self {
+        PointAttractor {
+            position,
+            strength,
+            radius,
+            falloff: Falloff::InverseSquare,
+            mass_proportional: true,
+        }
+    }
+
+    fn apply(&self, body: &mut RigidBody) {
+        let offset = self.position - body.position;
+        let distance = offset.magnitude();
+        if distance < 1e-4 {
+            return;
+        }
+
+        let mut magnitude = self.strength * self.falloff.factor(distance, self.radius);
+        if self.mass_proportional {
+            magnitude *= body.mass;
+        }
+        body.apply_force(offset / distance * magnitude);
+    }
+}
+
+impl Explosion {
+    pub fn new(center: Vector3<f32>, strength: f32, radius: f32) -> Self {
+        Explosion {
+            center,
+            strength,
+            radius,
+            falloff: Falloff::Linear,
+            duration: 0.05,
+        }
+    }
+
+    fn apply(&self, body: &mut RigidBody) {
+        let contact_point = closest_surface_point(body, self.center);
+        let offset = contact_point - self.center;
+        let distance = offset.magnitude();
+
+        let direction = if distance > 1e-4 {
+            offset / distance
+        } else {
+            let to_body = body.position - self.center;
+            if to_body.magnitude2() < 1e-8 {
+                return;
+            }
+            to_body.normalize()
+        };
+
+        let magnitude = self.strength * self.falloff.factor(distance, self.radius);
+        if magnitude != 0.0 {
+            body.apply_force_at_point(direction * magnitude, contact_point);
+        }
+    }
+}
+
+impl WindVolume {
+    pub fn new(region: WorldBounds, velocity: Vector3<f32>, drag: f32) -> Self {
+        WindVolume { region, velocity, drag }
+    }
+
+    fn apply(&self, body: &mut RigidBody) {
+        if !self.region.contains(body.position) {
+            return;
+        }
+        let relative = self.velocity - body.velocity;
+        body.apply_force(relative * self.drag);
+    }
+}
+
+impl BuoyancyVolume {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>, fluid_density: f32) -> Self {
+        BuoyancyVolume {
+            min,
+            max,
+            fluid_density,
+            linear_drag: 1.0,
+            angular_drag: 0.5,
+        }
+    }
+
+    fn surface(&self) -> f32 {
+        self.max.y
+    }
+
+    /// Clips a shape's XZ footprint, given by its corners, against the volume's. Returns the
+    /// fraction of the footprint inside and the center of that part, or `None` if no part is.
+    fn clip_xz(&self, min: [f32; 2], max: [f32; 2]) -> Option<(f32, [f32; 2])> {
+        let low = [min[0].max(self.min.x), min[1].max(self.min.z)];
+        let high = [max[0].min(self.max.x), max[1].min(self.max.z)];
+        if low[0] >= high[0] || low[1] >= high[1] {
+            return None;
+        }
+
+        let area = (max[0] - min[0]) * (max[1] - min[1]);
+        let inside = (high[0] - low[0]) * (high[1] - low[1]);
+        let fraction = if area > 0.0 { (inside / area).min(1.0) } else { 1.0 };
+        Some((fraction, [(low[0] + high[0]) / 2.0, (low[1] + high[1]) / 2.0]))
+    }
+
+    fn apply(&self, body: &mut RigidBody, gravity: Vector3<f32>) {
+        let mut submerged_volume = 0.0;
+        let mut weighted_center = Vector3::zero();
+
+        for shape in &body.collision_box {
+            let (footprint_min, footprint_max) = footprint(shape, &body.rotation);
+            let Some((fraction, footprint_center)) = self.clip_xz(footprint_min, footprint_max) else {
+                continue;
+            };
+            let (volume, center) = match shape {
+                CollisionBox::Sphere(sphere) => submerged_sphere(sphere, self.surface(), self.min.y),
+                CollisionBox::BoundingBox(bbox) => submerged_box(bbox, &body.rotation, self.surface(), self.min.y),
+            };
+            // Only the part of the shape above the volume's footprint is in the fluid
+            let volume = volume * fraction;
+            let center = Vector3::new(footprint_center[0], center.y, footprint_center[1]);
+            submerged_volume += volume;
+            weighted_center += center * volume;
+        }
+
+        if submerged_volume <= 0.0 {
+            return;
+        }
+
+        let center_of_buoyancy = weighted_center / submerged_volume;
+        let buoyancy = -gravity * self.fluid_density * submerged_volume;
+        body.apply_force_at_point(buoyancy, center_of_buoyancy);
+
+        let total_volume = body_volume(body);
+        let fraction = if total_volume > 0.0 { (submerged_volume / total_volume).min(1.0) } else { 1.0 };
+        let velocity = body.velocity;
+        let angular_velocity = body.angular_velocity;
+        body.apply_force(-velocity * self.linear_drag * fraction * body.mass);
+        body.apply_torque(-angular_velocity * self.angular_drag * fraction * body.mass);
+    }
+}
+
+impl ForceField {
+    pub(crate) fn apply(&self, body: &mut RigidBody, gravity: Vector3<f32>) {
+        if !body.movable {
+            return;
+        }
+        match self {
+            ForceField::Attractor(field) => field.apply(body),
+            ForceField::Explosion(field) => field.apply(body),
+            ForceField::Wind(field) => field.apply(body),
+            ForceField::Buoyancy(field) => field.apply(body, gravity),
+        }
+    }
+
+    /// Seconds after which the field stops acting, if it is not permanent.
+    pub(crate) fn lifetime(&self) -> Option<f32> {
+        match self {
+            ForceField::Explosion(field) => Some(field.duration),
+            _ => None,
+        }
+    }
+}
+
+fn closest_surface_point(body: &RigidBody, point: Vector3<f32>) -> Vector3<f32> {
+    let mut closest = body.position;
+    let mut closest_distance = f32::MAX;
+
+    for shape in &body.collision_box {
+        let candidate = match shape {
+            CollisionBox::Sphere(sphere) => {
+                let center = sphere.center.to_vec();
+                let offset = point - center;
+                if offset.magnitude() <= sphere.radius || offset.magnitude2() < 1e-8 {
+                    point
+                } else {
+                    center + offset.normalize() * sphere.radius
+                }
+            }
+            CollisionBox::BoundingBox(bbox) => {
+                let rotation = Matrix3::from(body.rotation);
+                let center = bbox.center().to_vec();
+                let half = bbox.size() / 2.0;
+                let local = rotation.transpose() * (point - center);
+                let clamped = Vector3::new(
+                    local.x.max(-half.x).min(half.x),
+                    local.y.max(-half.y).min(half.y),
+                    local.z.max(-half.z).min(half.z),
+                );
+                center + rotation * clamped
+            }
+        };
+
+        let distance = (candidate - point).magnitude2();
+        if distance < closest_distance {
+            closest_distance = distance;
+            closest = candidate;
+        }
+    }
+
+    closest
+}
+
+/// XZ bounds of a shape, as the corners of the rectangle it covers from above.
+fn footprint(shape: &CollisionBox, rotation: &cgmath::Quaternion<f32>) -> ([f32; 2], [f32; 2]) {
+    match shape {
+        CollisionBox::Sphere(sphere) => {
+            let center = sphere.center;
+            let r = sphere.radius;
+            ([center.x - r, center.z - r], [center.x + r, center.z + r])
+        }
+        CollisionBox::BoundingBox(bbox) => {
+            let corners = bbox.rotated_points(rotation);
+            let mut min = [f32::MAX; 2];
+            let mut max = [f32::MIN; 2];
+            for corner in &corners {
+                min = [min[0].min(corner.x), min[1].min(corner.z)];
+                max = [max[0].max(corner.x), max[1].max(corner.z)];
+            }
+            (min, max)
+        }
+    }
+}
+
+/// Volume of a sphere of radius `r` below `depth` from its bottom, and the height of its
+/// centroid above the bottom.
+fn sphere_cap(r: f32, depth: f32) -> (f32, f32) {
+    let depth = depth.clamp(0.0, 2.0 * r);
+    if depth <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let volume = PI * depth * depth * (3.0 * r - depth) / 3.0;
+    // Centroid of a spherical cap of height `depth`, measured from the sphere center.
+    let cap_offset = 3.0 * (2.0 * r - depth).powi(2) / (4.0 * (3.0 * r - depth));
+    (volume, r - cap_offset)
+}
+
+/// Volume of the slice of the sphere between `floor` and `surface`, and its centroid.
+fn submerged_sphere(sphere: &Sphere, surface: f32, floor: f32) -> (f32, Vector3<f32>) {
+    let center = sphere.center.to_vec();
+    let r = sphere.radius;
+    let bottom = center.y - r;
+
+    if bottom >= surface || center.y + r <= floor {
+        return (0.0, center);
+    }
+
+    // The part below the floor is outside the fluid: take the cap below the surface minus
+    // the cap below the floor
+    let (wet_volume, wet_height) = sphere_cap(r, surface - bottom);
+    let (dry_volume, dry_height) = sphere_cap(r, floor - bottom);
+    let volume = wet_volume - dry_volume;
+    if volume <= 0.0 {
+        return (0.0, center);
+    }
+    let height = (wet_volume * wet_height - dry_volume * dry_height) / volume;
+    let centroid = Vector3::new(center.x, bottom + height, center.z);
+
+    (volume, centroid)
+}
+
+fn submerged_box(bbox: &BoundingBox, rotation: &cgmath::Quaternion<f32>, surface: f32, floor: f32) -> (f32, Vector3<f32>) {
+    let corners = bbox.rotated_points(rotation);
+    let bottom = corners.iter().map(|c| c.y).fold(f32::MAX, f32::min);
+    let top = corners.iter().map(|c| c.y).fold(f32::MIN, f32::max);
+    let center = bbox.center().to_vec();
+
+    if bottom >= surface || top <= floor || top <= bottom {
+        return (0.0, center);
+    }
+
+    let size = bbox.size();
+    let submerged_bottom = bottom.max(floor);
+    let submerged_top = top.min(surface);
+    let fraction = ((submerged_top - submerged_bottom) / (top - bottom)).clamp(0.0, 1.0);
+    let volume = size.x * size.y * size.z * fraction;
+    let centroid = Vector3::new(center.x, (submerged_bottom + submerged_top) / 2.0, center.z);
+
+    (volume, centroid)
+}
+
+fn body_volume(body: &RigidBody) -> f32 {
+    body.collision_box
+        .iter()
+        .map(|shape| match shape {
+            CollisionBox::Sphere(sphere) => 4.0 / 3.0 * PI * sphere.radius.powi(3),
+            CollisionBox::BoundingBox(bbox) => {
+                let size = bbox.size();
+                size.x * size.y * size.z
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Point3, Quaternion, One};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4 * b.abs().max(1.0)
+    }
+
+    fn sphere_body(position: Vector3<f32>, radius: f32) -> RigidBody {
+        let mut body = RigidBody::new(vec![CollisionBox::Sphere(Sphere::new(Point3::new(0.0, 0.0, 0.0), radius))]);
+        body.set_position(position);
+        body
+    }
+
+    #[test]
+    fn falloff_reaches_zero_at_the_radius() {
+        assert_eq!(Falloff::Constant.factor(4.0, 5.0), 1.0);
+        assert_eq!(Falloff::Linear.factor(0.0, 5.0), 1.0);
+        assert!(close(Falloff::Linear.factor(2.5, 5.0), 0.5));
+        assert!(close(Falloff::InverseSquare.factor(2.0, 5.0), 0.2));
+        for falloff in [Falloff::Constant, Falloff::Linear, Falloff::InverseSquare] {
+            assert_eq!(falloff.factor(5.1, 5.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn explosion_pushes_nearer_bodies_harder() {
+        let explosion = Explosion::new(Vector3::zero(), 100.0, 10.0);
+        let mut near = sphere_body(Vector3::new(3.0, 0.0, 0.0), 1.0);
+        let mut far = sphere_body(Vector3::new(7.0, 0.0, 0.0), 1.0);
+        let mut outside = sphere_body(Vector3::new(20.0, 0.0, 0.0), 1.0);
+        explosion.apply(&mut near);
+        explosion.apply(&mut far);
+        explosion.apply(&mut outside);
+
+        // Measured from the closest surface point, two units from the center
+        assert!(close(near.forces.x, 80.0));
+        assert!(close(far.forces.x, 40.0));
+        assert_eq!(near.forces.y, 0.0);
+        assert_eq!(outside.forces, Vector3::zero());
+    }
+
+    #[test]
+    fn submerged_sphere_volume_and_centroid() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let full = 4.0 / 3.0 * PI;
+
+        let (volume, _) = submerged_sphere(&sphere, 5.0, -5.0);
+        assert!(close(volume, full));
+        let (volume, centroid) = submerged_sphere(&sphere, 0.0, -5.0);
+        assert!(close(volume, full / 2.0));
+        assert!(close(centroid.y, -3.0 / 8.0));
+        let (volume, _) = submerged_sphere(&sphere, -2.0, -5.0);
+        assert_eq!(volume, 0.0);
+
+        // Poking through the floor: only the part above it is in the fluid
+        let (volume, centroid) = submerged_sphere(&sphere, 5.0, 0.0);
+        assert!(close(volume, full / 2.0));
+        assert!(close(centroid.y, 3.0 / 8.0));
+    }
+
+    #[test]
+    fn submerged_box_is_clamped_to_surface_and_floor() {
+        let bbox = BoundingBox::new(Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 4.0, 1.0));
+        let rotation = Quaternion::one();
+
+        let (volume, centroid) = submerged_box(&bbox, &rotation, 1.0, -10.0);
+        assert!(close(volume, 4.0));
+        assert!(close(centroid.y, 0.5));
+
+        let (volume, centroid) = submerged_box(&bbox, &rotation, 10.0, 3.0);
+        assert!(close(volume, 4.0));
+        assert!(close(centroid.y, 3.5));
+
+        let (volume, _) = submerged_box(&bbox, &rotation, 10.0, 4.0);
+        assert_eq!(volume, 0.0);
+    }
+
+    #[test]
+    fn buoyancy_uses_the_part_of_the_body_over_the_volume() {
+        let water = BuoyancyVolume::new(Vector3::new(0.0, -10.0, -10.0), Vector3::new(10.0, 0.0, 10.0), 1.0);
+        let gravity = Vector3::new(0.0, -10.0, 0.0);
+        let full = 4.0 / 3.0 * PI * 10.0;
+
+        let mut inside = sphere_body(Vector3::new(5.0, -5.0, 0.0), 1.0);
+        water.apply(&mut inside, gravity);
+        assert!(close(inside.forces.y, full));
+
+        // Center half a unit outside the edge: a quarter of its footprint is over the water
+        let mut straddling = sphere_body(Vector3::new(-0.5, -5.0, 0.0), 1.0);
+        water.apply(&mut straddling, gravity);
+        assert!(close(straddling.forces.y, full * 0.25));
+
+        let mut outside = sphere_body(Vector3::new(-2.0, -5.0, 0.0), 1.0);
+        water.apply(&mut outside, gravity);
+        assert_eq!(outside.forces, Vector3::zero());
+    }
+}
@@ -1,8 +1,17 @@
+pub(crate) mod arena;
 pub mod body;
+pub mod bounds;
 mod collision;
 pub mod collision_box;
+pub mod force_field;
+pub mod raycast;
+pub mod vehicle;
 pub mod world;
 mod octree;
 
 pub use body::*;
+pub use bounds::*;
+pub use force_field::*;
+pub use raycast::*;
+pub use vehicle::*;
 pub use world::*;
@@ -0,0 +1,211 @@
+use cgmath::{
+    Vector3,
+    Quaternion,
+    Matrix,
+    Matrix3,
+    EuclideanSpace,
+    InnerSpace,
+};
+use super::arena::*;
+use super::body::*;
+use super::collision_box::*;
+use super::world::BodyHandle;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub handle: BodyHandle,
+    pub point: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+/// Casts a ray against every body accepted by `filter` and returns the closest hit.
+/// `direction` does not need to be normalized.
+pub(crate) fn cast_ray(
+    bodies: &Arena<RigidBody>,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+    filter: impl Fn(BodyHandle, &RigidBody) -> bool,
+) -> Option<RaycastHit> {
+    if direction.magnitude2() < 1e-12 {
+        return None;
+    }
+    let direction = direction.normalize();
+    let mut closest: Option<RaycastHit> = None;
+
+    for (index, body) in bodies.iter() {
+        let handle = BodyHandle(index);
+        if !filter(handle, body) {
+            continue;
+        }
+
+        for shape in &body.collision_box {
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+            if let Some((distance, normal)) = ray_shape(shape, &body.rotation, origin, direction, limit) {
+                closest = Some(RaycastHit {
+                    handle,
+                    point: origin + direction * distance,
+                    normal,
+                    distance,
+                });
+            }
+        }
+    }
+
+    closest
+}
+
+fn ray_shape(
+    shape: &CollisionBox,
+    rotation: &Quaternion<f32>,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<(f32, Vector3<f32>)> {
+    match shape {
+        CollisionBox::Sphere(sphere) => ray_sphere(sphere, origin, direction, max_distance),
+        CollisionBox::BoundingBox(bbox) => ray_box(bbox, rotation, origin, direction, max_distance),
+    }
+}
+
+fn ray_sphere(sphere: &Sphere, origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<(f32, Vector3<f32>)> {
+    let center = sphere.center.to_vec();
+    let offset = origin - center;
+    let b = offset.dot(direction);
+    let c = offset.magnitude2() - sphere.radius * sphere.radius;
+
+    if c > 0.0 && b > 0.0 {
+        return None;
+    }
+
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let distance = (-b - discriminant.sqrt()).max(0.0);
+    if distance > max_distance {
+        return None;
+    }
+
+    let point = origin + direction * distance;
+    let normal = if c > 0.0 { (point - center).normalize() } else { -direction };
+    Some((distance, normal))
+}
+
+fn ray_box(
+    bbox: &BoundingBox,
+    rotation: &Quaternion<f32>,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<(f32, Vector3<f32>)> {
+    let rotation = Matrix3::from(*rotation);
+    let inverse = rotation.transpose();
+    let center = bbox.center().to_vec();
+    let half = bbox.size() / 2.0;
+
+    let local_origin = inverse * (origin - center);
+    let local_direction = inverse * direction;
+
+    let mut t_min = 0.0_f32;
+    let mut t_max = max_distance;
+    let mut hit_axis = None;
+
+    for axis in 0..3 {
+        let o = local_origin[axis];
+        let d = local_direction[axis];
+
+        if d.abs() < 1e-8 {
+            if o < -half[axis] || o > half[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let mut t0 = (-half[axis] - o) / d;
+        let mut t1 = (half[axis] - o) / d;
+        let mut sign = -1.0;
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+            sign = 1.0;
+        }
+
+        if t0 > t_min {
+            t_min = t0;
+            hit_axis = Some((axis, sign));
+        }
+        t_max = t_max.min(t1);
+
+        if t_min > t_max {
+            return None;
+        }
+    }
+
+    let mut local_normal = Vector3::new(0.0, 0.0, 0.0);
+    match hit_axis {
+        Some((axis, sign)) => local_normal[axis] = sign,
+        None => return Some((0.0, -direction)),
+    }
+
+    Some((t_min, rotation * local_normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Point3, Rad, Rotation3, One};
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    #[test]
+    fn ray_sphere_hits_the_near_side() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 5.0), 1.0);
+        let (distance, normal) = ray_sphere(&sphere, Vector3::new(0.0, 0.0, 0.0), Vector3::unit_z(), 100.0).unwrap();
+        assert!((distance - 4.0).abs() < 1e-4);
+        assert!(close(normal, -Vector3::unit_z()));
+
+        assert!(ray_sphere(&sphere, Vector3::new(0.0, 0.0, 0.0), Vector3::unit_z(), 3.0).is_none());
+        assert!(ray_sphere(&sphere, Vector3::new(0.0, 2.0, 0.0), Vector3::unit_z(), 100.0).is_none());
+        assert!(ray_sphere(&sphere, Vector3::new(0.0, 0.0, 0.0), -Vector3::unit_z(), 100.0).is_none());
+    }
+
+    #[test]
+    fn ray_sphere_from_inside_hits_at_the_origin() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 2.0);
+        let (distance, normal) = ray_sphere(&sphere, Vector3::new(0.5, 0.0, 0.0), Vector3::unit_x(), 100.0).unwrap();
+        assert_eq!(distance, 0.0);
+        assert!(close(normal, -Vector3::unit_x()));
+    }
+
+    #[test]
+    fn ray_box_reports_the_face_it_enters() {
+        let bbox = BoundingBox::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let rotation = Quaternion::one();
+
+        let (distance, normal) = ray_box(&bbox, &rotation, Vector3::new(0.0, 10.0, 0.0), -Vector3::unit_y(), 100.0).unwrap();
+        assert!((distance - 9.0).abs() < 1e-4);
+        assert!(close(normal, Vector3::unit_y()));
+
+        let (distance, normal) = ray_box(&bbox, &rotation, Vector3::new(-5.0, 0.5, 0.5), Vector3::unit_x(), 100.0).unwrap();
+        assert!((distance - 4.0).abs() < 1e-4);
+        assert!(close(normal, -Vector3::unit_x()));
+
+        assert!(ray_box(&bbox, &rotation, Vector3::new(-5.0, 2.0, 0.0), Vector3::unit_x(), 100.0).is_none());
+        assert!(ray_box(&bbox, &rotation, Vector3::new(-5.0, 0.0, 0.0), Vector3::unit_x(), 3.0).is_none());
+    }
+
+    #[test]
+    fn ray_box_follows_the_rotation() {
+        // A unit cube turned 45 degrees around y puts an edge at x = -sqrt(2)
+        let bbox = BoundingBox::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let rotation = Quaternion::from_angle_y(Rad(std::f32::consts::FRAC_PI_4));
+        let (distance, normal) = ray_box(&bbox, &rotation, Vector3::new(-5.0, 0.0, 0.1), Vector3::unit_x(), 100.0).unwrap();
+        assert!((distance - (5.0 - 2.0_f32.sqrt() + 0.1)).abs() < 1e-3);
+        assert!(normal.x < 0.0);
+        assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+    }
+}
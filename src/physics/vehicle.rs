@@ -0,0 +1,472 @@
+use cgmath::{
+    Vector3,
+    Matrix3,
+    Quaternion,
+    Rotation3,
+    InnerSpace,
+    Rad,
+};
+use std::f32::consts::PI;
+use super::arena::*;
+use super::body::*;
+use super::raycast::*;
+use super::world::BodyHandle;
+
+/// Engine torque in Nm as a function of rpm, linearly interpolated between points.
+#[derive(Clone, Debug)]
+pub struct TorqueCurve {
+    points: Vec<(f32, f32)>,
+}
+
+impl TorqueCurve {
+    pub fn new(mut points: Vec<(f32, f32)>) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        TorqueCurve { points }
+    }
+
+    pub fn sample(&self, rpm: f32) -> f32 {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return 0.0;
+        };
+        if rpm <= first.0 {
+            return first.1;
+        }
+        if rpm >= last.0 {
+            return last.1;
+        }
+
+        for pair in self.points.windows(2) {
+            let (rpm_a, torque_a) = pair[0];
+            let (rpm_b, torque_b) = pair[1];
+            if rpm <= rpm_b {
+                let t = (rpm - rpm_a) / (rpm_b - rpm_a);
+                return torque_a + (torque_b - torque_a) * t;
+            }
+        }
+        last.1
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Engine {
+    pub torque_curve: TorqueCurve,
+    pub idle_rpm: f32,
+    pub max_rpm: f32,
+    pub gear_ratio: f32,
+    pub reverse_ratio: f32,
+    pub final_drive: f32,
+    rpm: f32,
+}
+
+impl Engine {
+    pub fn new(torque_curve: TorqueCurve) -> Self {
+        Engine {
+            torque_curve,
+            idle_rpm: 900.0,
+            max_rpm: 7000.0,
+            gear_ratio: 2.5,
+            reverse_ratio: 3.0,
+            final_drive: 3.4,
+            rpm: 900.0,
+        }
+    }
+
+    pub fn rpm(&self) -> f32 {
+        self.rpm
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new(TorqueCurve::new(vec![
+            (1000.0, 200.0),
+            (3000.0, 320.0),
+            (5000.0, 380.0),
+            (6500.0, 330.0),
+            (7000.0, 0.0),
+        ]))
+    }
+}
+
+/// Slip-based tire model: forces grow linearly with slip and are capped by a friction circle.
+#[derive(Clone, Copy, Debug)]
+pub struct TireModel {
+    /// Longitudinal force per unit load per unit slip ratio.
+    pub longitudinal_stiffness: f32,
+    /// Lateral force per unit load per radian of slip angle.
+    pub lateral_stiffness: f32,
+    pub friction: f32,
+}
+
+impl Default for TireModel {
+    fn default() -> Self {
+        TireModel {
+            longitudinal_stiffness: 10.0,
+            lateral_stiffness: 8.0,
+            friction: 1.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WheelContact {
+    pub body: BodyHandle,
+    pub point: Vector3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Wheel {
+    /// Suspension top mount in chassis space.
+    pub attachment: Vector3<f32>,
+    pub radius: f32,
+    pub mass: f32,
+    pub rest_length: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub steerable: bool,
+    pub driven: bool,
+    pub max_brake_torque: f32,
+
+    compression: f32,
+    load: f32,
+    angular_velocity: f32,
+    spin_angle: f32,
+    steer_angle: f32,
+    contact: Option<WheelContact>,
+}
+
+impl Wheel {
+    pub fn new(attachment: Vector3<f32>, radius: f32) -> Self {
+        Wheel {
+            attachment,
+            radius,
+            mass: 20.0,
+            rest_length: 0.4,
+            stiffness: 35000.0,
+            damping: 3500.0,
+            steerable: false,
+            driven: false,
+            max_brake_torque: 2500.0,
+            compression: 0.0,
+            load: 0.0,
+            angular_velocity: 0.0,
+            spin_angle: 0.0,
+            steer_angle: 0.0,
+            contact: None,
+        }
+    }
+
+    pub fn steerable(mut self, steerable: bool) -> Self {
+        self.steerable = steerable;
+        self
+    }
+
+    pub fn driven(mut self, driven: bool) -> Self {
+        self.driven = driven;
+        self
+    }
+
+    pub fn compression(&self) -> f32 {
+        self.compression
+    }
+
+    /// Normal load carried by the wheel in the last step.
+    pub fn load(&self) -> f32 {
+        self.load
+    }
+
+    pub fn angular_velocity(&self) -> f32 {
+        self.angular_velocity
+    }
+
+    /// Accumulated rotation around the axle, for rendering the wheel.
+    pub fn spin_angle(&self) -> f32 {
+        self.spin_angle
+    }
+
+    pub fn steer_angle(&self) -> f32 {
+        self.steer_angle
+    }
+
+    pub fn contact(&self) -> Option<WheelContact> {
+        self.contact
+    }
+
+    fn inertia(&self) -> f32 {
+        0.5 * self.mass * self.radius * self.radius
+    }
+}
+
+/// Couples the suspension of two wheels to resist body roll.
+#[derive(Clone, Copy, Debug)]
+pub struct AntiRollBar {
+    pub left: usize,
+    pub right: usize,
+    pub stiffness: f32,
+}
+
+/// Raycast vehicle driving a chassis body owned by the world.
+///
+/// Chassis space is right-handed with `-Z` forward, `+X` right and `+Y` up.
+///
+/// ```rust
+/// use std::any::Any;
+/// use rusty_gears::*;
+///
+/// pub struct CarController {
+///     vehicle: VehicleHandle,
+///     throttle: f32,
+///     steering: f32,
+///     brake: bool,
+/// }
+///
+/// impl Gear for CarController {
+///     fn handle_event(&mut self, event: &GearEvent, game: &mut Game) {
+///         if let GearEvent::KeyboardInput(key, state) = event {
+///             let pressed = *state == ElementState::Pressed;
+///             match key {
+///                 KeyCode::KeyI => self.throttle = if pressed { 1.0 } else { 0.0 },
+///                 KeyCode::KeyK => self.throttle = if pressed { -1.0 } else { 0.0 },
+///                 KeyCode::KeyJ => self.steering = if pressed { -1.0 } else { 0.0 },
+///                 KeyCode::KeyL => self.steering = if pressed { 1.0 } else { 0.0 },
+///                 KeyCode::Space => self.brake = pressed,
+///                 _ => {}
+///             }
+///         }
+///
+///         if let Some(vehicle) = game.physics.vehicle_mut(self.vehicle) {
+///             vehicle.set_throttle(self.throttle);
+///             vehicle.set_steering(self.steering);
+///             vehicle.set_brake(if self.brake { 1.0 } else { 0.0 });
+///         }
+///     }
+///
+///     fn as_any(&self) -> &dyn Any { self }
+///     fn as_any_mut(&mut self) -> &mut dyn Any { self }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Vehicle {
+    pub chassis: BodyHandle,
+    pub wheels: Vec<Wheel>,
+    pub engine: Engine,
+    pub tire: TireModel,
+    pub anti_roll_bars: Vec<AntiRollBar>,
+    pub max_steer_angle: Rad<f32>,
+    throttle: f32,
+    brake: f32,
+    steering: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VehicleHandle(pub(crate) Index);
+
+impl Vehicle {
+    pub fn new(chassis: BodyHandle) -> Self {
+        Vehicle {
+            chassis,
+            wheels: Vec::new(),
+            engine: Engine::default(),
+            tire: TireModel::default(),
+            anti_roll_bars: Vec::new(),
+            max_steer_angle: Rad(0.6),
+            throttle: 0.0,
+            brake: 0.0,
+            steering: 0.0,
+        }
+    }
+
+    /// Adds a wheel and returns its index, used by anti-roll bars.
+    pub fn add_wheel(&mut self, wheel: Wheel) -> usize {
+        self.wheels.push(wheel);
+        self.wheels.len() - 1
+    }
+
+    pub fn add_anti_roll_bar(&mut self, left: usize, right: usize, stiffness: f32) -> &mut Self {
+        self.anti_roll_bars.push(AntiRollBar { left, right, stiffness });
+        self
+    }
+
+    /// Throttle in `[-1, 1]`; negative values drive in reverse.
+    pub fn set_throttle(&mut self, throttle: f32) {
+        self.throttle = throttle.clamp(-1.0, 1.0);
+    }
+
+    /// Brake pedal in `[0, 1]`.
+    pub fn set_brake(&mut self, brake: f32) {
+        self.brake = brake.clamp(0.0, 1.0);
+    }
+
+    /// Steering in `[-1, 1]`, scaled by `max_steer_angle`; positive steers right.
+    pub fn set_steering(&mut self, steering: f32) {
+        self.steering = steering.clamp(-1.0, 1.0);
+    }
+
+    pub(crate) fn step(&mut self, bodies: &mut Arena<RigidBody>, dt: f32) {
+        let Some(chassis) = bodies.get(self.chassis.0) else {
+            return;
+        };
+
+        let chassis_handle = self.chassis;
+        let position = chassis.position;
+        let rotation = Matrix3::from(chassis.rotation);
+        let velocity = chassis.velocity;
+        let angular_velocity = chassis.angular_velocity;
+
+        let up = rotation * Vector3::unit_y();
+        let forward = rotation * -Vector3::unit_z();
+
+        let mut contacts = Vec::with_capacity(self.wheels.len());
+        for wheel in &mut self.wheels {
+            let mount = position + rotation * wheel.attachment;
+            let hit = cast_ray(bodies, mount, -up, wheel.rest_length + wheel.radius, |handle, _| handle != chassis_handle);
+
+            let previous = wheel.compression;
+            wheel.compression = match hit {
+                Some(hit) => (wheel.rest_length + wheel.radius - hit.distance).max(0.0),
+                None => 0.0,
+            };
+            wheel.contact = hit.map(|hit| WheelContact { body: hit.handle, point: hit.point, normal: hit.normal });
+            wheel.load = match hit {
+                Some(_) => {
+                    let compression_speed = (wheel.compression - previous) / dt;
+                    (wheel.stiffness * wheel.compression + wheel.damping * compression_speed).max(0.0)
+                }
+                None => 0.0,
+            };
+            contacts.push(hit);
+        }
+
+        for bar in &self.anti_roll_bars {
+            if bar.left >= self.wheels.len() || bar.right >= self.wheels.len() {
+                continue;
+            }
+            let force = (self.wheels[bar.left].compression - self.wheels[bar.right].compression) * bar.stiffness;
+            for (index, force) in [(bar.left, force), (bar.right, -force)] {
+                let wheel = &mut self.wheels[index];
+                if wheel.contact.is_some() {
+                    wheel.load = (wheel.load + force).max(0.0);
+                }
+            }
+        }
+
+        let drive_torque = self.drive_torque();
+        let steer_angle = self.steering * self.max_steer_angle.0;
+        let mut forces = Vec::with_capacity(self.wheels.len());
+
+        for (wheel, hit) in self.wheels.iter_mut().zip(contacts) {
+            wheel.steer_angle = if wheel.steerable { steer_angle } else { 0.0 };
+
+            let mut torque = if wheel.driven { drive_torque } else { 0.0 };
+            let brake_torque = wheel.max_brake_torque * self.brake;
+
+            if let Some(hit) = hit {
+                let steer = Quaternion::from_axis_angle(up, Rad(-wheel.steer_angle));
+                let wheel_forward = steer * forward;
+                let wheel_forward = (wheel_forward - hit.normal * wheel_forward.dot(hit.normal)).normalize();
+                let wheel_right = wheel_forward.cross(hit.normal).normalize();
+
+                let contact_velocity = velocity + angular_velocity.cross(hit.point - position);
+                let longitudinal_speed = contact_velocity.dot(wheel_forward);
+                let lateral_speed = contact_velocity.dot(wheel_right);
+
+                let reference_speed = longitudinal_speed.abs().max(0.5);
+                let slip_ratio = (wheel.angular_velocity * wheel.radius - longitudinal_speed) / reference_speed;
+                let slip_angle = lateral_speed.atan2(reference_speed);
+
+                let mut longitudinal = wheel.load * self.tire.longitudinal_stiffness * slip_ratio;
+                let mut lateral = -wheel.load * self.tire.lateral_stiffness * slip_angle;
+
+                let max_force = wheel.load * self.tire.friction;
+                let combined = (longitudinal * longitudinal + lateral * lateral).sqrt();
+                if combined > max_force && combined > 0.0 {
+                    let scale = max_force / combined;
+                    longitudinal *= scale;
+                    lateral *= scale;
+                }
+
+                torque -= longitudinal * wheel.radius;
+
+                let force = hit.normal * wheel.load + wheel_forward * longitudinal + wheel_right * lateral;
+                forces.push((hit.handle, force, hit.point));
+            }
+
+            wheel.angular_velocity += torque / wheel.inertia() * dt;
+
+            let brake_delta = brake_torque / wheel.inertia() * dt;
+            if wheel.angular_velocity.abs() <= brake_delta {
+                wheel.angular_velocity = 0.0;
+            } else {
+                wheel.angular_velocity -= brake_delta * wheel.angular_velocity.signum();
+            }
+
+            wheel.spin_angle = (wheel.spin_angle + wheel.angular_velocity * dt) % (2.0 * PI);
+        }
+
+        self.update_engine_rpm();
+
+        for (ground, force, point) in forces {
+            if let Some(chassis) = bodies.get_mut(self.chassis.0) {
+                chassis.apply_force_at_point(force, point);
+            }
+            if let Some(ground) = bodies.get_mut(ground.0) {
+                if ground.movable {
+                    ground.apply_force_at_point(-force, point);
+                }
+            }
+        }
+    }
+
+    fn drive_torque(&self) -> f32 {
+        let driven = self.wheels.iter().filter(|wheel| wheel.driven).count();
+        if driven == 0 || self.throttle == 0.0 {
+            return 0.0;
+        }
+
+        let ratio = if self.throttle > 0.0 { self.engine.gear_ratio } else { -self.engine.reverse_ratio };
+        let engine_torque = if self.engine.rpm >= self.engine.max_rpm {
+            0.0
+        } else {
+            self.engine.torque_curve.sample(self.engine.rpm) * self.throttle.abs()
+        };
+
+        engine_torque * ratio * self.engine.final_drive / driven as f32
+    }
+
+    fn update_engine_rpm(&mut self) {
+        let driven: Vec<&Wheel> = self.wheels.iter().filter(|wheel| wheel.driven).collect();
+        if driven.is_empty() {
+            self.engine.rpm = self.engine.idle_rpm;
+            return;
+        }
+
+        let wheel_speed = driven.iter().map(|wheel| wheel.angular_velocity.abs()).sum::<f32>() / driven.len() as f32;
+        let ratio = if self.throttle < 0.0 { self.engine.reverse_ratio } else { self.engine.gear_ratio };
+        let rpm = wheel_speed * ratio * self.engine.final_drive * 60.0 / (2.0 * PI);
+
+        self.engine.rpm = rpm.clamp(self.engine.idle_rpm, self.engine.max_rpm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn torque_curve_interpolates_between_points() {
+        let curve = TorqueCurve::new(vec![(3000.0, 300.0), (1000.0, 100.0), (5000.0, 200.0)]);
+        assert_eq!(curve.sample(1000.0), 100.0);
+        assert_eq!(curve.sample(2000.0), 200.0);
+        assert_eq!(curve.sample(4500.0), 225.0);
+        assert_eq!(curve.sample(5000.0), 200.0);
+    }
+
+    #[test]
+    fn torque_curve_holds_its_ends() {
+        let curve = TorqueCurve::new(vec![(1000.0, 100.0), (5000.0, 200.0)]);
+        assert_eq!(curve.sample(0.0), 100.0);
+        assert_eq!(curve.sample(9000.0), 200.0);
+        assert_eq!(TorqueCurve::new(Vec::new()).sample(3000.0), 0.0);
+    }
+}
@@ -1,29 +1,164 @@
 use super::body::*;
 use cgmath::{
     Vector3,
-    InnerSpace
+    Quaternion,
+    Matrix3,
+    EuclideanSpace,
+    InnerSpace,
+    Zero,
 };
+use super::arena::*;
+use super::bounds::*;
 use super::collision::*;
+use super::collision_box::*;
+use super::force_field::*;
+use super::raycast::*;
+use super::vehicle::*;
+use crate::DebugLines;
 
 use rayon::prelude::*;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Stable reference to a body owned by a `PhysicsWorld`.
+///
+/// Handles stay valid until the body is removed; after that they resolve to `None`,
+/// even if the slot gets reused by a newer body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BodyHandle(pub(crate) Index);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ForceFieldHandle(pub(crate) Index);
+
+/// Contact found during the last collision step, kept for debug drawing.
+#[derive(Clone, Copy, Debug)]
+pub struct ContactPoint {
+    pub point: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub overlap: f32,
+}
+
+struct ActiveField {
+    field: ForceField,
+    age: f32,
+}
+
+/// Per-step snapshot of the data collision detection reads, laid out column-wise
+/// so the pair tests can run in parallel without touching the bodies themselves.
+struct BodyColumns<'a> {
+    handles: Vec<BodyHandle>,
+    rotations: Vec<Quaternion<f32>>,
+    shapes: Vec<&'a [CollisionBox]>,
+    movable: Vec<bool>,
+}
+
+impl<'a> BodyColumns<'a> {
+    fn gather(bodies: &'a Arena<RigidBody>) -> Self {
+        let mut columns = BodyColumns {
+            handles: Vec::with_capacity(bodies.len()),
+            rotations: Vec::with_capacity(bodies.len()),
+            shapes: Vec::with_capacity(bodies.len()),
+            movable: Vec::with_capacity(bodies.len()),
+        };
+
+        for (index, body) in bodies.iter() {
+            columns.handles.push(BodyHandle(index));
+            columns.rotations.push(body.rotation);
+            columns.shapes.push(&body.collision_box);
+            columns.movable.push(body.movable);
+        }
+
+        columns
+    }
+
+    fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    fn get_collision(&self, i: usize, j: usize) -> Option<Collision> {
+        if !self.movable[i] && !self.movable[j] {
+            return None;
+        }
+        for shape_a in self.shapes[i] {
+            for shape_b in self.shapes[j] {
+                if let Some(collision) = Collision::detect(shape_a, shape_b, &self.rotations[i], &self.rotations[j]) {
+                    return Some(collision);
+                }
+            }
+        }
+        None
+    }
+}
 
 pub struct PhysicsWorld {
-    pub(crate) bodies: Vec<BodyRef>,
+    pub(crate) bodies: Arena<RigidBody>,
+    force_fields: Arena<ActiveField>,
+    vehicles: Arena<Vehicle>,
+    contacts: Vec<ContactPoint>,
+    /// Wall-clock time the last `update` took.
+    update_time: Duration,
     pub(crate) gravity: Vector3<f32>,
     pub(crate) delta_time: f32,
-    pub(crate) bounds: f32,
+    pub(crate) bounds: WorldBounds,
+    pub(crate) bounds_policy: BoundsPolicy,
+    pub(crate) bounds_events: Vec<BoundsEvent>,
+    pub(crate) bounds_handler: Option<BoundsHandler>,
+    /// Bodies that were reported as escaped and have not re-entered the bounds since.
+    escaped_bodies: HashSet<BodyHandle>,
 }
 
+/// How far inside the boundary `BoundsPolicy::Clamp` puts a body, so gravity and
+/// float error don't push it straight back out on the next step.
+const CLAMP_INSET: f32 = 0.01;
+/// How far inside the boundary an escaped body has to get before it can be reported again.
+const REENTRY_MARGIN: f32 = 2.0 * CLAMP_INSET;
+
 impl PhysicsWorld {
     pub fn new(gravity: Vector3<f32>) -> Self {
         PhysicsWorld {
-            bodies: Vec::new(),
+            bodies: Arena::new(),
+            force_fields: Arena::new(),
+            vehicles: Arena::new(),
+            contacts: Vec::new(),
+            update_time: Duration::ZERO,
             gravity,
             delta_time: 1.0 / 1000.0,
-            bounds: 1000.0
+            bounds: WorldBounds::default(),
+            bounds_policy: BoundsPolicy::default(),
+            bounds_events: Vec::new(),
+            bounds_handler: None,
+            escaped_bodies: HashSet::new(),
         }
     }
 
+    /// Sets the region in which bodies are simulated. Defaults to a sphere of radius 1000 around the origin.
+    pub fn set_bounds(&mut self, bounds: WorldBounds) {
+        self.bounds = bounds;
+    }
+
+    pub fn bounds(&self) -> WorldBounds {
+        self.bounds
+    }
+
+    /// Sets what happens to movable bodies that leave the world bounds. Defaults to `BoundsPolicy::Remove`.
+    pub fn set_bounds_policy(&mut self, policy: BoundsPolicy) {
+        self.bounds_policy = policy;
+    }
+
+    /// Registers a callback invoked for every body that leaves the world bounds,
+    /// e.g. to despawn the renderable that follows it.
+    pub fn set_bounds_handler(&mut self, handler: impl FnMut(&BoundsEvent) + 'static + Send + Sync) -> &mut Self {
+        self.bounds_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Returns the bodies that left the world bounds during the last `update`.
+    ///
+    /// Events are cleared at the start of every `update`, so they don't pile up when nobody takes them.
+    pub fn take_bounds_events(&mut self) -> Vec<BoundsEvent> {
+        std::mem::take(&mut self.bounds_events)
+    }
+
     pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
         self.gravity = gravity;
     }
@@ -32,67 +167,243 @@ impl PhysicsWorld {
         self.delta_time = 1.0 / frequency;
     }
 
-    pub fn add_body(&mut self, body: BodyRef) {
-        self.bodies.push(body);
+    /// Moves `body` into the world and returns the handle used to access it afterwards.
+    pub fn add_body(&mut self, body: RigidBody) -> BodyHandle {
+        BodyHandle(self.bodies.insert(body))
+    }
+
+    /// Removes a body from the world, returning it if the handle was still valid.
+    pub fn remove_body(&mut self, handle: BodyHandle) -> Option<RigidBody> {
+        self.bodies.remove(handle.0)
+    }
+
+    pub fn body(&self, handle: BodyHandle) -> Option<&RigidBody> {
+        self.bodies.get(handle.0)
+    }
+
+    pub fn body_mut(&mut self, handle: BodyHandle) -> Option<&mut RigidBody> {
+        self.bodies.get_mut(handle.0)
+    }
+
+    pub fn contains_body(&self, handle: BodyHandle) -> bool {
+        self.bodies.contains(handle.0)
+    }
+
+    pub fn body_count(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = (BodyHandle, &RigidBody)> {
+        self.bodies.iter().map(|(index, body)| (BodyHandle(index), body))
+    }
+
+    pub fn bodies_mut(&mut self) -> impl Iterator<Item = (BodyHandle, &mut RigidBody)> {
+        self.bodies.iter_mut().map(|(index, body)| (BodyHandle(index), body))
+    }
+
+    /// Registers a force field that acts on every body each substep.
+    ///
+    /// Explosions remove themselves after their duration; other fields stay until removed.
+    pub fn add_force_field(&mut self, field: impl Into<ForceField>) -> ForceFieldHandle {
+        ForceFieldHandle(self.force_fields.insert(ActiveField { field: field.into(), age: 0.0 }))
+    }
+
+    pub fn remove_force_field(&mut self, handle: ForceFieldHandle) -> Option<ForceField> {
+        self.force_fields.remove(handle.0).map(|active| active.field)
+    }
+
+    pub fn force_field(&self, handle: ForceFieldHandle) -> Option<&ForceField> {
+        self.force_fields.get(handle.0).map(|active| &active.field)
+    }
+
+    pub fn force_field_mut(&mut self, handle: ForceFieldHandle) -> Option<&mut ForceField> {
+        self.force_fields.get_mut(handle.0).map(|active| &mut active.field)
+    }
+
+    /// Registers a vehicle; its wheels are simulated every substep until it is removed.
+    pub fn add_vehicle(&mut self, vehicle: Vehicle) -> VehicleHandle {
+        VehicleHandle(self.vehicles.insert(vehicle))
+    }
+
+    /// Removes a vehicle. The chassis body stays in the world.
+    pub fn remove_vehicle(&mut self, handle: VehicleHandle) -> Option<Vehicle> {
+        self.vehicles.remove(handle.0)
+    }
+
+    pub fn vehicle(&self, handle: VehicleHandle) -> Option<&Vehicle> {
+        self.vehicles.get(handle.0)
+    }
+
+    pub fn vehicle_mut(&mut self, handle: VehicleHandle) -> Option<&mut Vehicle> {
+        self.vehicles.get_mut(handle.0)
+    }
+
+    /// Returns the closest body hit by a ray, if any within `max_distance`.
+    pub fn raycast(&self, origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<RaycastHit> {
+        cast_ray(&self.bodies, origin, direction, max_distance, |_, _| true)
+    }
+
+    /// Like `raycast`, but only bodies for which `filter` returns `true` are considered.
+    pub fn raycast_filtered(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+        filter: impl Fn(BodyHandle, &RigidBody) -> bool,
+    ) -> Option<RaycastHit> {
+        cast_ray(&self.bodies, origin, direction, max_distance, filter)
     }
 
     pub fn update(&mut self, mut dt: f32) {
+        let start = Instant::now();
+        self.bounds_events.clear();
         while dt > 0.0 {
-            for body_ref in &mut self.bodies {
+            let gravity = self.gravity;
+            let step = self.delta_time;
+            let fields: Vec<ForceField> = self.force_fields.iter().map(|(_, active)| active.field).collect();
 
-                let mut body = body_ref.lock().unwrap();
+            for (_, vehicle) in self.vehicles.iter_mut() {
+                vehicle.step(&mut self.bodies, step);
+            }
 
+            self.bodies.par_values_mut().for_each(|body| {
                 if body.gravity && body.movable {
                     let mass = body.mass;
-                    body.apply_force(self.gravity * mass);
+                    body.apply_force(gravity * mass);
                 }
 
-                body.update(self.delta_time);
-            }
+                for field in &fields {
+                    field.apply(body, gravity);
+                }
+
+                body.update(step);
+            });
+            self.age_force_fields(step);
             self.handle_collisions();
             dt -= self.delta_time;
         }
-        self.filter_bodies();
+        self.enforce_bounds();
+        self.update_time = start.elapsed();
     }
 
-    fn handle_collisions(&mut self) {
-        let body_count = self.bodies.len();
-
-        let collisions: Vec<(usize, usize, Collision)> = (0..body_count)
-            .into_par_iter()
-            .flat_map(|i| {
-                (i + 1..body_count)
-                    .into_par_iter()
-                    .filter_map(|j| self.get_collision(i, j).map(|collision| (i, j, collision)))
-                    .collect::<Vec<_>>()
-            })
-        .collect();
-
-        collisions.into_par_iter().for_each(|(i, j, collision)| {
-            self.resolve_collision(i, j, collision);
-        });
+    /// How long the last `update` took to simulate all of its substeps.
+    pub fn update_time(&self) -> Duration {
+        self.update_time
     }
 
-    fn get_collision(&self, i: usize, j: usize) -> Option<Collision> {
-        let body_a = self.bodies[i].lock().unwrap();
-        let body_b = self.bodies[j].lock().unwrap();
+    /// Contacts resolved in the last substep.
+    pub fn contacts(&self) -> &[ContactPoint] {
+        &self.contacts
+    }
 
-        if !body_a.movable && !body_b.movable {
-            return None;
+    /// Emits wireframes of every collision shape plus contact points, contact normals,
+    /// velocity vectors, vehicle suspension rays and the wheels touching the ground.
+    ///
+    /// There are no broadphase nodes to draw: every body is tested against every other one.
+    pub fn debug_draw(&self, lines: &mut DebugLines) {
+        const DYNAMIC: [f32; 3] = [0.2, 1.0, 0.2];
+        const STATIC: [f32; 3] = [0.6, 0.6, 0.6];
+        const CONTACT: [f32; 3] = [1.0, 0.2, 0.2];
+        const NORMAL: [f32; 3] = [1.0, 1.0, 0.2];
+        const VELOCITY: [f32; 3] = [0.2, 0.6, 1.0];
+        const WHEEL: [f32; 3] = [1.0, 0.5, 0.0];
+        const SUSPENSION: [f32; 3] = [1.0, 0.2, 1.0];
+
+        for (_, body) in self.bodies() {
+            let color = if body.movable { DYNAMIC } else { STATIC };
+            for shape in &body.collision_box {
+                match shape {
+                    CollisionBox::BoundingBox(bbox) => {
+                        lines.oriented_box(bbox.center().to_vec(), bbox.size() / 2.0, body.rotation, color);
+                    }
+                    CollisionBox::Sphere(sphere) => {
+                        lines.sphere(sphere.center.to_vec(), sphere.radius, color);
+                    }
+                }
+            }
+
+            if body.movable {
+                lines.arrow(body.position, body.velocity * 0.1, VELOCITY);
+            }
         }
-        for shape_a in body_a.collision_box.iter() {
-            for shape_b in body_b.collision_box.iter() {
-                if let Some(collision) = Collision::detect(&shape_a, &shape_b, &body_a.rotation, &body_b.rotation) {
-                    return Some(collision);
+
+        for contact in &self.contacts {
+            lines.point(contact.point, 0.2, CONTACT);
+            lines.arrow(contact.point, contact.normal * 0.5, NORMAL);
+        }
+
+        for (_, vehicle) in self.vehicles.iter() {
+            let Some(chassis) = self.body(vehicle.chassis) else {
+                continue;
+            };
+            let rotation = Matrix3::from(chassis.rotation);
+            let down = rotation * -Vector3::unit_y();
+            for wheel in &vehicle.wheels {
+                // Suspension ray from the mount to the ground, or its full length when airborne
+                let mount = chassis.position + rotation * wheel.attachment;
+                match wheel.contact() {
+                    Some(contact) => {
+                        lines.line(mount, contact.point, SUSPENSION);
+                        lines.sphere(contact.point + contact.normal * wheel.radius, wheel.radius, WHEEL);
+                    }
+                    None => lines.line(mount, mount + down * (wheel.rest_length + wheel.radius), SUSPENSION),
                 }
             }
         }
-        None
     }
 
-    fn resolve_collision(&self, i: usize, j: usize, collision: Collision) {
-        let mut body_a = self.bodies[i].lock().unwrap();
-        let mut body_b = self.bodies[j].lock().unwrap();
+    fn age_force_fields(&mut self, step: f32) {
+        let mut expired = Vec::new();
+        for (index, active) in self.force_fields.iter_mut() {
+            active.age += step;
+            if active.field.lifetime().is_some_and(|lifetime| active.age >= lifetime) {
+                expired.push(index);
+            }
+        }
+        for index in expired {
+            self.force_fields.remove(index);
+        }
+    }
+
+    fn handle_collisions(&mut self) {
+        let collisions: Vec<(BodyHandle, BodyHandle, Collision)> = {
+            let columns = BodyColumns::gather(&self.bodies);
+            let body_count = columns.len();
+            let columns = &columns;
+
+            (0..body_count)
+                .into_par_iter()
+                .flat_map(|i| {
+                    (i + 1..body_count)
+                        .into_par_iter()
+                        .filter_map(|j| {
+                            columns
+                                .get_collision(i, j)
+                                .map(|collision| (columns.handles[i], columns.handles[j], collision))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            .collect()
+        };
+
+        self.contacts.clear();
+        for (a, b, collision) in collisions {
+            self.contacts.push(ContactPoint {
+                point: collision.contact_point,
+                normal: collision.normal,
+                overlap: collision.overlap,
+            });
+            self.resolve_collision(a, b, collision);
+        }
+    }
+
+    fn resolve_collision(&mut self, a: BodyHandle, b: BodyHandle, collision: Collision) {
+        let gravity = self.gravity;
+        let delta_time = self.delta_time;
+
+        let (Some(body_a), Some(body_b)) = self.bodies.get2_mut(a.0, b.0) else {
+            return;
+        };
 
         let r_a = collision.contact_point - body_a.position;
         let r_b = collision.contact_point - body_b.position;
@@ -115,11 +426,11 @@ impl PhysicsWorld {
             body_a.apply_torque(torque_a);
 
             if body_a.position.y > collision.contact_point.y {
-                let gravity_torque = -r_a.cross(self.gravity * mass);
+                let gravity_torque = -r_a.cross(gravity * mass);
                 body_a.apply_torque(gravity_torque);
             }
 
-            body_a.apply_surface_friction(normal, collision.contact_point, friction_coefficient, self.delta_time);
+            body_a.apply_surface_friction(normal, collision.contact_point, friction_coefficient, delta_time);
 
             body_a.correction = -correction / mass;
         }
@@ -132,24 +443,140 @@ impl PhysicsWorld {
             body_b.apply_torque(torque_b);
 
             if body_b.position.y > collision.contact_point.y {
-                let gravity_torque = -r_b.cross(self.gravity * mass);
+                let gravity_torque = -r_b.cross(gravity * mass);
                 body_b.apply_torque(gravity_torque);
             }
 
-            body_b.apply_surface_friction(normal, collision.contact_point, friction_coefficient, self.delta_time);
+            body_b.apply_surface_friction(normal, collision.contact_point, friction_coefficient, delta_time);
 
             body_b.correction = correction / mass;
         }
     }
 
-    fn filter_bodies(&mut self) {
-        let mut new_bodies = Vec::new();
-        for body_ref in self.bodies.drain(..) {
-            let body = body_ref.lock().unwrap();
-            if body.position.magnitude() <= self.bounds {
-                new_bodies.push(body_ref.clone());
+    fn enforce_bounds(&mut self) {
+        let bounds = self.bounds;
+        let policy = self.bounds_policy;
+
+        let reentry = bounds.shrink(REENTRY_MARGIN);
+        let bodies = &self.bodies;
+        self.escaped_bodies.retain(|handle| {
+            bodies.get(handle.0).is_some_and(|body| body.movable && !reentry.contains(body.position))
+        });
+
+        let outside: Vec<BoundsEvent> = self
+            .bodies()
+            .filter(|(_, body)| body.movable && !bounds.contains(body.position))
+            .map(|(handle, body)| BoundsEvent { handle, position: body.position, policy })
+            .collect();
+
+        for event in &outside {
+            match policy {
+                BoundsPolicy::Remove => {
+                    self.remove_body(event.handle);
+                }
+                BoundsPolicy::Clamp => {
+                    if let Some(body) = self.body_mut(event.handle) {
+                        let normal = bounds.outward_normal(body.position);
+                        let outward_speed = body.velocity.dot(normal).max(0.0);
+                        body.velocity -= normal * outward_speed;
+                        body.set_position(bounds.shrink(CLAMP_INSET).clamp(body.position));
+                    }
+                }
+                BoundsPolicy::Teleport(target) => {
+                    if let Some(body) = self.body_mut(event.handle) {
+                        body.set_position(target);
+                        body.velocity = Vector3::zero();
+                        body.angular_velocity = Vector3::zero();
+                    }
+                }
+                BoundsPolicy::Freeze => {
+                    if let Some(body) = self.body_mut(event.handle) {
+                        body.velocity = Vector3::zero();
+                        body.angular_velocity = Vector3::zero();
+                        body.movable = false;
+                    }
+                }
+            }
+
+            // Removed, teleported and frozen bodies drop out of the set on the next update.
+            if !self.escaped_bodies.insert(event.handle) {
+                continue;
             }
+            if let Some(handler) = self.bounds_handler.as_mut() {
+                handler(event);
+            }
+            self.bounds_events.push(*event);
         }
-        self.bodies = new_bodies;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point3;
+
+    fn world_with_body(policy: BoundsPolicy, position: Vector3<f32>) -> (PhysicsWorld, BodyHandle) {
+        let mut world = PhysicsWorld::new(Vector3::zero());
+        world.set_bounds(WorldBounds::aabb(Vector3::new(-10.0, 0.0, -10.0), Vector3::new(10.0, 10.0, 10.0)));
+        world.set_bounds_policy(policy);
+        let mut body = RigidBody::new(vec![CollisionBox::Sphere(Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.5))]);
+        body.set_position(position);
+        body.velocity = Vector3::new(0.0, -3.0, 1.0);
+        let handle = world.add_body(body);
+        (world, handle)
+    }
+
+    #[test]
+    fn remove_policy_drops_the_body() {
+        let (mut world, handle) = world_with_body(BoundsPolicy::Remove, Vector3::new(0.0, -1.0, 0.0));
+        world.enforce_bounds();
+        assert!(!world.contains_body(handle));
+        assert_eq!(world.take_bounds_events().len(), 1);
+    }
+
+    #[test]
+    fn clamp_policy_moves_the_body_inside_and_reports_once() {
+        let (mut world, handle) = world_with_body(BoundsPolicy::Clamp, Vector3::new(0.0, -1.0, 0.0));
+        world.enforce_bounds();
+        let body = world.body(handle).unwrap();
+        assert!(world.bounds().contains(body.position));
+        assert_eq!(body.velocity, Vector3::new(0.0, 0.0, 1.0));
+
+        // Pushed back out while resting on the boundary: clamped again but not reported again
+        world.body_mut(handle).unwrap().set_position(Vector3::new(0.0, -0.001, 0.0));
+        world.enforce_bounds();
+        assert_eq!(world.take_bounds_events().len(), 1);
+
+        // Once well inside it counts as a new escape
+        world.body_mut(handle).unwrap().set_position(Vector3::new(0.0, 5.0, 0.0));
+        world.enforce_bounds();
+        world.body_mut(handle).unwrap().set_position(Vector3::new(0.0, -1.0, 0.0));
+        world.enforce_bounds();
+        assert_eq!(world.take_bounds_events().len(), 1);
+    }
+
+    #[test]
+    fn teleport_and_freeze_policies_stop_the_body() {
+        let target = Vector3::new(1.0, 2.0, 3.0);
+        let (mut world, handle) = world_with_body(BoundsPolicy::Teleport(target), Vector3::new(20.0, 5.0, 0.0));
+        world.enforce_bounds();
+        let body = world.body(handle).unwrap();
+        assert_eq!(body.position, target);
+        assert_eq!(body.velocity, Vector3::zero());
+
+        let (mut world, handle) = world_with_body(BoundsPolicy::Freeze, Vector3::new(20.0, 5.0, 0.0));
+        world.enforce_bounds();
+        let body = world.body(handle).unwrap();
+        assert!(!body.movable);
+        assert_eq!(body.velocity, Vector3::zero());
+        assert_eq!(body.position, Vector3::new(20.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn bodies_inside_the_bounds_are_left_alone() {
+        let (mut world, handle) = world_with_body(BoundsPolicy::Remove, Vector3::new(0.0, 5.0, 0.0));
+        world.enforce_bounds();
+        assert!(world.contains_body(handle));
+        assert!(world.take_bounds_events().is_empty());
     }
 }
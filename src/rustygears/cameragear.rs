@@ -29,7 +29,11 @@ impl Gear for Camera {
             }
         }
 
+        // A released cursor is for clicking around, not for looking
         if let GearEvent::MouseMotion(x, y) = event {
+            if !game.window.is_cursor_captured() {
+                return;
+            }
             let dt = game.time.delta_time();
             self.rotate(*x as f32, *y as f32, dt);
         }
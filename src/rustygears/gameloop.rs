@@ -1,11 +1,11 @@
 use crate::{
-    window::State, DrawLight, DrawModel, GearEvent
+    window::{SceneShaders, State}, DrawLight, GearEvent
 };
 
 use tokio::runtime::Runtime;
 use winit::{
     event::{
-        DeviceEvent, Event, KeyEvent, WindowEvent
+        DeviceEvent, ElementState, Event, KeyEvent, WindowEvent
     },
     event_loop::EventLoop, keyboard::PhysicalKey
 };
@@ -15,7 +15,7 @@ use std::{iter, sync::{Arc, Mutex}};
 use super::Game;
 
 
-fn render(state: &State) -> Result<(), wgpu::SurfaceError> {
+fn render(state: &mut State) -> Result<(), wgpu::SurfaceError> {
     let render_data = &state.render_data;
     let output = state.surface.get_current_texture()?;
     let view = output
@@ -26,20 +26,22 @@ fn render(state: &State) -> Result<(), wgpu::SurfaceError> {
         label: Some("Render Encoder"),
     });
 
+    render_data.lights.shadows.render(&mut encoder, &render_data.scene);
+
     {
+        // With MSAA the scene is drawn into the multisampled target and resolved into the HDR one
+        let (color_view, resolve_target, store) = match &render_data.msaa_texture {
+            Some(msaa) => (msaa, Some(render_data.post.hdr_view()), wgpu::StoreOp::Discard),
+            None => (render_data.post.hdr_view(), None, wgpu::StoreOp::Store),
+        };
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
+                view: color_view,
+                resolve_target,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
-                    store: wgpu::StoreOp::Store,
+                    load: wgpu::LoadOp::Clear(render_data.environment.clear_color()),
+                    store,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
@@ -54,22 +56,41 @@ fn render(state: &State) -> Result<(), wgpu::SurfaceError> {
             timestamp_writes: None,
         });
 
-        render_pass.set_vertex_buffer(1, render_data.instance_buffer.slice(..));
         render_pass.set_pipeline(&render_data.light_render_pipeline);
-        render_pass.draw_light_model(
+        render_pass.draw_light_model_instanced(
             &render_data.obj_light,
+            0..render_data.lights.count(),
+            &render_data.camera_bind_group,
+            &render_data.lights.bind_group,
+        );
+
+        render_data.scene.draw(
+            &mut render_pass,
+            &render_data.render_pipelines,
+            &render_data.materials,
             &render_data.camera_bind_group,
-            &render_data.light_bind_group,
+            &render_data.lights.bind_group,
+            &render_data.environment.bind_group,
         );
 
-        render_pass.set_pipeline(&render_data.render_pipeline);
-        render_pass.draw_model_instanced(
-            &render_data.obj_model,
-            0..render_data.instances.len() as u32,
+        render_data.environment.draw_sky(&mut render_pass, &render_data.camera_bind_group);
+
+        render_data.scene.draw_transparent(
+            &mut render_pass,
+            &render_data.render_pipelines,
+            &render_data.materials,
             &render_data.camera_bind_group,
-            &render_data.light_bind_group,
+            &render_data.lights.bind_group,
+            &render_data.environment.bind_group,
         );
+
+        render_data.debug_lines.draw(&mut render_pass, &render_data.camera_bind_group);
     }
+
+    render_data.post.render(&mut encoder, &view);
+
+    let size = [state.config.width, state.config.height];
+    state.render_data.ui.render(&state.device, &state.queue, &mut encoder, &view, size);
     state.queue.submit(iter::once(encoder.finish()));
     output.present();
 
@@ -92,15 +113,13 @@ impl GameLoop {
 
     async fn run_loop(&mut self, game: &mut Game) {
         let event_loop = EventLoop::new().unwrap();
-        let title = env!("CARGO_PKG_NAME");
-        let window = winit::window::WindowBuilder::new()
-            .with_title(title)
-            .build(&event_loop)
-            .unwrap();
-        window.set_cursor_grab(winit::window::CursorGrabMode::Locked).expect("nema cursor");
-        window.set_cursor_visible(false);
-        
-        let state = State::new(&window, game.cameras.active_camera().expect("no camera found")).await;
+        let window = game.window.build(&event_loop, &game.assets);
+        let mut window_config = game.window.clone();
+
+        let camera = game.cameras.active_camera().expect("no camera found");
+        let state = State::new(&window, camera, &game.render_settings, &game.assets, SceneShaders::default())
+            .await
+            .expect("failed to create the renderer");
         let state = Arc::new(Mutex::new(state));
 
         let game = Arc::new(Mutex::new(std::mem::take(game)));
@@ -108,15 +127,114 @@ impl GameLoop {
         event_loop.run(move |event, control_flow| {
             match event {
                 Event::NewEvents(_) => {
-                    game.lock().unwrap().time.update();
+                    {
+                        let mut game = game.lock().unwrap();
+                        let mut state = state.lock().unwrap();
+                        let mut wanted = game.window.clone();
+                        if game.ui.enabled {
+                            // The overlay needs the pointer; the gears' grab comes back once it is closed
+                            wanted.release_cursor();
+                        }
+                        if wanted != window_config {
+                            wanted.apply(state.window, &window_config, &game.assets);
+                            window_config = wanted;
+                        }
+                        if !state.apply_render_settings(&game.render_settings) {
+                            // A new backend or adapter needs a whole new device and surface
+                            let window = state.window;
+                            let camera = game.cameras.active_camera().expect("no camera found");
+                            // Keeps the scene shaders that were hot reloaded
+                            let shaders = state.render_data.shaders.clone();
+                            let rebuilt = tokio::task::block_in_place(|| {
+                                tokio::runtime::Handle::current().block_on(State::new(window, camera, &game.render_settings, &game.assets, shaders))
+                            });
+                            match rebuilt {
+                                Ok(rebuilt) => {
+                                    // The shaders carried over are the last ones that compiled
+                                    let shader_error = state.shader_error.take();
+                                    *state = rebuilt;
+                                    state.shader_error = shader_error;
+                                    // The surface is only configured once the old one is gone
+                                    let size = window.inner_size();
+                                    state.resize(size);
+                                    // The new UI renderer starts without the font atlas
+                                    game.ui.reset_textures();
+                                }
+                                Err(e) => {
+                                    // Keep rendering with the current device and apply the other changes
+                                    log::error!("could not switch the renderer: {e:#}");
+                                    game.render_settings.backend = state.settings.backend;
+                                    game.render_settings.adapter = state.settings.adapter;
+                                    state.apply_render_settings(&game.render_settings);
+                                }
+                            }
+                        }
+                        state.hot_reload(&game.scene);
+                        game.ui.shader_error.clone_from(&state.shader_error);
+                    }
+                    {
+                        let mut game = game.lock().unwrap();
+                        game.time.update();
+                        let dt = game.time.delta_time();
+                        game.physics.update(dt);
+                        game.scene.update_animations(dt);
+
+                        let game = &mut *game;
+                        game.debug.lines.clear();
+                        if game.debug.physics {
+                            game.physics.debug_draw(&mut game.debug.lines);
+                        }
+                    }
                     Game::dispatch_event(game.clone(), GearEvent::Update());
+                    {
+                        let mut game = game.lock().unwrap();
+                        let game = &mut *game;
+                        game.hierarchy.update(&mut game.physics, &mut game.scene, &mut game.lights, &game.cameras);
+                    }
                     state.lock().unwrap().update(game.lock().unwrap().cameras.active_camera().expect("no camera found"));
+                    {
+                        let game = game.lock().unwrap();
+                        let camera = game.cameras.active_camera().expect("no camera found");
+                        state.lock().unwrap().update_lights(&game.lights, &camera.lock().unwrap());
+                    }
+                    state.lock().unwrap().update_materials(&game.lock().unwrap().materials);
+                    {
+                        let mut game = game.lock().unwrap();
+                        let camera = game.cameras.active_camera().expect("no camera found");
+                        state.lock().unwrap().update_scene(&mut game.scene, &camera.lock().unwrap());
+                    }
+                    state.lock().unwrap().update_environment(&game.lock().unwrap().environment);
+                    {
+                        let game = game.lock().unwrap();
+                        state.lock().unwrap().update_post_processing(&game.post_processing, game.time.total_time());
+                    }
+                    state.lock().unwrap().update_debug_lines(&game.lock().unwrap().debug.lines);
+                    {
+                        let (size, scale_factor) = {
+                            let state = state.lock().unwrap();
+                            (state.window.inner_size(), state.window.scale_factor())
+                        };
+                        let context = {
+                            let mut game = game.lock().unwrap();
+                            game.ui.wants_frame().then(|| (game.ui.begin_frame(size, scale_factor), game.ui.enabled))
+                        };
+                        if let Some((context, enabled)) = context {
+                            if enabled {
+                                Game::dispatch_event(game.clone(), GearEvent::Ui(context));
+                            }
+                            let mut game = game.lock().unwrap();
+                            let game = &mut *game;
+                            game.ui.end_frame(&game.time, &game.physics, &game.scene);
+                        }
+                    }
+                    state.lock().unwrap().update_ui(&mut game.lock().unwrap().ui);
                     state.lock().unwrap().window().request_redraw();
                 }
 
                 Event::DeviceEvent { event, .. } => {
                     match event {
-                        DeviceEvent::MouseMotion { delta } => {
+                        // Mouse look would turn the camera while the overlay is used
+                        DeviceEvent::MouseMotion { delta } if !game.lock().unwrap().ui.enabled => {
                             Game::dispatch_event(game.clone(), GearEvent::MouseMotion(delta.0, delta.1));
                         }
                         _ => {}
@@ -124,6 +242,8 @@ impl GameLoop {
                 }
 
                 Event::WindowEvent { ref event, window_id, } if window_id == state.lock().unwrap().window().id() => {
+                    // Input the debug UI takes is not passed on to the gears
+                    let ui_input = game.lock().unwrap().ui.on_window_event(event);
                     match event {
                         WindowEvent::CloseRequested => control_flow.exit(),
 
@@ -133,11 +253,23 @@ impl GameLoop {
 
                         WindowEvent::RedrawRequested => {
                             Game::dispatch_event(game.clone(), GearEvent::RenderRequested());
-                            let _ = render(&state.lock().unwrap());
+                            let _ = render(&mut state.lock().unwrap());
                         }
 
-                        WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(key), state, .. }, .. } => {
-                            Game::dispatch_event(game.clone(), GearEvent::KeyboardInput(*key, *state));
+                        WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(key), state, repeat, .. }, .. } => {
+                            {
+                                let mut game = game.lock().unwrap();
+                                if !*repeat && *state == ElementState::Pressed && game.debug.toggle_key == Some(*key) {
+                                    game.debug.physics = !game.debug.physics;
+                                }
+                                if !*repeat && *state == ElementState::Pressed && game.ui.toggle_key == Some(*key) {
+                                    game.ui.enabled = !game.ui.enabled;
+                                }
+                            }
+                            // Releases still go through so no key stays held down
+                            if !ui_input || *state == ElementState::Released {
+                                Game::dispatch_event(game.clone(), GearEvent::KeyboardInput(*key, *state));
+                            }
                         }
                         _ => {}
                     }
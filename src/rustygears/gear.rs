@@ -62,4 +62,14 @@ pub enum GearEvent {
 
     MouseMotion(f64, f64),
 
+///```ignore
+/// if let GearEvent::Ui(ctx) = event {
+///     egui::Window::new("Tweaks").show(ctx, |ui| {
+///         ui.add(egui::Slider::new(&mut self.speed, 0.0..=10.0).text("speed"));
+///     });
+/// }
+///```
+
+    Ui(egui::Context),
+
 }
@@ -0,0 +1,347 @@
+use cgmath::{ElementWise, EuclideanSpace, Point3, Vector3};
+
+use crate::physics::arena::{Arena, Index};
+use crate::{BodyHandle, CameraManager, InstanceHandle, LightHandle, LightManager, NodeTransform, PhysicsWorld, SceneManager};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityHandle(Index);
+
+/// Something placed in the world by an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attachment {
+    /// A camera by its ID. The camera keeps its own yaw and pitch, measured from the
+    /// entity's orientation.
+    Camera(u64),
+    /// A light, shining along the entity's -Z axis.
+    Light(LightHandle),
+    Instance(InstanceHandle),
+    /// A rigid body. A movable body is simulated and moves the entity instead, which
+    /// makes it possible to hang other things off it, e.g. a camera behind a car.
+    Body(BodyHandle),
+}
+
+/// A node of the transform hierarchy.
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub name: String,
+    /// Transform relative to the parent, or to the world for root entities.
+    pub local: NodeTransform,
+    pub attachments: Vec<Attachment>,
+    parent: Option<EntityHandle>,
+    children: Vec<EntityHandle>,
+    world: NodeTransform,
+}
+
+impl Entity {
+    pub fn parent(&self) -> Option<EntityHandle> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[EntityHandle] {
+        &self.children
+    }
+
+    /// Transform relative to the world as of the last update.
+    pub fn world(&self) -> NodeTransform {
+        self.world
+    }
+}
+
+/// Entities with local transforms and parents, and the cameras, lights, model instances
+/// and bodies attached to them.
+///
+/// World transforms are propagated once per frame, after the gears have handled
+/// `GearEvent::Update`, and then copied to everything attached.
+pub struct Hierarchy {
+    entities: Arena<Entity>,
+}
+
+impl Hierarchy {
+    pub(crate) fn new() -> Self {
+        Self {
+            entities: Arena::new(),
+        }
+    }
+
+    /// Adds a root entity.
+    pub fn spawn(&mut self, name: &str, local: NodeTransform) -> EntityHandle {
+        EntityHandle(self.entities.insert(Entity {
+            name: name.to_string(),
+            local,
+            attachments: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            world: local,
+        }))
+    }
+
+    /// Adds an entity placed at `local` relative to `parent`; a root entity if the parent
+    /// is no longer valid.
+    pub fn spawn_child(&mut self, parent: EntityHandle, name: &str, local: NodeTransform) -> EntityHandle {
+        let handle = self.spawn(name, local);
+        self.set_parent(handle, Some(parent));
+        handle
+    }
+
+    /// Removes an entity and all of its descendants. Attached objects stay where they
+    /// were last placed.
+    pub fn despawn(&mut self, handle: EntityHandle) {
+        self.set_parent(handle, None);
+        let mut stack = vec![handle];
+        while let Some(handle) = stack.pop() {
+            if let Some(entity) = self.entities.remove(handle.0) {
+                stack.extend(entity.children);
+            }
+        }
+    }
+
+    /// Moves an entity under `parent`, or to the root with `None`, keeping its local
+    /// transform. Does nothing if a handle is not valid or the parent is the entity
+    /// itself or one of its descendants.
+    pub fn set_parent(&mut self, handle: EntityHandle, parent: Option<EntityHandle>) {
+        if !self.entities.contains(handle.0) {
+            return;
+        }
+        if let Some(parent) = parent {
+            if !self.entities.contains(parent.0) || self.ancestors(parent).any(|ancestor| ancestor == handle) {
+                return;
+            }
+        }
+
+        if let Some(old) = self.entities.get(handle.0).and_then(|entity| entity.parent) {
+            if let Some(old) = self.entities.get_mut(old.0) {
+                old.children.retain(|child| *child != handle);
+            }
+        }
+        if let Some(parent) = parent {
+            if let Some(parent) = self.entities.get_mut(parent.0) {
+                parent.children.push(handle);
+            }
+        }
+        if let Some(entity) = self.entities.get_mut(handle.0) {
+            entity.parent = parent;
+        }
+    }
+
+    /// The entity itself, its parent, and so on up to the root.
+    fn ancestors(&self, handle: EntityHandle) -> impl Iterator<Item = EntityHandle> + '_ {
+        std::iter::successors(Some(handle), |handle| self.entities.get(handle.0).and_then(|entity| entity.parent))
+    }
+
+    pub fn entity(&self, handle: EntityHandle) -> Option<&Entity> {
+        self.entities.get(handle.0)
+    }
+
+    pub fn entity_mut(&mut self, handle: EntityHandle) -> Option<&mut Entity> {
+        self.entities.get_mut(handle.0)
+    }
+
+    pub fn find(&self, name: &str) -> Option<EntityHandle> {
+        self.entities.iter().find(|(_, entity)| entity.name == name).map(|(index, _)| EntityHandle(index))
+    }
+
+    /// Changes the local transform of an entity. Does nothing if the handle is no longer valid.
+    pub fn set_local(&mut self, handle: EntityHandle, local: NodeTransform) {
+        if let Some(entity) = self.entities.get_mut(handle.0) {
+            entity.local = local;
+        }
+    }
+
+    /// Transform relative to the world as of the last update.
+    pub fn world(&self, handle: EntityHandle) -> Option<NodeTransform> {
+        self.entities.get(handle.0).map(|entity| entity.world)
+    }
+
+    /// Attaches an object to an entity. Does nothing if the handle is no longer valid.
+    pub fn attach(&mut self, handle: EntityHandle, attachment: Attachment) {
+        if let Some(entity) = self.entities.get_mut(handle.0) {
+            entity.attachments.push(attachment);
+        }
+    }
+
+    pub fn detach(&mut self, handle: EntityHandle, attachment: Attachment) {
+        if let Some(entity) = self.entities.get_mut(handle.0) {
+            entity.attachments.retain(|a| *a != attachment);
+        }
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = (EntityHandle, &Entity)> {
+        self.entities.iter().map(|(index, entity)| (EntityHandle(index), entity))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.len() == 0
+    }
+
+    /// Propagates world transforms from the roots down, then places everything attached.
+    pub(crate) fn update(
+        &mut self,
+        physics: &mut PhysicsWorld,
+        scene: &mut SceneManager,
+        lights: &mut LightManager,
+        cameras: &CameraManager,
+    ) {
+        let mut stack: Vec<(EntityHandle, NodeTransform)> = self
+            .entities
+            .iter()
+            .filter(|(_, entity)| entity.parent.is_none())
+            .map(|(index, _)| (EntityHandle(index), NodeTransform::default()))
+            .collect();
+
+        while let Some((handle, parent_world)) = stack.pop() {
+            let Some(entity) = self.entities.get_mut(handle.0) else {
+                continue;
+            };
+
+            // Simulated bodies move their entity
+            let simulated = entity.attachments.iter().find_map(|attachment| match attachment {
+                Attachment::Body(body) => physics.body(*body).filter(|body| body.movable),
+                _ => None,
+            });
+            if let Some(body) = simulated {
+                let world = NodeTransform {
+                    translation: body.position,
+                    rotation: body.rotation,
+                    scale: parent_world.scale.mul_element_wise(entity.local.scale),
+                };
+                entity.local = world.relative_to(&parent_world);
+            }
+
+            entity.world = parent_world.child(&entity.local);
+            let world = entity.world;
+            stack.extend(entity.children.iter().map(|child| (*child, world)));
+
+            for attachment in &entity.attachments {
+                Self::place(*attachment, &world, physics, scene, lights, cameras);
+            }
+        }
+    }
+
+    fn place(
+        attachment: Attachment,
+        world: &NodeTransform,
+        physics: &mut PhysicsWorld,
+        scene: &mut SceneManager,
+        lights: &mut LightManager,
+        cameras: &CameraManager,
+    ) {
+        match attachment {
+            Attachment::Camera(id) => {
+                if let Some(camera) = cameras.camera(id) {
+                    let mut camera = camera.lock().unwrap();
+                    camera.position = Point3::from_vec(world.translation);
+                    camera.orientation = world.rotation;
+                }
+            }
+            Attachment::Light(handle) => {
+                lights.set_position(handle, world.translation);
+                lights.set_direction(handle, world.rotation * -Vector3::unit_z());
+            }
+            Attachment::Instance(handle) => {
+                if let Some(instance) = scene.instance_mut(handle) {
+                    instance.position = world.translation;
+                    instance.rotation = world.rotation;
+                    instance.scale = world.scale;
+                }
+            }
+            Attachment::Body(handle) => {
+                if let Some(body) = physics.body_mut(handle).filter(|body| !body.movable) {
+                    body.set_position(world.translation);
+                    body.rotation = world.rotation;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Quaternion, Rad, Rotation3};
+    use crate::{CollisionBox, RigidBody, Sphere};
+
+    fn at(x: f32, y: f32, z: f32) -> NodeTransform {
+        NodeTransform::from_translation(Vector3::new(x, y, z))
+    }
+
+    fn update(hierarchy: &mut Hierarchy, physics: &mut PhysicsWorld) {
+        hierarchy.update(physics, &mut SceneManager::new(), &mut LightManager::new(), &CameraManager::new());
+    }
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut hierarchy = Hierarchy::new();
+        let root = hierarchy.spawn("root", NodeTransform::default());
+        let child = hierarchy.spawn_child(root, "child", NodeTransform::default());
+        let grandchild = hierarchy.spawn_child(child, "grandchild", NodeTransform::default());
+
+        hierarchy.set_parent(root, Some(grandchild));
+        hierarchy.set_parent(child, Some(child));
+        assert_eq!(hierarchy.entity(root).unwrap().parent(), None);
+        assert_eq!(hierarchy.entity(child).unwrap().parent(), Some(root));
+
+        // Moving a subtree elsewhere is fine and leaves the old parent
+        hierarchy.set_parent(grandchild, Some(root));
+        assert_eq!(hierarchy.entity(grandchild).unwrap().parent(), Some(root));
+        assert!(hierarchy.entity(child).unwrap().children().is_empty());
+        assert_eq!(hierarchy.entity(root).unwrap().children(), [child, grandchild]);
+    }
+
+    #[test]
+    fn despawn_removes_descendants() {
+        let mut hierarchy = Hierarchy::new();
+        let root = hierarchy.spawn("root", NodeTransform::default());
+        let child = hierarchy.spawn_child(root, "child", NodeTransform::default());
+        let grandchild = hierarchy.spawn_child(child, "grandchild", NodeTransform::default());
+
+        hierarchy.despawn(child);
+        assert!(hierarchy.entity(grandchild).is_none());
+        assert!(hierarchy.entity(root).unwrap().children().is_empty());
+        assert_eq!(hierarchy.len(), 1);
+    }
+
+    #[test]
+    fn update_propagates_transforms_down() {
+        let mut hierarchy = Hierarchy::new();
+        let mut physics = PhysicsWorld::new(Vector3::new(0.0, 0.0, 0.0));
+        let turned = at(10.0, 0.0, 0.0).with_rotation(Quaternion::from_angle_y(Rad(std::f32::consts::FRAC_PI_2)));
+        let root = hierarchy.spawn("root", turned.with_scale(Vector3::new(2.0, 2.0, 2.0)));
+        let child = hierarchy.spawn_child(root, "child", at(0.0, 0.0, -1.0));
+        let grandchild = hierarchy.spawn_child(child, "grandchild", at(0.0, 1.0, 0.0));
+
+        update(&mut hierarchy, &mut physics);
+        // Forward (-Z) turned a quarter to the left is -X, and scaled by the root
+        assert!(close(hierarchy.world(child).unwrap().translation, Vector3::new(8.0, 0.0, 0.0)));
+        assert!(close(hierarchy.world(grandchild).unwrap().translation, Vector3::new(8.0, 2.0, 0.0)));
+
+        hierarchy.set_local(root, NodeTransform::default());
+        update(&mut hierarchy, &mut physics);
+        assert!(close(hierarchy.world(grandchild).unwrap().translation, Vector3::new(0.0, 1.0, -1.0)));
+    }
+
+    #[test]
+    fn movable_bodies_move_their_entity() {
+        let mut hierarchy = Hierarchy::new();
+        let mut physics = PhysicsWorld::new(Vector3::new(0.0, 0.0, 0.0));
+        let mut body = RigidBody::new(vec![CollisionBox::Sphere(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0))]);
+        body.set_position(Vector3::new(3.0, 4.0, 5.0));
+        let body = physics.add_body(body);
+
+        let parent = hierarchy.spawn("parent", at(1.0, 0.0, 0.0));
+        let car = hierarchy.spawn_child(parent, "car", NodeTransform::default());
+        let camera = hierarchy.spawn_child(car, "camera", at(0.0, 2.0, 0.0));
+        hierarchy.attach(car, Attachment::Body(body));
+
+        update(&mut hierarchy, &mut physics);
+        assert!(close(hierarchy.entity(car).unwrap().local.translation, Vector3::new(2.0, 4.0, 5.0)));
+        assert!(close(hierarchy.world(camera).unwrap().translation, Vector3::new(3.0, 6.0, 5.0)));
+    }
+}
@@ -2,6 +2,8 @@ mod rustygears;
 mod gameloop;
 mod gear;
 mod cameragear;
+mod hierarchy;
 
 pub use rustygears::*;
 pub use gear::*;
+pub use hierarchy::{Attachment, Entity, EntityHandle, Hierarchy};
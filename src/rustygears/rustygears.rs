@@ -1,17 +1,41 @@
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use crate::AssetServer;
 use crate::Camera;
 use crate::CameraManager;
 use crate::Gear;
 use crate::GearEvent;
+use crate::Hierarchy;
+use crate::PhysicsWorld;
+use crate::DebugDraw;
+use crate::DebugUi;
+use crate::Environment;
+use crate::LightManager;
+use crate::MaterialManager;
+use crate::PostProcessing;
+use crate::RenderSettings;
+use crate::SceneManager;
 use crate::Time;
+use crate::WindowConfig;
 use crate::rustygears::gameloop::GameLoop;
 
 pub struct Game {
     gears: Vec<Arc<Mutex<dyn Gear>>>,
     pub time: Time,
+    pub assets: AssetServer,
     pub cameras: CameraManager,
+    pub lights: LightManager,
+    pub materials: MaterialManager,
+    pub scene: SceneManager,
+    pub hierarchy: Hierarchy,
+    pub physics: PhysicsWorld,
+    pub debug: DebugDraw,
+    pub ui: DebugUi,
+    pub environment: Environment,
+    pub post_processing: PostProcessing,
+    pub render_settings: RenderSettings,
+    pub window: WindowConfig,
 }
 
 impl Default for Game {
@@ -25,7 +49,19 @@ impl Game {
         Self {
             gears: Vec::new(),
             time: Time::new(),
+            assets: AssetServer::new(),
             cameras: CameraManager::new(),
+            lights: LightManager::new(),
+            materials: MaterialManager::new(),
+            scene: SceneManager::new(),
+            hierarchy: Hierarchy::new(),
+            physics: PhysicsWorld::new(cgmath::vec3(0.0, -9.81, 0.0)),
+            debug: DebugDraw::default(),
+            ui: DebugUi::default(),
+            environment: Environment::new(),
+            post_processing: PostProcessing::new(),
+            render_settings: RenderSettings::new(),
+            window: WindowConfig::default(),
         }
     }
 
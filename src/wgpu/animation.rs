@@ -0,0 +1,518 @@
+use std::ops::{Add, Mul};
+
+use cgmath::{ElementWise, InnerSpace, Matrix4, One, Quaternion, Rotation, SquareMatrix, Vector3};
+
+use super::model::Model;
+
+/// Local transform of a node, split up so it can be animated and blended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl NodeTransform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Transform of a child placed at `local` relative to this one. Like the matrix
+    /// product, except that shear from rotated non-uniform scale is dropped.
+    pub fn child(&self, local: &NodeTransform) -> NodeTransform {
+        NodeTransform {
+            translation: self.translation + self.rotation * self.scale.mul_element_wise(local.translation),
+            rotation: self.rotation * local.rotation,
+            scale: self.scale.mul_element_wise(local.scale),
+        }
+    }
+
+    /// The local transform that places a child of `parent` at this transform; the
+    /// inverse of `child`.
+    pub fn relative_to(&self, parent: &NodeTransform) -> NodeTransform {
+        let inverse_rotation = parent.rotation.invert();
+        let inverse_scale = parent.scale.map(|s| if s != 0.0 { 1.0 / s } else { 0.0 });
+        NodeTransform {
+            translation: (inverse_rotation * (self.translation - parent.translation)).mul_element_wise(inverse_scale),
+            rotation: inverse_rotation * self.rotation,
+            scale: self.scale.mul_element_wise(inverse_scale),
+        }
+    }
+
+    /// Moves `amount` of the way towards `other`, taking the shorter way around for the rotation.
+    pub fn blend(&self, other: &NodeTransform, amount: f32) -> NodeTransform {
+        NodeTransform {
+            translation: self.translation + (other.translation - self.translation) * amount,
+            rotation: nlerp(self.rotation, other.rotation, amount),
+            scale: self.scale + (other.scale - self.scale) * amount,
+        }
+    }
+}
+
+fn nlerp(a: Quaternion<f32>, b: Quaternion<f32>, amount: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - amount) + b * amount).normalize()
+}
+
+/// A bone of a skeleton: a node of the model and the matrix taking vertices from model
+/// space into the space of that node in the bind pose.
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub node: usize,
+    pub inverse_bind_matrix: Matrix4<f32>,
+}
+
+/// The joints skinned meshes are bound to. Vertex joint indices point into `joints`; the
+/// hierarchy is that of the nodes they belong to.
+#[derive(Clone, Debug, Default)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds every keyframe until the next one.
+    Step,
+    Linear,
+    /// Hermite spline; every keyframe stores an in-tangent, the value and an out-tangent.
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+/// Keyframes animating one property of one node.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    fn sample(&self, time: f32, transform: &mut NodeTransform) {
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                if let Some(value) = sample_keyframes(&self.times, values, self.interpolation, time) {
+                    transform.translation = value;
+                }
+            }
+            Keyframes::Rotation(values) if self.interpolation == Interpolation::Linear => {
+                if !self.times.is_empty() && !values.is_empty() {
+                    transform.rotation = slerp_keyframes(&self.times, values, time);
+                }
+            }
+            Keyframes::Rotation(values) => {
+                if let Some(value) = sample_keyframes(&self.times, values, self.interpolation, time) {
+                    transform.rotation = value.normalize();
+                }
+            }
+            Keyframes::Scale(values) => {
+                if let Some(value) = sample_keyframes(&self.times, values, self.interpolation, time) {
+                    transform.scale = value;
+                }
+            }
+        }
+    }
+}
+
+fn keyframe_span(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let next = times.partition_point(|&t| t <= time);
+    if next == 0 {
+        return (0, 0, 0.0);
+    }
+    if next == times.len() {
+        return (next - 1, next - 1, 0.0);
+    }
+    let previous = next - 1;
+    let span = times[next] - times[previous];
+    let amount = if span > 0.0 { (time - times[previous]) / span } else { 0.0 };
+    (previous, next, amount)
+}
+
+fn sample_keyframes<T>(times: &[f32], values: &[T], interpolation: Interpolation, time: f32) -> Option<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    if times.is_empty() {
+        return None;
+    }
+    let (previous, next, amount) = keyframe_span(times, time);
+
+    match interpolation {
+        Interpolation::Step => values.get(previous).copied(),
+        Interpolation::Linear => {
+            let (a, b) = (*values.get(previous)?, *values.get(next)?);
+            Some(a * (1.0 - amount) + b * amount)
+        }
+        Interpolation::CubicSpline => {
+            let value = |i: usize| values.get(i * 3 + 1).copied();
+            if previous == next {
+                return value(previous);
+            }
+            let span = times[next] - times[previous];
+            let (t, t2, t3) = (amount, amount * amount, amount * amount * amount);
+            let out_tangent = *values.get(previous * 3 + 2)?;
+            let in_tangent = *values.get(next * 3)?;
+            Some(
+                value(previous)? * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * ((t3 - 2.0 * t2 + t) * span)
+                    + value(next)? * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * ((t3 - t2) * span),
+            )
+        }
+    }
+}
+
+fn slerp_keyframes(times: &[f32], values: &[Quaternion<f32>], time: f32) -> Quaternion<f32> {
+    let (previous, next, amount) = keyframe_span(times, time);
+    let (a, b) = (values[previous], values[next.min(values.len() - 1)]);
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, amount)
+}
+
+/// A named animation, e.g. `"walk"`, moving nodes of a model over time.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Time of the last keyframe in seconds.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Overwrites the animated properties of `transforms`, one per model node, with their
+    /// values at `time`.
+    pub fn sample(&self, time: f32, transforms: &mut [NodeTransform]) {
+        for channel in &self.channels {
+            if let Some(transform) = transforms.get_mut(channel.node) {
+                channel.sample(time, transform);
+            }
+        }
+    }
+}
+
+/// A clip being played by an `AnimationPlayer`.
+#[derive(Clone, Debug)]
+pub struct AnimationLayer {
+    pub clip: String,
+    /// Playback position in seconds.
+    pub time: f32,
+    /// Playback rate relative to the player's.
+    pub speed: f32,
+    pub looping: bool,
+    /// How much the clip contributes to the pose, relative to the other layers.
+    pub weight: f32,
+    target_weight: f32,
+    /// Weight change per second while fading.
+    fade_rate: f32,
+}
+
+impl AnimationLayer {
+    fn new(clip: &str, weight: f32) -> Self {
+        Self {
+            clip: clip.to_string(),
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            weight,
+            target_weight: weight,
+            fade_rate: 0.0,
+        }
+    }
+
+    fn fade_to(&mut self, weight: f32, duration: f32) {
+        self.target_weight = weight;
+        if duration > 0.0 {
+            self.fade_rate = (weight - self.weight).abs() / duration;
+        } else {
+            self.weight = weight;
+        }
+    }
+
+    fn is_fading_out(&self) -> bool {
+        self.target_weight <= 0.0
+    }
+}
+
+/// Plays and blends the animation clips of a model instance.
+///
+/// Clips are looked up by name in the instance's model; unknown names are ignored. An
+/// instance of a skinned model without a player, or with no clips playing, shows the
+/// bind pose.
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    layers: Vec<AnimationLayer>,
+    /// Playback rate of all clips.
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self {
+            layers: Vec::new(),
+            speed: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl AnimationPlayer {
+    /// A player looping `clip`.
+    pub fn new(clip: &str) -> Self {
+        let mut player = Self::default();
+        player.play(clip);
+        player
+    }
+
+    /// Switches to `clip` straight away, from its start.
+    pub fn play(&mut self, clip: &str) {
+        self.layers.clear();
+        self.layers.push(AnimationLayer::new(clip, 1.0));
+    }
+
+    /// Fades from whatever is playing to `clip` over `duration` seconds. A clip that is
+    /// already playing keeps its position.
+    pub fn cross_fade(&mut self, clip: &str, duration: f32) {
+        for layer in &mut self.layers {
+            layer.fade_to(0.0, duration);
+        }
+        match self.layers.iter_mut().find(|layer| layer.clip == clip) {
+            Some(layer) => layer.fade_to(1.0, duration),
+            None => {
+                let mut layer = AnimationLayer::new(clip, 0.0);
+                layer.fade_to(1.0, duration);
+                self.layers.push(layer);
+            }
+        }
+    }
+
+    /// Plays `clip` alongside the others with `weight`, e.g. to mix walking and running
+    /// by speed. A weight of zero stops the clip.
+    pub fn blend(&mut self, clip: &str, weight: f32) {
+        match self.layers.iter_mut().find(|layer| layer.clip == clip) {
+            Some(layer) => layer.fade_to(weight, 0.0),
+            None => self.layers.push(AnimationLayer::new(clip, weight)),
+        }
+        self.layers.retain(|layer| layer.weight > 0.0);
+    }
+
+    pub fn stop(&mut self, clip: &str) {
+        self.layers.retain(|layer| layer.clip != clip);
+    }
+
+    pub fn stop_all(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn is_playing(&self, clip: &str) -> bool {
+        self.layers.iter().any(|layer| layer.clip == clip && !layer.is_fading_out())
+    }
+
+    pub fn layers(&self) -> &[AnimationLayer] {
+        &self.layers
+    }
+
+    /// The layer playing `clip`, to change its time, speed or looping.
+    pub fn layer_mut(&mut self, clip: &str) -> Option<&mut AnimationLayer> {
+        self.layers.iter_mut().find(|layer| layer.clip == clip)
+    }
+
+    pub(crate) fn advance(&mut self, dt: f32) {
+        if self.paused {
+            return;
+        }
+        for layer in &mut self.layers {
+            layer.time += dt * self.speed * layer.speed;
+            if layer.weight != layer.target_weight {
+                let step = layer.fade_rate * dt;
+                layer.weight = if layer.weight < layer.target_weight {
+                    (layer.weight + step).min(layer.target_weight)
+                } else {
+                    (layer.weight - step).max(layer.target_weight)
+                };
+            }
+        }
+        self.layers.retain(|layer| !(layer.is_fading_out() && layer.weight <= 0.0));
+    }
+
+    /// Local transforms of every node of `model` for the current state of the player.
+    pub(crate) fn pose(&self, model: &Model) -> Vec<NodeTransform> {
+        let rest: Vec<NodeTransform> = model.nodes.iter().map(|node| node.rest).collect();
+        let mut pose = rest.clone();
+        let mut total = 0.0;
+
+        for layer in &self.layers {
+            let Some(clip) = model.animation(&layer.clip) else {
+                continue;
+            };
+            if layer.weight <= 0.0 {
+                continue;
+            }
+            let time = match layer.looping {
+                true if clip.duration > 0.0 => layer.time.rem_euclid(clip.duration),
+                _ => layer.time.clamp(0.0, clip.duration),
+            };
+
+            let mut sampled = rest.clone();
+            clip.sample(time, &mut sampled);
+            let amount = layer.weight / (total + layer.weight);
+            for (transform, sampled) in pose.iter_mut().zip(&sampled) {
+                *transform = transform.blend(sampled, amount);
+            }
+            total += layer.weight;
+        }
+
+        // Layers fading in from nothing blend with the bind pose
+        if total > 0.0 && total < 1.0 {
+            for (transform, rest) in pose.iter_mut().zip(&rest) {
+                *transform = rest.blend(transform, total);
+            }
+        }
+        pose
+    }
+}
+
+/// Skinning matrices of the skeleton of `model`, posed by `player` or in the bind pose.
+pub(crate) fn joint_matrices(model: &Model, skeleton: &Skeleton, player: Option<&AnimationPlayer>) -> Vec<Matrix4<f32>> {
+    let pose = match player {
+        Some(player) => player.pose(model),
+        None => model.nodes.iter().map(|node| node.rest).collect(),
+    };
+
+    // Nodes are stored parents first
+    let mut world: Vec<Matrix4<f32>> = Vec::with_capacity(model.nodes.len());
+    for (node, transform) in model.nodes.iter().zip(&pose) {
+        let parent = node.parent.map(|parent| world[parent]).unwrap_or_else(Matrix4::identity);
+        world.push(parent * transform.matrix());
+    }
+
+    skeleton
+        .joints
+        .iter()
+        .map(|joint| world.get(joint.node).copied().unwrap_or_else(Matrix4::identity) * joint.inverse_bind_matrix)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wgpu::model::Node;
+
+    fn clip(name: &str, x: f32) -> AnimationClip {
+        AnimationClip {
+            name: name.to_string(),
+            duration: 1.0,
+            channels: vec![Channel {
+                node: 0,
+                times: vec![0.0],
+                keyframes: Keyframes::Translation(vec![Vector3::new(x, 0.0, 0.0)]),
+                interpolation: Interpolation::Step,
+            }],
+        }
+    }
+
+    /// A single node the clips `a` and `b` move to x = 0 and x = 10.
+    fn model() -> Model {
+        Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            nodes: vec![Node {
+                name: "root".to_string(),
+                parent: None,
+                children: Vec::new(),
+                transform: Matrix4::identity(),
+                rest: NodeTransform::from_translation(Vector3::new(-4.0, 0.0, 0.0)),
+                meshes: Vec::new(),
+            }],
+            lods: Vec::new(),
+            skeleton: None,
+            animations: vec![clip("a", 0.0), clip("b", 10.0)],
+            sources: Vec::new(),
+        }
+    }
+
+    fn weights(player: &AnimationPlayer) -> Vec<(&str, f32)> {
+        player.layers().iter().map(|layer| (layer.clip.as_str(), layer.weight)).collect()
+    }
+
+    #[test]
+    fn cross_fade_moves_weight_over_its_duration() {
+        let model = model();
+        let mut player = AnimationPlayer::new("a");
+        player.cross_fade("b", 1.0);
+        assert!(!player.is_playing("a"));
+        assert!(player.is_playing("b"));
+
+        player.advance(0.25);
+        assert_eq!(weights(&player), [("a", 0.75), ("b", 0.25)]);
+        assert!((player.pose(&model)[0].translation.x - 2.5).abs() < 1e-5);
+
+        // The faded out clip is dropped once it reaches zero
+        player.advance(1.0);
+        assert_eq!(weights(&player), [("b", 1.0)]);
+        assert_eq!(player.pose(&model)[0].translation.x, 10.0);
+    }
+
+    #[test]
+    fn partial_weights_blend_with_the_rest_pose() {
+        let model = model();
+        let mut player = AnimationPlayer::default();
+        assert_eq!(player.pose(&model)[0].translation.x, -4.0);
+
+        player.blend("b", 0.5);
+        assert!((player.pose(&model)[0].translation.x - 3.0).abs() < 1e-5);
+
+        player.blend("a", 0.5);
+        assert!((player.pose(&model)[0].translation.x - 5.0).abs() < 1e-5);
+
+        player.blend("b", 0.0);
+        assert_eq!(weights(&player), [("a", 0.5)]);
+    }
+
+    #[test]
+    fn paused_players_do_not_advance() {
+        let mut player = AnimationPlayer::new("a");
+        player.cross_fade("b", 1.0);
+        player.paused = true;
+        player.advance(0.5);
+        assert_eq!(weights(&player), [("a", 1.0), ("b", 0.0)]);
+        assert_eq!(player.layers()[0].time, 0.0);
+    }
+}
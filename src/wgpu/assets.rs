@@ -0,0 +1,369 @@
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, RwLock, Weak};
+
+use notify::Watcher;
+use tokio::sync::OnceCell;
+
+/// Something the `AssetServer` can make out of the contents of a file.
+pub trait Asset: Send + Sync + Sized + 'static {
+    fn from_bytes(bytes: Vec<u8>, path: &Path) -> anyhow::Result<Self>;
+}
+
+impl Asset for Vec<u8> {
+    fn from_bytes(bytes: Vec<u8>, _path: &Path) -> anyhow::Result<Self> {
+        Ok(bytes)
+    }
+}
+
+impl Asset for String {
+    fn from_bytes(bytes: Vec<u8>, path: &Path) -> anyhow::Result<Self> {
+        String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", path.display()))
+    }
+}
+
+/// A decoded PNG, JPEG or HDR image.
+pub struct Image {
+    pub(crate) image: image::DynamicImage,
+}
+
+impl Image {
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+}
+
+impl Asset for Image {
+    fn from_bytes(bytes: Vec<u8>, _path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            image: image::load_from_memory(&bytes)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    /// The file could not be found or read, or its contents were invalid.
+    Failed(String),
+}
+
+struct Slot<T> {
+    file_name: String,
+    value: OnceCell<Result<Arc<T>, String>>,
+}
+
+/// A file loaded by the `AssetServer`.
+///
+/// The asset is cached as long as a clone of its handle is alive: loading the same file
+/// again returns a handle to the same asset instead of reading it a second time.
+pub struct AssetHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> AssetHandle<T> {
+    pub fn file_name(&self) -> &str {
+        &self.slot.file_name
+    }
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+impl<T> fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AssetHandle").field(&self.slot.file_name).finish()
+    }
+}
+
+/// Files changed on disk since the renderer last asked.
+#[derive(Default)]
+pub(crate) struct Changes {
+    /// File names relative to the root they are in, as they are loaded.
+    pub files: HashSet<String>,
+    /// File names of the built-in shaders.
+    pub shaders: HashSet<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.shaders.is_empty()
+    }
+}
+
+struct HotReload {
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    /// Canonical paths of the watched roots, in order.
+    roots: Vec<PathBuf>,
+    shader_dir: Option<PathBuf>,
+}
+
+impl HotReload {
+    fn new(roots: &[PathBuf], shader_dir: Option<&Path>) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        let roots: Vec<PathBuf> = roots.iter().filter_map(|root| root.canonicalize().ok()).collect();
+        for root in &roots {
+            watcher.watch(root, notify::RecursiveMode::Recursive)?;
+        }
+        let shader_dir = shader_dir.and_then(|shader_dir| shader_dir.canonicalize().ok());
+        if let Some(shader_dir) = &shader_dir {
+            watcher.watch(shader_dir, notify::RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            roots,
+            shader_dir,
+        })
+    }
+
+    fn take_changes(&self) -> Changes {
+        let mut changes = Changes::default();
+        let paths = self
+            .events
+            .try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| event.kind.is_create() || event.kind.is_modify())
+            .flat_map(|event| event.paths);
+        for path in paths {
+            let path = path.canonicalize().unwrap_or(path);
+            if self.shader_dir.as_deref() == path.parent() && path.extension().is_some_and(|extension| extension == "wgsl") {
+                if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+                    changes.shaders.insert(file_name.to_string());
+                }
+            }
+            // A file in several roots is reported for each; only the first one is loaded
+            for root in &self.roots {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    let parts: Vec<_> = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect();
+                    changes.files.insert(parts.join("/"));
+                }
+            }
+        }
+        changes
+    }
+}
+
+struct AssetServerInner {
+    roots: RwLock<Vec<PathBuf>>,
+    /// Directory the built-in scene shaders are reloaded from.
+    shader_dir: RwLock<Option<PathBuf>>,
+    /// Slots of the assets that still have handles, by type and file name.
+    cache: Mutex<HashMap<(TypeId, String), Weak<dyn Any + Send + Sync>>>,
+    /// Watches the roots while hot reloading is on.
+    hot_reload: Mutex<Option<HotReload>>,
+}
+
+/// Loads files from the asset root directories, caching them while they are in use.
+///
+/// Files are read and decoded in the background on the tokio runtime of the game loop;
+/// outside of a runtime they are loaded right away. Clones of the server share their
+/// roots and cache, so a gear can take one into a task of its own.
+///
+/// With hot reloading on, the roots are watched for changes: changed models, materials
+/// and textures are reloaded in place, and so are the built-in `scene_prelude.wgsl`,
+/// `shader.wgsl` and `light.wgsl` when a shader directory is set. Meant for development,
+/// as watching a large directory tree has a cost.
+#[derive(Clone)]
+pub struct AssetServer {
+    inner: Arc<AssetServerInner>,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetServer {
+    /// Creates a server with the `res` directory under the working directory as its only root.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(AssetServerInner {
+                roots: RwLock::new(vec![PathBuf::from("res")]),
+                shader_dir: RwLock::new(None),
+                cache: Mutex::new(HashMap::new()),
+                hot_reload: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Adds a directory searched after the existing roots.
+    pub fn add_root(&self, root: impl Into<PathBuf>) {
+        self.inner.roots.write().unwrap().push(root.into());
+        self.rewatch();
+    }
+
+    /// Replaces the directories searched for files, in order.
+    pub fn set_roots(&self, roots: Vec<PathBuf>) {
+        *self.inner.roots.write().unwrap() = roots;
+        self.rewatch();
+    }
+
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.inner.roots.read().unwrap().clone()
+    }
+
+    /// Sets the directory holding the engine's built-in shaders, usually `src/wgpu` in a
+    /// checkout of the engine, so they are reloaded when edited. Off with `None`, the default.
+    pub fn set_shader_dir(&self, shader_dir: Option<PathBuf>) {
+        *self.inner.shader_dir.write().unwrap() = shader_dir;
+        self.rewatch();
+    }
+
+    pub fn shader_dir(&self) -> Option<PathBuf> {
+        self.inner.shader_dir.read().unwrap().clone()
+    }
+
+    /// Path of `file_name` in the first root that has it.
+    pub fn resolve(&self, file_name: &str) -> anyhow::Result<PathBuf> {
+        let roots = self.inner.roots.read().unwrap();
+        roots
+            .iter()
+            .map(|root| root.join(file_name))
+            .find(|path| path.exists())
+            .ok_or_else(|| anyhow::anyhow!("not found in {:?}", roots))
+    }
+
+    /// Starts loading a file, or returns the handle of the asset if it is already cached.
+    pub fn load<T: Asset>(&self, file_name: &str) -> AssetHandle<T> {
+        let key = (TypeId::of::<T>(), file_name.to_string());
+        let mut cache = self.inner.cache.lock().unwrap();
+        cache.retain(|_, slot| slot.strong_count() > 0);
+        if let Some(slot) = cache.get(&key).and_then(Weak::upgrade).and_then(|slot| slot.downcast().ok()) {
+            return AssetHandle { slot };
+        }
+
+        let slot = Arc::new(Slot {
+            file_name: file_name.to_string(),
+            value: OnceCell::new(),
+        });
+        let erased: Arc<dyn Any + Send + Sync> = slot.clone();
+        cache.insert(key, Arc::downgrade(&erased));
+        drop(cache);
+
+        let handle = AssetHandle { slot };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let server = self.clone();
+                let handle = handle.clone();
+                runtime.spawn(async move {
+                    let _ = server.wait(&handle).await;
+                });
+            }
+            Err(_) => {
+                let _ = handle.slot.value.set(self.read(file_name));
+            }
+        }
+        handle
+    }
+
+    /// Loads a file and waits for it, e.g. from a gear's own task.
+    pub async fn load_async<T: Asset>(&self, file_name: &str) -> anyhow::Result<(AssetHandle<T>, Arc<T>)> {
+        let handle = self.load(file_name);
+        let asset = self.wait(&handle).await?;
+        Ok((handle, asset))
+    }
+
+    /// Waits until the asset is loaded.
+    pub async fn wait<T: Asset>(&self, handle: &AssetHandle<T>) -> anyhow::Result<Arc<T>> {
+        let value = handle
+            .slot
+            .value
+            .get_or_init(|| async {
+                if tokio::runtime::Handle::try_current().is_err() {
+                    return self.read(&handle.slot.file_name);
+                }
+                let server = self.clone();
+                let file_name = handle.slot.file_name.clone();
+                tokio::task::spawn_blocking(move || server.read(&file_name))
+                    .await
+                    .unwrap_or_else(|error| Err(error.to_string()))
+            })
+            .await;
+        value.clone().map_err(anyhow::Error::msg)
+    }
+
+    pub fn load_state<T>(&self, handle: &AssetHandle<T>) -> LoadState {
+        match handle.slot.value.get() {
+            None => LoadState::Loading,
+            Some(Ok(_)) => LoadState::Loaded,
+            Some(Err(error)) => LoadState::Failed(error.clone()),
+        }
+    }
+
+    /// The asset, once it is loaded.
+    pub fn get<T>(&self, handle: &AssetHandle<T>) -> Option<Arc<T>> {
+        handle.slot.value.get().and_then(|value| value.as_ref().ok()).cloned()
+    }
+
+    /// Turns watching the roots for changed files on or off.
+    pub fn set_hot_reload(&self, enabled: bool) {
+        let mut hot_reload = self.inner.hot_reload.lock().unwrap();
+        *hot_reload = None;
+        if enabled {
+            match HotReload::new(&self.roots(), self.shader_dir().as_deref()) {
+                Ok(watcher) => *hot_reload = Some(watcher),
+                Err(error) => log::warn!("failed to watch the asset roots: {}", error),
+            }
+        }
+    }
+
+    pub fn hot_reload(&self) -> bool {
+        self.inner.hot_reload.lock().unwrap().is_some()
+    }
+
+    /// Watches the current roots if hot reloading is on.
+    fn rewatch(&self) {
+        if self.hot_reload() {
+            self.set_hot_reload(true);
+        }
+    }
+
+    /// Files changed since the last call, which are dropped from the cache so that loading
+    /// them again reads the new contents.
+    pub(crate) fn take_changes(&self) -> Changes {
+        let changes = match &*self.inner.hot_reload.lock().unwrap() {
+            Some(hot_reload) => hot_reload.take_changes(),
+            None => return Changes::default(),
+        };
+        if !changes.files.is_empty() {
+            let mut cache = self.inner.cache.lock().unwrap();
+            cache.retain(|(_, file_name), _| !changes.files.contains(file_name));
+        }
+        changes
+    }
+
+    fn read<T: Asset>(&self, file_name: &str) -> Result<Arc<T>, String> {
+        let load = || {
+            let path = self.resolve(file_name)?;
+            let bytes = std::fs::read(&path)?;
+            T::from_bytes(bytes, &path)
+        };
+        load().map(Arc::new).map_err(|error: anyhow::Error| format!("{}: {}", file_name, error))
+    }
+}
@@ -12,6 +12,8 @@ static ID_COUNTER: Mutex<u64> = Mutex::new(0);
 pub struct Camera {
     id: u64,
     pub position: Point3<f32>,
+    /// Frame the yaw and pitch are measured in; follows the entity the camera is attached to.
+    pub orientation: Quaternion<f32>,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
     view_position: [f32; 4],
@@ -36,6 +38,7 @@ impl Camera {
         let mut camera = Camera {
             id,
             position: position.into(),
+            orientation: Quaternion::one(),
             yaw: yaw.into(),
             pitch: pitch.into(),
             view_position: [0.0; 4],
@@ -62,8 +65,8 @@ impl Camera {
 
         Matrix4::look_to_rh(
             self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
+            self.orientation * Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
+            self.orientation * Vector3::unit_y(),
         )
     }
 
@@ -159,6 +162,22 @@ impl Projection {
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    pub(crate) fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub(crate) fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    pub(crate) fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub(crate) fn zfar(&self) -> f32 {
+        self.zfar
+    }
 }
 
 //pub struct CameraManager {
@@ -253,6 +272,11 @@ impl CameraManager {
         self.active_camera_id.and_then(|id| self.cameras.get(&id).cloned())
     }
 
+    /// Returns the camera with the given ID, if the manager has one.
+    pub fn camera(&self, camera_id: u64) -> Option<Arc<Mutex<Camera>>> {
+        self.cameras.get(&camera_id).cloned()
+    }
+
     /// Returns the ID of the currently active camera.
     ///
     /// # Returns
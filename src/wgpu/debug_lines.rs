@@ -0,0 +1,288 @@
+use cgmath::{
+    Vector3,
+    Quaternion,
+    Matrix3,
+    InnerSpace,
+};
+use std::f32::consts::TAU;
+use super::model::Vertex;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Vertex for DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+const SPHERE_SEGMENTS: usize = 24;
+
+/// Line segments drawn on top of the scene for one frame.
+///
+/// The list is cleared at the start of every frame; physics debug drawing and gears
+/// handling `GearEvent::Update` can append to it.
+#[derive(Default)]
+pub struct DebugLines {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Number of line segments.
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub(crate) fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+
+    pub fn line(&mut self, start: Vector3<f32>, end: Vector3<f32>, color: [f32; 3]) {
+        self.vertices.push(DebugVertex { position: start.into(), color });
+        self.vertices.push(DebugVertex { position: end.into(), color });
+    }
+
+    /// Small three-axis cross marking a point.
+    pub fn point(&mut self, position: Vector3<f32>, size: f32, color: [f32; 3]) {
+        let h = size / 2.0;
+        self.line(position - Vector3::unit_x() * h, position + Vector3::unit_x() * h, color);
+        self.line(position - Vector3::unit_y() * h, position + Vector3::unit_y() * h, color);
+        self.line(position - Vector3::unit_z() * h, position + Vector3::unit_z() * h, color);
+    }
+
+    pub fn arrow(&mut self, origin: Vector3<f32>, vector: Vector3<f32>, color: [f32; 3]) {
+        let length = vector.magnitude();
+        if length < 1e-6 {
+            return;
+        }
+
+        let end = origin + vector;
+        let direction = vector / length;
+        let helper = if direction.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+        let side = direction.cross(helper).normalize();
+        let up = side.cross(direction);
+        let head = (length * 0.2).min(0.5);
+
+        self.line(origin, end, color);
+        for offset in [side, -side, up, -up] {
+            self.line(end, end - direction * head + offset * head * 0.5, color);
+        }
+    }
+
+    pub fn aabb(&mut self, min: Vector3<f32>, max: Vector3<f32>, color: [f32; 3]) {
+        let center = (min + max) / 2.0;
+        let half = (max - min) / 2.0;
+        self.oriented_box(center, half, Quaternion::new(1.0, 0.0, 0.0, 0.0), color);
+    }
+
+    pub fn oriented_box(&mut self, center: Vector3<f32>, half_extents: Vector3<f32>, rotation: Quaternion<f32>, color: [f32; 3]) {
+        let rotation = Matrix3::from(rotation);
+        let corner = |x: f32, y: f32, z: f32| {
+            center + rotation * Vector3::new(x * half_extents.x, y * half_extents.y, z * half_extents.z)
+        };
+
+        let corners = [
+            corner(-1.0, -1.0, -1.0),
+            corner(1.0, -1.0, -1.0),
+            corner(1.0, 1.0, -1.0),
+            corner(-1.0, 1.0, -1.0),
+            corner(-1.0, -1.0, 1.0),
+            corner(1.0, -1.0, 1.0),
+            corner(1.0, 1.0, 1.0),
+            corner(-1.0, 1.0, 1.0),
+        ];
+
+        for i in 0..4 {
+            self.line(corners[i], corners[(i + 1) % 4], color);
+            self.line(corners[i + 4], corners[(i + 1) % 4 + 4], color);
+            self.line(corners[i], corners[i + 4], color);
+        }
+    }
+
+    /// Sphere drawn as three orthogonal circles.
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, color: [f32; 3]) {
+        let axes = [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x()),
+        ];
+
+        for (u, v) in axes {
+            let mut previous = center + u * radius;
+            for i in 1..=SPHERE_SEGMENTS {
+                let angle = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                let next = center + (u * angle.cos() + v * angle.sin()) * radius;
+                self.line(previous, next, color);
+                previous = next;
+            }
+        }
+    }
+}
+
+/// Runtime switch for the physics debug overlay.
+pub struct DebugDraw {
+    /// Whether `PhysicsWorld::debug_draw` output is added to the lines each frame.
+    pub physics: bool,
+    /// Key that toggles `physics` while the game runs; `None` disables the shortcut.
+    pub toggle_key: Option<crate::KeyCode>,
+    pub lines: DebugLines,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self {
+            physics: false,
+            toggle_key: Some(crate::KeyCode::F3),
+            lines: DebugLines::new(),
+        }
+    }
+}
+
+pub(crate) struct DebugLinesRenderer {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    vertex_count: u32,
+}
+
+impl DebugLinesRenderer {
+    const INITIAL_CAPACITY: usize = 4096;
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Lines Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Lines Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Lines Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[DebugVertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Lines are an overlay: they are always visible, even inside the models they outline.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: super::texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let buffer = Self::create_buffer(device, Self::INITIAL_CAPACITY);
+
+        Self {
+            pipeline,
+            buffer,
+            capacity: Self::INITIAL_CAPACITY,
+            vertex_count: 0,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Lines Buffer"),
+            size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub(crate) fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lines: &DebugLines) {
+        let vertices = lines.vertices();
+        self.vertex_count = vertices.len() as u32;
+        if vertices.is_empty() {
+            return;
+        }
+
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(vertices));
+    }
+
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
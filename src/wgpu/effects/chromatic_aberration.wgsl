@@ -0,0 +1,21 @@
+// Splits the color channels toward the edges of the screen, like a cheap lens
+
+struct ChromaticAberrationParams {
+    intensity: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: ChromaticAberrationParams;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Grows with the distance from the center, so the middle of the screen stays sharp
+    let offset = (in.uv - 0.5) * params.intensity;
+    let source = sample_source(in.uv);
+
+    let red = sample_source(in.uv + offset).r;
+    let blue = sample_source(in.uv - offset).b;
+    return vec4<f32>(red, source.g, blue, source.a);
+}
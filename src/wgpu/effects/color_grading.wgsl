@@ -0,0 +1,42 @@
+// Remaps colors through a 3D lookup table stored as a horizontal strip of `size` squares of
+// `size` x `size` texels: red along x and green along y within each square, blue across squares
+
+struct ColorGradingParams {
+    strength: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: ColorGradingParams;
+@group(1) @binding(1)
+var t_lut: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source = sample_source(in.uv);
+
+    let dimensions = vec2<f32>(textureDimensions(t_lut));
+    let size = dimensions.y;
+    // No table loaded, or not laid out as a strip
+    if size < 2.0 || dimensions.x != size * size {
+        return source;
+    }
+
+    let color = clamp(source.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let blue = color.b * (size - 1.0);
+    let slice = floor(blue);
+    let next_slice = min(slice + 1.0, size - 1.0);
+
+    // Texel centers, so filtering never bleeds into the neighbouring square
+    let texel = color.rg * (size - 1.0) + 0.5;
+    let uv = vec2<f32>(slice * size + texel.x, texel.y) / dimensions;
+    let next_uv = vec2<f32>(next_slice * size + texel.x, texel.y) / dimensions;
+
+    let graded = mix(
+        textureSampleLevel(t_lut, s_linear, uv, 0.0).rgb,
+        textureSampleLevel(t_lut, s_linear, next_uv, 0.0).rgb,
+        blue - slice,
+    );
+    return vec4<f32>(mix(source.rgb, graded, params.strength), source.a);
+}
@@ -0,0 +1,50 @@
+// Fast approximate anti-aliasing: blurs along edges found from the luma of neighbouring pixels
+
+struct FxaaParams {
+    span_max: f32,
+    reduce_mul: f32,
+    reduce_min: f32,
+    _padding: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: FxaaParams;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = frame.texel_size;
+    let center = sample_source(in.uv);
+
+    let luma_nw = luminance(sample_source(in.uv + vec2<f32>(-1.0, -1.0) * t).rgb);
+    let luma_ne = luminance(sample_source(in.uv + vec2<f32>(1.0, -1.0) * t).rgb);
+    let luma_sw = luminance(sample_source(in.uv + vec2<f32>(-1.0, 1.0) * t).rgb);
+    let luma_se = luminance(sample_source(in.uv + vec2<f32>(1.0, 1.0) * t).rgb);
+    let luma_m = luminance(center.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Perpendicular to the luma gradient, i.e. along the edge
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.reduce_mul, params.reduce_min);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2<f32>(-params.span_max), vec2<f32>(params.span_max)) * t;
+
+    let near = 0.5 * (
+        sample_source(in.uv + dir * (1.0 / 3.0 - 0.5)).rgb
+        + sample_source(in.uv + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let far = near * 0.5 + 0.25 * (
+        sample_source(in.uv - dir * 0.5).rgb
+        + sample_source(in.uv + dir * 0.5).rgb
+    );
+
+    // The wide blur overshot the local contrast, so it crossed another edge
+    let luma_far = luminance(far);
+    if luma_far < luma_min || luma_far > luma_max {
+        return vec4<f32>(near, center.a);
+    }
+    return vec4<f32>(far, center.a);
+}
@@ -0,0 +1,252 @@
+use std::any::Any;
+use super::post::{EffectStage, PostEffect};
+
+/// Fast approximate anti-aliasing, smoothing jagged edges after tone mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fxaa {
+    /// Longest blur along an edge, in pixels.
+    pub span_max: f32,
+    /// How much the blur shrinks in bright areas.
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+}
+
+impl Fxaa {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn shader(&self) -> &str {
+        include_str!("fxaa.wgsl")
+    }
+
+    fn uniforms(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&[self.span_max, self.reduce_mul, self.reduce_min, 0.0]).to_vec()
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Darkens the corners of the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vignette {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance from the center where darkening starts, with 0.5 touching the top and bottom edges.
+    pub radius: f32,
+    /// Distance over which the darkening fades in.
+    pub smoothness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            color: [0.0; 3],
+            intensity: 0.4,
+            radius: 0.5,
+            smoothness: 0.5,
+        }
+    }
+}
+
+impl Vignette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_color(mut self, color: [f32; 3]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f32, smoothness: f32) -> Self {
+        self.radius = radius;
+        self.smoothness = smoothness;
+        self
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn shader(&self) -> &str {
+        include_str!("vignette.wgsl")
+    }
+
+    fn uniforms(&self) -> Vec<u8> {
+        let [r, g, b] = self.color;
+        bytemuck::cast_slice(&[r, g, b, self.intensity, self.radius, self.smoothness, 0.0, 0.0]).to_vec()
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Remaps colors through a lookup table image, e.g. one exported from an image editor.
+///
+/// The table is a horizontal strip of `size` squares of `size` x `size` texels (such as
+/// 256x16 or 1024x32): red runs along x and green along y within each square, and blue
+/// across the squares. While the image is missing or malformed the effect does nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorGrading {
+    /// Lookup table image, relative to the `res` directory.
+    pub lut: String,
+    /// Blend between the original (0) and the graded (1) colors.
+    pub strength: f32,
+}
+
+impl ColorGrading {
+    pub fn new(lut: &str) -> Self {
+        Self {
+            lut: lut.to_string(),
+            strength: 1.0,
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn name(&self) -> &str {
+        "color_grading"
+    }
+
+    fn shader(&self) -> &str {
+        include_str!("color_grading.wgsl")
+    }
+
+    fn uniforms(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&[self.strength, 0.0, 0.0, 0.0]).to_vec()
+    }
+
+    fn texture(&self) -> Option<&str> {
+        Some(&self.lut)
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Splits the color channels toward the edges of the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChromaticAberration {
+    /// Offset between the red and blue channels at the screen edges, as a fraction of the screen.
+    pub intensity: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { intensity: 0.01 }
+    }
+}
+
+impl ChromaticAberration {
+    pub fn new(intensity: f32) -> Self {
+        Self { intensity }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn name(&self) -> &str {
+        "chromatic_aberration"
+    }
+
+    fn shader(&self) -> &str {
+        include_str!("chromatic_aberration.wgsl")
+    }
+
+    fn uniforms(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&[self.intensity, 0.0, 0.0, 0.0]).to_vec()
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Screen-space ambient occlusion, darkening creases and contact areas of the HDR scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ssao {
+    /// World space radius searched for occluders.
+    pub radius: f32,
+    /// Depth difference ignored, against self-occlusion on flat surfaces.
+    pub bias: f32,
+    /// Blend between no occlusion (0) and full occlusion (1).
+    pub intensity: f32,
+    /// Contrast of the occlusion.
+    pub power: f32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+            power: 1.5,
+        }
+    }
+}
+
+impl Ssao {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl PostEffect for Ssao {
+    fn name(&self) -> &str {
+        "ssao"
+    }
+
+    fn shader(&self) -> &str {
+        include_str!("ssao.wgsl")
+    }
+
+    fn stage(&self) -> EffectStage {
+        EffectStage::Hdr
+    }
+
+    fn uniforms(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&[self.radius, self.bias, self.intensity, self.power]).to_vec()
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
@@ -0,0 +1,82 @@
+// Screen-space ambient occlusion: darkens creases and contact areas by testing points in a
+// hemisphere around each pixel against the depth buffer
+
+struct SsaoParams {
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    power: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: SsaoParams;
+
+const SAMPLE_COUNT: u32 = 16u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+fn screen_uv(position: vec3<f32>) -> vec2<f32> {
+    let clip = frame.projection * vec4<f32>(position, 1.0);
+    let ndc = clip.xy / clip.w;
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source = sample_source(in.uv);
+    // Nothing was drawn here
+    if load_depth(in.uv) >= 1.0 {
+        return source;
+    }
+
+    let t = frame.texel_size;
+    let position = view_position(in.uv);
+
+    // Normal from the neighbouring positions, taking the smaller step on each axis so it does
+    // not bend around silhouettes
+    let right = view_position(in.uv + vec2<f32>(t.x, 0.0)) - position;
+    let left = position - view_position(in.uv - vec2<f32>(t.x, 0.0));
+    let down = view_position(in.uv + vec2<f32>(0.0, t.y)) - position;
+    let up = position - view_position(in.uv - vec2<f32>(0.0, t.y));
+    let dx = select(right, left, abs(left.z) < abs(right.z));
+    let dy = select(down, up, abs(up.z) < abs(down.z));
+    var normal = normalize(cross(dy, dx));
+    if dot(normal, position) > 0.0 {
+        normal = -normal;
+    }
+
+    // Rotating the kernel per pixel trades banding for fine noise
+    let angle = interleaved_gradient_noise(in.clip_position.xy) * 6.28318531;
+    let random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    var tangent = random - normal * dot(random, normal);
+    if length(tangent) < 0.001 {
+        tangent = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    tangent = normalize(tangent);
+    let bitangent = cross(normal, tangent);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        let fi = f32(i);
+        let cos_theta = 1.0 - (fi + 0.5) / f32(SAMPLE_COUNT);
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let phi = fi * GOLDEN_ANGLE;
+        let direction = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+        // More samples close to the surface, where occlusion matters most
+        var scale = (fi + 1.0) / f32(SAMPLE_COUNT);
+        scale = mix(0.1, 1.0, scale * scale);
+        let offset = tangent * direction.x + bitangent * direction.y + normal * direction.z;
+        let sample = position + offset * params.radius * scale;
+
+        let scene_z = view_position(screen_uv(sample)).z;
+        // Geometry far in front of the sample is a different object, not an occluder
+        let range = smoothstep(0.0, 1.0, params.radius / abs(position.z - scene_z));
+        occlusion += select(0.0, 1.0, scene_z >= sample.z + params.bias) * range;
+    }
+
+    let ambient = pow(1.0 - occlusion / f32(SAMPLE_COUNT), params.power);
+    return vec4<f32>(source.rgb * mix(1.0, ambient, params.intensity), source.a);
+}
@@ -0,0 +1,21 @@
+// Darkens the image toward its corners
+
+struct VignetteParams {
+    color: vec3<f32>,
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: VignetteParams;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source = sample_source(in.uv);
+
+    // Corrected for the aspect ratio, so the vignette is round rather than stretched
+    let offset = (in.uv - 0.5) * vec2<f32>(frame.resolution.x / frame.resolution.y, 1.0);
+    let amount = smoothstep(params.radius, params.radius + params.smoothness, length(offset)) * params.intensity;
+
+    return vec4<f32>(mix(source.rgb, params.color, amount), source.a);
+}
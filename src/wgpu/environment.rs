@@ -0,0 +1,460 @@
+use std::collections::HashSet;
+use wgpu::util::DeviceExt;
+use super::assets::AssetServer;
+use super::skybox::{IblBaker, IblMaps, Skybox, SkyboxRenderer, SkyboxSource};
+
+/// How fog thickens with distance from the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogMode {
+    /// No distance fog; only height fog applies, if any.
+    None,
+    /// Fog grows linearly from nothing at `start` to opaque at `end`.
+    Linear { start: f32, end: f32 },
+    /// Visibility falls off as `exp(-density * distance)`.
+    Exponential { density: f32 },
+    /// Visibility falls off as `exp(-(density * distance)^2)`, keeping nearby objects clearer.
+    ExponentialSquared { density: f32 },
+}
+
+impl FogMode {
+    fn id(&self) -> u32 {
+        match self {
+            FogMode::None => 0,
+            FogMode::Linear { .. } => 1,
+            FogMode::Exponential { .. } => 2,
+            FogMode::ExponentialSquared { .. } => 3,
+        }
+    }
+}
+
+/// Fog that is densest at `base` height and thins out exponentially above it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeightFog {
+    pub base: f32,
+    /// How quickly the fog thins out with height.
+    pub falloff: f32,
+    pub density: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    pub color: [f32; 3],
+    pub mode: FogMode,
+    pub height: Option<HeightFog>,
+}
+
+impl Fog {
+    pub fn linear(color: [f32; 3], start: f32, end: f32) -> Self {
+        Fog {
+            color,
+            mode: FogMode::Linear { start, end },
+            height: None,
+        }
+    }
+
+    pub fn exponential(color: [f32; 3], density: f32) -> Self {
+        Fog {
+            color,
+            mode: FogMode::Exponential { density },
+            height: None,
+        }
+    }
+
+    pub fn exponential_squared(color: [f32; 3], density: f32) -> Self {
+        Fog {
+            color,
+            mode: FogMode::ExponentialSquared { density },
+            height: None,
+        }
+    }
+
+    /// Height fog alone, without distance fog.
+    pub fn height(color: [f32; 3], base: f32, falloff: f32, density: f32) -> Self {
+        Fog {
+            color,
+            mode: FogMode::None,
+            height: Some(HeightFog { base, falloff, density }),
+        }
+    }
+
+    pub fn with_height(mut self, base: f32, falloff: f32, density: f32) -> Self {
+        self.height = Some(HeightFog { base, falloff, density });
+        self
+    }
+}
+
+/// Scene-wide settings that are not tied to any object: background, sky and fog.
+///
+/// While fog is enabled the screen is cleared with the fog color, so distant
+/// geometry blends into the background. A skybox is drawn over the background and,
+/// through image-based lighting, replaces the flat ambient light of the `LightManager`.
+pub struct Environment {
+    background: [f32; 3],
+    fog: Option<Fog>,
+    skybox: Option<Skybox>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            background: [0.1, 0.2, 0.3],
+            fog: None,
+            skybox: None,
+        }
+    }
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the color the screen is cleared with while fog is disabled.
+    pub fn set_background(&mut self, color: [f32; 3]) {
+        self.background = color;
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        self.background
+    }
+
+    pub fn set_fog(&mut self, fog: Fog) {
+        self.fog = Some(fog);
+    }
+
+    pub fn clear_fog(&mut self) {
+        self.fog = None;
+    }
+
+    pub fn fog(&self) -> Option<&Fog> {
+        self.fog.as_ref()
+    }
+
+    pub fn fog_mut(&mut self) -> Option<&mut Fog> {
+        self.fog.as_mut()
+    }
+
+    /// Changes the fog color. Does nothing while fog is disabled.
+    pub fn set_fog_color(&mut self, color: [f32; 3]) {
+        if let Some(fog) = &mut self.fog {
+            fog.color = color;
+        }
+    }
+
+    /// Changes the density of exponential distance fog and of height fog.
+    /// Does nothing while fog is disabled.
+    pub fn set_fog_density(&mut self, density: f32) {
+        if let Some(fog) = &mut self.fog {
+            match &mut fog.mode {
+                FogMode::Exponential { density: d } | FogMode::ExponentialSquared { density: d } => *d = density,
+                FogMode::None | FogMode::Linear { .. } => {}
+            }
+            if let Some(height) = &mut fog.height {
+                height.density = density;
+            }
+        }
+    }
+
+    pub fn set_skybox(&mut self, skybox: Skybox) {
+        self.skybox = Some(skybox);
+    }
+
+    pub fn clear_skybox(&mut self) {
+        self.skybox = None;
+    }
+
+    pub fn skybox(&self) -> Option<&Skybox> {
+        self.skybox.as_ref()
+    }
+
+    pub fn skybox_mut(&mut self) -> Option<&mut Skybox> {
+        self.skybox.as_mut()
+    }
+
+    /// Color the frame is cleared with: the fog color while fog is enabled.
+    pub fn clear_color(&self) -> [f32; 3] {
+        self.fog.map(|fog| fog.color).unwrap_or(self.background)
+    }
+
+    /// `prefiltered_mips` is `None` while no baked sky is available.
+    fn to_raw(&self, prefiltered_mips: Option<u32>) -> EnvironmentUniform {
+        let ibl_params = match (&self.skybox, prefiltered_mips) {
+            (Some(skybox), Some(mips)) => [skybox.ibl_intensity, (mips - 1) as f32, 1.0, skybox.intensity],
+            _ => [0.0; 4],
+        };
+
+        let Some(fog) = self.fog else {
+            return EnvironmentUniform {
+                fog_color: self.background,
+                fog_mode: FogMode::None.id(),
+                fog_params: [0.0; 4],
+                height_params: [0.0; 4],
+                ibl_params,
+            };
+        };
+
+        let fog_params = match fog.mode {
+            FogMode::None => [0.0; 4],
+            FogMode::Linear { start, end } => [start, end, 0.0, 0.0],
+            FogMode::Exponential { density } | FogMode::ExponentialSquared { density } => [0.0, 0.0, density, 0.0],
+        };
+        let height_params = fog
+            .height
+            .map(|height| [height.base, height.falloff, height.density, 1.0])
+            .unwrap_or([0.0; 4]);
+
+        EnvironmentUniform {
+            fog_color: fog.color,
+            fog_mode: fog.mode.id(),
+            fog_params,
+            height_params,
+            ibl_params,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    fog_color: [f32; 3],
+    fog_mode: u32,
+    /// start, end, density, unused
+    fog_params: [f32; 4],
+    /// base, falloff, density, enabled
+    height_params: [f32; 4],
+    /// intensity, max lod, enabled, sky intensity
+    ibl_params: [f32; 4],
+}
+
+/// GPU side of the `Environment`: its uniform, the image-based lighting maps and the sky.
+pub(crate) struct EnvironmentBuffers {
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    baker: IblBaker,
+    maps: IblMaps,
+    sky: SkyboxRenderer,
+    /// Skybox the current maps were baked from, or that failed to load.
+    baked: Option<Skybox>,
+    has_sky: bool,
+    clear_color: wgpu::Color,
+}
+
+impl EnvironmentBuffers {
+    fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let cube = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                cube(1),
+                cube(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                cube(5),
+            ],
+            label: Some("environment_bind_group_layout"),
+        })
+    }
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let layout = Self::create_bind_group_layout(device);
+        let environment = Environment::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Buffer"),
+            contents: bytemuck::cast_slice(&[environment.to_raw(None)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let baker = IblBaker::new(device, queue);
+        let maps = IblMaps::placeholder(device);
+        let bind_group = Self::create_bind_group(device, &layout, &buffer, &maps, &baker, &sampler);
+        let sky = SkyboxRenderer::new(device, color_format, camera_bind_group_layout, &layout, sample_count);
+
+        Self {
+            buffer,
+            bind_group,
+            layout,
+            sampler,
+            baker,
+            maps,
+            sky,
+            baked: None,
+            has_sky: false,
+            clear_color: to_color(environment.clear_color()),
+        }
+    }
+
+    pub(crate) fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// Rebuilds the sky pipeline for render targets with a different sample count.
+    pub(crate) fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.sky = SkyboxRenderer::new(device, color_format, camera_bind_group_layout, &self.layout, sample_count);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        maps: &IblMaps,
+        baker: &IblBaker,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&maps.irradiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&maps.prefiltered),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&baker.brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&maps.sky),
+                },
+            ],
+            label: Some("environment_bind_group"),
+        })
+    }
+
+    /// Uploads the environment, rebaking the sky first if its source changed.
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Environment,
+        assets: &AssetServer,
+    ) {
+        let wanted = environment.skybox();
+        let needs_bake = match (wanted, &self.baked) {
+            (Some(wanted), Some(baked)) => wanted.source != baked.source || wanted.resolution != baked.resolution,
+            (Some(_), None) => true,
+            (None, baked) => baked.is_some(),
+        };
+
+        if needs_bake {
+            self.baked = wanted.cloned();
+            let maps = match wanted {
+                Some(skybox) => match self.baker.bake(device, queue, skybox, assets) {
+                    Ok(maps) => Some(maps),
+                    Err(error) => {
+                        log::error!("failed to load skybox {:?}: {}", skybox.source, error);
+                        None
+                    }
+                },
+                None => None,
+            };
+            self.has_sky = maps.is_some();
+            self.maps = maps.unwrap_or_else(|| IblMaps::placeholder(device));
+            self.bind_group = Self::create_bind_group(device, &self.layout, &self.buffer, &self.maps, &self.baker, &self.sampler);
+        }
+
+        let mips = self.has_sky.then_some(self.maps.prefiltered_mips);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[environment.to_raw(mips)]));
+        self.clear_color = to_color(environment.clear_color());
+    }
+
+    /// Bakes the skybox again on the next upload if any of its images are in `files`.
+    pub(crate) fn reload(&mut self, files: &HashSet<String>) {
+        let changed = self.baked.as_ref().is_some_and(|baked| match &baked.source {
+            SkyboxSource::Equirectangular(file_name) => files.contains(file_name),
+            SkyboxSource::Cubemap(faces) => faces.iter().any(|file_name| files.contains(file_name)),
+        });
+        if changed {
+            self.baked = None;
+        }
+    }
+
+    pub(crate) fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    /// Draws the sky behind everything rendered so far, if a skybox is loaded.
+    pub(crate) fn draw_sky<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.has_sky {
+            self.sky.draw(render_pass, camera_bind_group, &self.bind_group);
+        }
+    }
+}
+
+fn to_color(color: [f32; 3]) -> wgpu::Color {
+    wgpu::Color {
+        r: color[0] as f64,
+        g: color[1] as f64,
+        b: color[2] as f64,
+        a: 1.0,
+    }
+}
@@ -0,0 +1,248 @@
+// Compute passes baking a sky into the cube maps used for image-based lighting.
+// Each entry point has its own bind group layout, so the bindings below do not overlap.
+
+const PI: f32 = 3.14159265359;
+
+// equirect_to_cube
+@group(0) @binding(0)
+var src_equirect: texture_2d<f32>;
+// faces_to_cube
+@group(0) @binding(1)
+var src_faces: texture_2d_array<f32>;
+// downsample
+@group(0) @binding(2)
+var src_mip: texture_2d_array<f32>;
+// irradiance, prefilter
+@group(0) @binding(3)
+var env_cube: texture_cube<f32>;
+@group(0) @binding(4)
+var env_sampler: sampler;
+// every pass writing a cube map
+@group(0) @binding(5)
+var dst_cube: texture_storage_2d_array<rgba16float, write>;
+// brdf_lut
+@group(0) @binding(6)
+var dst_lut: texture_storage_2d<rgba16float, write>;
+
+struct FilterParams {
+    roughness: f32,
+    source_size: f32,
+    sample_count: u32,
+    _padding: u32,
+}
+// prefilter
+@group(0) @binding(7)
+var<uniform> params: FilterParams;
+
+// World direction through the center of texel `id` of a cube face, in the standard
+// cube map layout: +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction(id: vec3<u32>, size: vec2<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let st = uv * 2.0 - 1.0;
+    switch id.z {
+        case 0u: { return normalize(vec3<f32>(1.0, -st.y, -st.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -st.y, st.x)); }
+        case 2u: { return normalize(vec3<f32>(st.x, 1.0, st.y)); }
+        case 3u: { return normalize(vec3<f32>(st.x, -1.0, -st.y)); }
+        case 4u: { return normalize(vec3<f32>(st.x, -st.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-st.x, -st.y, -1.0)); }
+    }
+}
+
+fn load_equirect(texel: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    let wrapped = vec2<i32>((texel.x + size.x) % size.x, clamp(texel.y, 0, size.y - 1));
+    return textureLoad(src_equirect, wrapped, 0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let dir = cube_direction(id, size);
+    let uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+
+    // Bilinear filtering by hand: 32-bit float textures cannot be sampled with a filter
+    let src_size = vec2<i32>(textureDimensions(src_equirect));
+    let pos = uv * vec2<f32>(src_size) - 0.5;
+    let base = vec2<i32>(floor(pos));
+    let f = fract(pos);
+    let top = mix(load_equirect(base, src_size), load_equirect(base + vec2<i32>(1, 0), src_size), f.x);
+    let bottom = mix(load_equirect(base + vec2<i32>(0, 1), src_size), load_equirect(base + vec2<i32>(1, 1), src_size), f.x);
+
+    textureStore(dst_cube, id.xy, id.z, vec4<f32>(mix(top, bottom, f.y).rgb, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn faces_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let src_size = textureDimensions(src_faces);
+    let texel = vec2<u32>((vec2<f32>(id.xy) + 0.5) / vec2<f32>(size) * vec2<f32>(src_size));
+    let color = textureLoad(src_faces, min(texel, src_size - 1u), id.z, 0);
+    textureStore(dst_cube, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let src = vec2<i32>(id.xy * 2u);
+    let layer = i32(id.z);
+    let color = textureLoad(src_mip, src, layer, 0)
+        + textureLoad(src_mip, src + vec2<i32>(1, 0), layer, 0)
+        + textureLoad(src_mip, src + vec2<i32>(0, 1), layer, 0)
+        + textureLoad(src_mip, src + vec2<i32>(1, 1), layer, 0);
+    textureStore(dst_cube, id.xy, id.z, color * 0.25);
+}
+
+// Cosine-weighted integral of the sky over the hemisphere around each direction
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let normal = cube_direction(id, size);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    // Low mips are enough for such a blurry result and avoid missing small bright spots
+    let lod = max(log2(f32(textureDimensions(env_cube).x) / 32.0), 0.0);
+    let delta = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let tangent = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let dir = tangent.x * right + tangent.y * up + tangent.z * normal;
+            sum += textureSampleLevel(env_cube, env_sampler, dir, lod).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    textureStore(dst_cube, id.xy, id.z, vec4<f32>(PI * sum / count, 1.0));
+}
+
+fn radical_inverse(bits_in: u32) -> f32 {
+    var bits = (bits_in << 16u) | (bits_in >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// Half vector around `normal` distributed according to the GGX lobe
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if abs(normal.z) > 0.999 {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + normal * h.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+// Sky convolved with the GGX lobe of `params.roughness`, one mip level per roughness
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let normal = cube_direction(id, size);
+    let roughness = params.roughness;
+    let base_lod = max(log2(params.source_size / f32(size.x)), 0.0);
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), normal, roughness);
+        let l = normalize(2.0 * dot(normal, h) * h - normal);
+        let n_dot_l = dot(normal, l);
+        if n_dot_l > 0.0 {
+            // Sample a mip matching the solid angle covered by this sample, against fireflies
+            let n_dot_h = max(dot(normal, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle), base_lod);
+
+            sum += textureSampleLevel(env_cube, env_sampler, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(dst_cube, id.xy, id.z, vec4<f32>(sum / max(weight, 0.0001), 1.0));
+}
+
+fn geometry_schlick_ibl(n_dot_x: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// Split-sum scale and bias applied to F0, indexed by (n_dot_v, roughness)
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst_lut);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let n_dot_v = max((f32(id.x) + 0.5) / f32(size.x), 0.001);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+
+    let sample_count = 512u;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, sample_count), normal, roughness);
+        let l = normalize(2.0 * dot(view, h) * h - view);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(view, h), 0.0);
+        if n_dot_l > 0.0 {
+            let g = geometry_schlick_ibl(n_dot_v, roughness) * geometry_schlick_ibl(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    textureStore(dst_lut, id.xy, vec4<f32>(scale / f32(sample_count), bias / f32(sample_count), 0.0, 1.0));
+}
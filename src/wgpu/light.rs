@@ -0,0 +1,403 @@
+use cgmath::{
+    Vector3,
+    InnerSpace,
+    Rad,
+};
+use crate::physics::arena::*;
+use super::camera::{Camera, Projection};
+use super::shadow::{ShadowMaps, ShadowSettings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightKind {
+    Point,
+    Directional,
+    Spot,
+}
+
+impl LightKind {
+    fn id(&self) -> u32 {
+        match self {
+            LightKind::Point => 0,
+            LightKind::Directional => 1,
+            LightKind::Spot => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    /// World position; ignored by directional lights.
+    pub position: Vector3<f32>,
+    /// Direction the light shines in; ignored by point lights.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely; `0.0` means unlimited.
+    pub range: f32,
+    /// Constant, linear and quadratic attenuation terms.
+    pub attenuation: [f32; 3],
+    /// Spot lights are at full strength inside `inner_cone` and fade out at `outer_cone`.
+    pub inner_cone: Rad<f32>,
+    pub outer_cone: Rad<f32>,
+    /// Shadow map settings; `None` means the light casts no shadows.
+    pub shadow: Option<ShadowSettings>,
+}
+
+impl Light {
+    pub fn point(position: Vector3<f32>, color: [f32; 3]) -> Self {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: -Vector3::unit_y(),
+            color,
+            intensity: 1.0,
+            range: 0.0,
+            attenuation: [1.0, 0.0, 0.0],
+            inner_cone: Rad(0.0),
+            outer_cone: Rad(0.0),
+            shadow: None,
+        }
+    }
+
+    pub fn directional(direction: Vector3<f32>, color: [f32; 3]) -> Self {
+        Light {
+            kind: LightKind::Directional,
+            direction: direction.normalize(),
+            ..Light::point(Vector3::new(0.0, 0.0, 0.0), color)
+        }
+    }
+
+    pub fn spot<I: Into<Rad<f32>>, O: Into<Rad<f32>>>(
+        position: Vector3<f32>,
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        inner_cone: I,
+        outer_cone: O,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot,
+            direction: direction.normalize(),
+            inner_cone: inner_cone.into(),
+            outer_cone: outer_cone.into(),
+            ..Light::point(position, color)
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_attenuation(mut self, constant: f32, linear: f32, quadratic: f32) -> Self {
+        self.attenuation = [constant, linear, quadratic];
+        self
+    }
+
+    pub fn with_shadows(mut self, settings: ShadowSettings) -> Self {
+        self.shadow = Some(settings);
+        self
+    }
+
+    fn to_raw(self, (shadow_layer, shadow_count): (i32, u32)) -> LightRaw {
+        LightRaw {
+            position: self.position.into(),
+            kind: self.kind.id(),
+            direction: self.direction.into(),
+            range: self.range,
+            color: self.color,
+            intensity: self.intensity,
+            attenuation: self.attenuation,
+            cos_inner: self.inner_cone.0.cos(),
+            cos_outer: self.outer_cone.0.cos(),
+            shadow_layer,
+            shadow_count,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    attenuation: [f32; 3],
+    cos_inner: f32,
+    cos_outer: f32,
+    /// First layer in the shadow atlas, `-1` without shadows.
+    shadow_layer: i32,
+    shadow_count: u32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightHeader {
+    ambient: [f32; 3],
+    count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightHandle(Index);
+
+pub struct LightManager {
+    lights: Arena<Light>,
+    ambient: [f32; 3],
+}
+
+impl LightManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            lights: Arena::new(),
+            ambient: [0.1, 0.1, 0.1],
+        }
+    }
+
+    /// Adds a light to the scene.
+    ///
+    /// # Returns
+    ///
+    /// A handle used to move, modify or remove the light later.
+    pub fn add_light(&mut self, light: Light) -> LightHandle {
+        LightHandle(self.lights.insert(light))
+    }
+
+    /// Removes a light, returning it if the handle was still valid.
+    pub fn remove_light(&mut self, handle: LightHandle) -> Option<Light> {
+        self.lights.remove(handle.0)
+    }
+
+    pub fn light(&self, handle: LightHandle) -> Option<&Light> {
+        self.lights.get(handle.0)
+    }
+
+    pub fn light_mut(&mut self, handle: LightHandle) -> Option<&mut Light> {
+        self.lights.get_mut(handle.0)
+    }
+
+    /// Moves a point or spot light. Does nothing if the handle is no longer valid.
+    pub fn set_position(&mut self, handle: LightHandle, position: Vector3<f32>) {
+        if let Some(light) = self.lights.get_mut(handle.0) {
+            light.position = position;
+        }
+    }
+
+    /// Points a directional or spot light. Does nothing if the handle is no longer valid.
+    pub fn set_direction(&mut self, handle: LightHandle, direction: Vector3<f32>) {
+        if let Some(light) = self.lights.get_mut(handle.0) {
+            light.direction = direction.normalize();
+        }
+    }
+
+    /// Sets the light applied to every surface regardless of the scene lights.
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = ambient;
+    }
+
+    pub fn ambient(&self) -> [f32; 3] {
+        self.ambient
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightHandle, &Light)> {
+        self.lights.iter().map(|(index, light)| (LightHandle(index), light))
+    }
+
+    pub fn count(&self) -> usize {
+        self.lights.len()
+    }
+
+    /// `shadows` holds the shadow layers of every light, in `iter` order.
+    pub(crate) fn to_raw(&self, shadows: &[(i32, u32)]) -> (LightHeader, Vec<LightRaw>) {
+        let lights: Vec<LightRaw> = self
+            .lights
+            .iter()
+            .zip(shadows)
+            .map(|((_, light), shadow)| light.to_raw(*shadow))
+            .collect();
+        let header = LightHeader {
+            ambient: self.ambient,
+            count: lights.len() as u32,
+        };
+        (header, lights)
+    }
+}
+
+/// GPU side of the `LightManager`: a header uniform, a storage buffer of lights and
+/// the shadow maps they sample.
+pub(crate) struct LightBuffers {
+    pub header_buffer: wgpu::Buffer,
+    pub light_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub shadows: ShadowMaps,
+    capacity: usize,
+    count: u32,
+    shadow_generation: u64,
+}
+
+impl LightBuffers {
+    const INITIAL_CAPACITY: usize = 16;
+
+    pub(crate) fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("light_bind_group_layout"),
+        })
+    }
+
+    pub(crate) fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, material_layout: &wgpu::BindGroupLayout) -> Self {
+        let header_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Header Buffer"),
+            size: std::mem::size_of::<LightHeader>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let light_buffer = Self::create_light_buffer(device, Self::INITIAL_CAPACITY);
+        let shadows = ShadowMaps::new(device, material_layout);
+        let bind_group = Self::create_bind_group(device, layout, &header_buffer, &light_buffer, &shadows);
+
+        Self {
+            header_buffer,
+            light_buffer,
+            bind_group,
+            shadow_generation: shadows.generation,
+            shadows,
+            capacity: Self::INITIAL_CAPACITY,
+            count: 0,
+        }
+    }
+
+    fn create_light_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: (capacity * std::mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        header_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: header_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadows.layer_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&shadows.atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&shadows.sampler),
+                },
+            ],
+            label: Some("light_bind_group"),
+        })
+    }
+
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        lights: &LightManager,
+        camera: &Camera,
+        projection: &Projection,
+    ) {
+        let shadows = self.shadows.prepare(device, queue, lights, camera, projection);
+        let (header, raw) = lights.to_raw(&shadows);
+
+        let grown = raw.len() > self.capacity;
+        if grown {
+            self.capacity = raw.len().next_power_of_two();
+            self.light_buffer = Self::create_light_buffer(device, self.capacity);
+        }
+        if grown || self.shadow_generation != self.shadows.generation {
+            self.shadow_generation = self.shadows.generation;
+            self.bind_group = Self::create_bind_group(device, layout, &self.header_buffer, &self.light_buffer, &self.shadows);
+        }
+
+        queue.write_buffer(&self.header_buffer, 0, bytemuck::cast_slice(&[header]));
+        if !raw.is_empty() {
+            queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+        self.count = header.count;
+    }
+
+    /// Number of lights uploaded in the last frame.
+    pub(crate) fn count(&self) -> u32 {
+        self.count
+    }
+}
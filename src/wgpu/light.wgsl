@@ -9,10 +9,25 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
     color: vec3<f32>,
+    intensity: f32,
+    attenuation: vec3<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+struct LightHeader {
+    ambient: vec3<f32>,
+    count: u32,
 }
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<uniform> light_header: LightHeader;
+@group(1) @binding(1)
+var<storage, read> lights: array<Light>;
+
+const LIGHT_DIRECTIONAL: u32 = 1u;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -26,8 +41,16 @@ struct VertexOutput {
 @vertex
 fn vs_main(
     model: VertexInput,
+    @builtin(instance_index) light_index: u32,
 ) -> VertexOutput {
-    let scale = 0.25;
+    let light = lights[light_index];
+
+    // Directional lights have no position, so their marker is collapsed to a point.
+    var scale = 0.25;
+    if light.kind == LIGHT_DIRECTIONAL {
+        scale = 0.0;
+    }
+
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
     out.color = light.color;
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, Vector3};
+
+use super::model::{Bounds, LodLevel, Mesh, ModelVertex};
+use super::resources::MeshData;
+
+/// Levels of detail simplified from a model when it is loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodGeneration {
+    /// Number of levels generated below the original meshes.
+    pub levels: usize,
+    /// Fraction of the previous level's triangles each level keeps.
+    pub reduction: f32,
+    /// Screen size below which the first generated level is drawn; every further level
+    /// halves it.
+    pub screen_size: f32,
+}
+
+impl Default for LodGeneration {
+    fn default() -> Self {
+        Self {
+            levels: 3,
+            reduction: 0.5,
+            screen_size: 0.25,
+        }
+    }
+}
+
+impl LodGeneration {
+    pub fn new(levels: usize) -> Self {
+        Self {
+            levels,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_reduction(mut self, reduction: f32) -> Self {
+        self.reduction = reduction;
+        self
+    }
+
+    pub fn with_screen_size(mut self, screen_size: f32) -> Self {
+        self.screen_size = screen_size;
+        self
+    }
+}
+
+/// Simplifies every mesh into the levels `generation` asks for. Stops early once a level
+/// can no longer be made smaller.
+pub(crate) fn generate_levels(device: &wgpu::Device, meshes: &[MeshData], generation: &LodGeneration) -> Vec<LodLevel> {
+    let reduction = generation.reduction.clamp(0.01, 0.99);
+    let mut levels = Vec::new();
+    let mut previous: Vec<(Vec<ModelVertex>, Vec<u32>)> =
+        meshes.iter().map(|mesh| (mesh.vertices.clone(), mesh.indices.clone())).collect();
+    let mut screen_size = generation.screen_size;
+
+    for _ in 0..generation.levels {
+        let simplified: Vec<(Vec<ModelVertex>, Vec<u32>)> = previous
+            .iter()
+            .map(|(vertices, indices)| {
+                let target = ((indices.len() / 3) as f32 * reduction) as usize;
+                simplify(vertices, indices, target)
+            })
+            .collect();
+
+        let before: usize = previous.iter().map(|(_, indices)| indices.len()).sum();
+        let after: usize = simplified.iter().map(|(_, indices)| indices.len()).sum();
+        if after == 0 || after >= before {
+            break;
+        }
+
+        let lod_meshes = meshes
+            .iter()
+            .zip(&simplified)
+            .map(|(mesh, (vertices, indices))| Mesh::new(device, &mesh.name, vertices, indices, mesh.material))
+            .collect();
+        levels.push(LodLevel { meshes: lod_meshes, screen_size });
+
+        previous = simplified;
+        screen_size *= 0.5;
+    }
+
+    levels
+}
+
+/// Reduces a triangle list to about `target` triangles by vertex clustering: vertices in
+/// the same cell of a grid, facing roughly the same way, are merged and the triangles that
+/// collapse are dropped. The grid resolution is searched for the count closest to `target`.
+pub(crate) fn simplify(vertices: &[ModelVertex], indices: &[u32], target: usize) -> (Vec<ModelVertex>, Vec<u32>) {
+    if vertices.is_empty() || indices.len() < 3 {
+        return (vertices.to_vec(), indices.to_vec());
+    }
+
+    let bounds = Bounds::from_points(vertices.iter().map(|vertex| vertex.position));
+    let size = bounds.max - bounds.min;
+    let longest = size.x.max(size.y).max(size.z).max(f32::EPSILON);
+
+    let mut best: Option<(Vec<ModelVertex>, Vec<u32>)> = None;
+    let (mut low, mut high) = (1u32, 1024u32);
+    while low <= high {
+        let resolution = (low + high) / 2;
+        let result = cluster(vertices, indices, &bounds, longest / resolution as f32);
+        let triangles = result.1.len() / 3;
+        if triangles > target {
+            high = resolution - 1;
+        } else {
+            low = resolution + 1;
+        }
+
+        // Prefer the largest result that still meets the target
+        let better = match &best {
+            None => true,
+            Some((_, best_indices)) => {
+                let best_triangles = best_indices.len() / 3;
+                match (triangles <= target, best_triangles <= target) {
+                    (true, true) => triangles > best_triangles,
+                    (true, false) => true,
+                    (false, true) => false,
+                    (false, false) => triangles < best_triangles,
+                }
+            }
+        };
+        if better {
+            best = Some(result);
+        }
+    }
+
+    best.unwrap_or_else(|| (vertices.to_vec(), indices.to_vec()))
+}
+
+fn cluster(vertices: &[ModelVertex], indices: &[u32], bounds: &Bounds, cell_size: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    // Sums of the merged vertices, averaged below
+    let mut cells: HashMap<(i32, i32, i32, u8), usize> = HashMap::new();
+    let mut merged: Vec<(ModelVertex, Vector3<f32>, Vector3<f32>, f32)> = Vec::new();
+    let mut remap = Vec::with_capacity(vertices.len());
+
+    for vertex in vertices {
+        let position = Vector3::from(vertex.position);
+        let cell = (position - bounds.min) / cell_size;
+        let key = (cell.x as i32, cell.y as i32, cell.z as i32, normal_direction(vertex.normal));
+
+        let index = *cells.entry(key).or_insert_with(|| {
+            merged.push((*vertex, Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), 0.0));
+            merged.len() - 1
+        });
+        let (_, positions, normals, count) = &mut merged[index];
+        *positions += position;
+        *normals += Vector3::from(vertex.normal);
+        *count += 1.0;
+        remap.push(index as u32);
+    }
+
+    let vertices = merged
+        .into_iter()
+        .map(|(mut vertex, positions, normals, count)| {
+            vertex.position = (positions / count).into();
+            if normals.magnitude2() > 0.0 {
+                vertex.normal = normals.normalize().into();
+            }
+            vertex
+        })
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut simplified = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [remap[triangle[0] as usize], remap[triangle[1] as usize], remap[triangle[2] as usize]];
+        if a == b || b == c || a == c {
+            continue;
+        }
+
+        // Rotate the smallest index first so duplicates compare equal without losing the winding
+        let key = if a < b && a < c {
+            (a, b, c)
+        } else if b < c {
+            (b, c, a)
+        } else {
+            (c, a, b)
+        };
+        if seen.insert(key) {
+            simplified.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    (vertices, simplified)
+}
+
+/// Which of the six axis directions a normal is closest to, keeping the two sides of thin
+/// walls and hard edges from merging.
+fn normal_direction(normal: [f32; 3]) -> u8 {
+    let [x, y, z] = normal.map(f32::abs);
+    let (axis, value) = if x >= y && x >= z {
+        (0, normal[0])
+    } else if y >= z {
+        (1, normal[1])
+    } else {
+        (2, normal[2])
+    };
+    axis * 2 + (value < 0.0) as u8
+}
+
+/// Level to draw for a model covering `screen_size` of the screen height, given the level
+/// drawn last frame. A level is only left once the screen size is `hysteresis` (a fraction
+/// of the threshold) past its boundary, so models resting near one do not flicker.
+pub(crate) fn select_level(levels: &[LodLevel], screen_size: f32, previous: usize, hysteresis: f32) -> usize {
+    let mut level = previous.min(levels.len());
+    while level < levels.len() && screen_size < levels[level].screen_size * (1.0 - hysteresis) {
+        level += 1;
+    }
+    while level > 0 && screen_size > levels[level - 1].screen_size * (1.0 + hysteresis) {
+        level -= 1;
+    }
+    level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(screen_sizes: &[f32]) -> Vec<LodLevel> {
+        screen_sizes.iter().map(|&screen_size| LodLevel { meshes: Vec::new(), screen_size }).collect()
+    }
+
+    /// Flat grid of `cells` by `cells` quads facing up.
+    fn grid(cells: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for z in 0..=cells {
+            for x in 0..=cells {
+                vertices.push(ModelVertex {
+                    position: [x as f32, 0.0, z as f32],
+                    tex_coords: [0.0, 0.0],
+                    normal: [0.0, 1.0, 0.0],
+                    tangent: [1.0, 0.0, 0.0],
+                    bitangent: [0.0, 0.0, 1.0],
+                    joints: [0; 4],
+                    weights: [0.0; 4],
+                });
+            }
+        }
+        let mut indices = Vec::new();
+        for z in 0..cells {
+            for x in 0..cells {
+                let i = z * (cells + 1) + x;
+                indices.extend_from_slice(&[i, i + cells + 1, i + 1, i + 1, i + cells + 1, i + cells + 2]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    #[test]
+    fn select_level_waits_for_the_hysteresis_margin() {
+        let levels = levels(&[0.5, 0.25]);
+        assert_eq!(select_level(&levels, 0.8, 0, 0.1), 0);
+        assert_eq!(select_level(&levels, 0.1, 0, 0.1), 2);
+
+        // Just under the first threshold: stays on the full mesh until 10% past it
+        assert_eq!(select_level(&levels, 0.47, 0, 0.1), 0);
+        assert_eq!(select_level(&levels, 0.44, 0, 0.1), 1);
+
+        // And going back up, stays on level 1 until 10% above it
+        assert_eq!(select_level(&levels, 0.53, 1, 0.1), 1);
+        assert_eq!(select_level(&levels, 0.56, 1, 0.1), 0);
+    }
+
+    #[test]
+    fn select_level_clamps_a_stale_previous_level() {
+        let levels = levels(&[0.5]);
+        assert_eq!(select_level(&levels, 0.4, 5, 0.0), 1);
+        assert_eq!(select_level(&[], 0.1, 2, 0.1), 0);
+    }
+
+    #[test]
+    fn simplify_reaches_its_target() {
+        let (vertices, indices) = grid(32);
+        let triangles = indices.len() / 3;
+        for target in [triangles / 2, triangles / 8, 16] {
+            let (simplified_vertices, simplified) = simplify(&vertices, &indices, target);
+            let count = simplified.len() / 3;
+            assert!(count <= target, "{count} triangles for a target of {target}");
+            assert!(count >= target / 4, "{count} triangles for a target of {target}");
+            assert!(simplified.iter().all(|&index| (index as usize) < simplified_vertices.len()));
+        }
+    }
+}
@@ -0,0 +1,646 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::physics::arena::{Arena, Index};
+use super::assets::{AssetHandle, AssetServer, LoadState};
+use super::model::BlendMode;
+use super::post;
+use super::texture;
+use super::window::BlendPipelines;
+
+/// Value of a material parameter, laid out in the uniform like the WGSL type it stands for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamValue {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl ParamValue {
+    fn wgsl_type(&self) -> &'static str {
+        match self {
+            ParamValue::Float(_) => "f32",
+            ParamValue::Vec2(_) => "vec2<f32>",
+            ParamValue::Vec3(_) => "vec3<f32>",
+            ParamValue::Vec4(_) => "vec4<f32>",
+        }
+    }
+
+    /// Alignment in a uniform buffer, in bytes.
+    fn align(&self) -> usize {
+        match self {
+            ParamValue::Float(_) => 4,
+            ParamValue::Vec2(_) => 8,
+            ParamValue::Vec3(_) | ParamValue::Vec4(_) => 16,
+        }
+    }
+
+    fn components(&self) -> &[f32] {
+        match self {
+            ParamValue::Float(value) => std::slice::from_ref(value),
+            ParamValue::Vec2(value) => value,
+            ParamValue::Vec3(value) => value,
+            ParamValue::Vec4(value) => value,
+        }
+    }
+
+    fn same_type(&self, other: &ParamValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// A texture a material shader samples.
+#[derive(Clone, Debug, PartialEq)]
+struct TextureSlot {
+    name: String,
+    /// Whether the texture holds colors, decoded from sRGB when sampled, or plain data.
+    srgb: bool,
+}
+
+/// A custom WGSL shader for scene materials.
+///
+/// The WGSL returned by `source` is appended to a prelude (`scene_prelude.wgsl`) declaring
+/// the camera, lights, shadows and environment of the built-in shader, the vertex layout as
+/// `VertexInput` and `InstanceInput`, and `VertexOutput` with the world position, normal,
+/// tangent frame and texture coordinates. It also provides helpers such as `model_vertex`,
+/// which places and skins a vertex, `light_incidence`, `shadow_factor`, `fog_visibility` and
+/// `image_based_lighting`.
+///
+/// The declared parameters become the fields of `params` at `@group(0) @binding(0)`, and each
+/// declared texture `name` becomes `t_name` and `s_name`, bound after it in order. The shader
+/// defines `vs_main`, usually just `return model_vertex(model, instance);`, and
+/// `fs_main(in: VertexOutput) -> @location(0) vec4<f32>` returning linear HDR color.
+///
+/// Shadows are cast with the undeformed meshes. A shader that fails to compile is reported
+/// and its materials are drawn with the materials of the model file instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialShader {
+    pub name: String,
+    pub source: String,
+    params: Vec<(String, ParamValue)>,
+    textures: Vec<TextureSlot>,
+}
+
+impl MaterialShader {
+    pub fn new(name: &str, source: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            source: source.to_string(),
+            params: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    /// Declares a parameter with the type and default value of `value`.
+    pub fn with_param(mut self, name: &str, value: ParamValue) -> Self {
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    /// Declares a color texture, such as an albedo map.
+    pub fn with_texture(mut self, name: &str) -> Self {
+        self.textures.push(TextureSlot {
+            name: name.to_string(),
+            srgb: true,
+        });
+        self
+    }
+
+    /// Declares a texture holding data rather than colors, such as a normal or noise map.
+    pub fn with_linear_texture(mut self, name: &str) -> Self {
+        self.textures.push(TextureSlot {
+            name: name.to_string(),
+            srgb: false,
+        });
+        self
+    }
+
+    pub fn params(&self) -> impl Iterator<Item = (&str, ParamValue)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = &str> {
+        self.textures.iter().map(|slot| slot.name.as_str())
+    }
+
+    /// Full WGSL of the shader: the prelude, the bindings of the declared parameters and
+    /// textures, then `source`.
+    fn wgsl(&self, prelude: &str) -> String {
+        let mut bindings = String::new();
+        if !self.params.is_empty() {
+            bindings.push_str("struct MaterialParams {\n");
+            for (name, value) in &self.params {
+                bindings.push_str(&format!("    {}: {},\n", name, value.wgsl_type()));
+            }
+            bindings.push_str("}\n@group(0) @binding(0)\nvar<uniform> params: MaterialParams;\n");
+        }
+        for (i, slot) in self.textures.iter().enumerate() {
+            bindings.push_str(&format!(
+                "@group(0) @binding({})\nvar t_{}: texture_2d<f32>;\n@group(0) @binding({})\nvar s_{}: sampler;\n",
+                1 + 2 * i,
+                slot.name,
+                2 + 2 * i,
+                slot.name,
+            ));
+        }
+        format!("{}\n{}\n{}", prelude, bindings, self.source)
+    }
+
+    /// The parameters packed with the alignment WGSL gives them in a uniform, taking each
+    /// value from `values` when it is there with the declared type.
+    fn uniforms(&self, values: &[(String, ParamValue)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (name, default) in &self.params {
+            let value = values
+                .iter()
+                .find(|(other, value)| other == name && value.same_type(default))
+                .map_or(default, |(_, value)| value);
+            bytes.resize(bytes.len().next_multiple_of(value.align()), 0);
+            bytes.extend_from_slice(bytemuck::cast_slice(value.components()));
+        }
+        bytes.resize(bytes.len().max(16).next_multiple_of(16), 0);
+        bytes
+    }
+}
+
+/// Parameter values and textures for a `MaterialShader`, assigned to models with
+/// `SceneManager::set_material`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomMaterial {
+    pub shader: ShaderHandle,
+    /// How the material is blended. A `Cutout` material is drawn like an opaque one and
+    /// leaves discarding fragments to its shader.
+    pub blend_mode: BlendMode,
+    params: Vec<(String, ParamValue)>,
+    textures: Vec<(String, String)>,
+}
+
+impl CustomMaterial {
+    pub fn new(shader: ShaderHandle) -> Self {
+        Self {
+            shader,
+            blend_mode: BlendMode::Opaque,
+            params: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn with_param(mut self, name: &str, value: ParamValue) -> Self {
+        self.set_param(name, value);
+        self
+    }
+
+    /// Binds an image from the asset roots to the texture the shader declared as `name`.
+    pub fn with_texture(mut self, name: &str, file_name: &str) -> Self {
+        self.set_texture(name, file_name);
+        self
+    }
+
+    /// Sets a parameter, e.g. every frame for an animated one. Parameters that are not
+    /// set, or set with another type than the shader declared, keep their default.
+    pub fn set_param(&mut self, name: &str, value: ParamValue) {
+        match self.params.iter_mut().find(|(other, _)| other == name) {
+            Some((_, current)) => *current = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<ParamValue> {
+        self.params.iter().find(|(other, _)| other == name).map(|(_, value)| *value)
+    }
+
+    /// Textures that are not set sample a single white texel.
+    pub fn set_texture(&mut self, name: &str, file_name: &str) {
+        match self.textures.iter_mut().find(|(other, _)| other == name) {
+            Some((_, current)) => *current = file_name.to_string(),
+            None => self.textures.push((name.to_string(), file_name.to_string())),
+        }
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&str> {
+        self.textures.iter().find(|(other, _)| other == name).map(|(_, file_name)| file_name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(Index);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(Index);
+
+/// Custom material shaders and the materials made from them.
+pub struct MaterialManager {
+    shaders: Arena<MaterialShader>,
+    materials: Arena<CustomMaterial>,
+}
+
+impl MaterialManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            shaders: Arena::new(),
+            materials: Arena::new(),
+        }
+    }
+
+    /// Registers a shader; its pipeline is built on the next frame.
+    pub fn add_shader(&mut self, shader: MaterialShader) -> ShaderHandle {
+        ShaderHandle(self.shaders.insert(shader))
+    }
+
+    /// Removes a shader. Its materials are drawn with those of the model files until they
+    /// are given another shader.
+    pub fn remove_shader(&mut self, handle: ShaderHandle) -> Option<MaterialShader> {
+        self.shaders.remove(handle.0)
+    }
+
+    pub fn shader(&self, handle: ShaderHandle) -> Option<&MaterialShader> {
+        self.shaders.get(handle.0)
+    }
+
+    /// Changing the source or the declarations rebuilds the pipeline on the next frame.
+    pub fn shader_mut(&mut self, handle: ShaderHandle) -> Option<&mut MaterialShader> {
+        self.shaders.get_mut(handle.0)
+    }
+
+    pub fn find_shader(&self, name: &str) -> Option<ShaderHandle> {
+        self.shaders.iter().find(|(_, shader)| shader.name == name).map(|(index, _)| ShaderHandle(index))
+    }
+
+    pub fn add_material(&mut self, material: CustomMaterial) -> MaterialHandle {
+        MaterialHandle(self.materials.insert(material))
+    }
+
+    pub fn remove_material(&mut self, handle: MaterialHandle) -> Option<CustomMaterial> {
+        self.materials.remove(handle.0)
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&CustomMaterial> {
+        self.materials.get(handle.0)
+    }
+
+    pub fn material_mut(&mut self, handle: MaterialHandle) -> Option<&mut CustomMaterial> {
+        self.materials.get_mut(handle.0)
+    }
+
+    /// Sets a parameter of a material. Does nothing if the handle is no longer valid.
+    pub fn set_param(&mut self, handle: MaterialHandle, name: &str, value: ParamValue) {
+        if let Some(material) = self.materials.get_mut(handle.0) {
+            material.set_param(name, value);
+        }
+    }
+
+    pub fn materials(&self) -> impl Iterator<Item = (MaterialHandle, &CustomMaterial)> {
+        self.materials.iter().map(|(index, material)| (MaterialHandle(index), material))
+    }
+}
+
+struct ShaderPipeline {
+    /// Shader the pipeline was built from, to notice changes.
+    shader: MaterialShader,
+    layout: wgpu::BindGroupLayout,
+    /// `None` when the shader failed to compile.
+    pipelines: Option<BlendPipelines>,
+    /// Bumped whenever the pipeline is rebuilt, so the materials rebuild their bind groups.
+    generation: u64,
+}
+
+struct MaterialBindings {
+    shader: ShaderHandle,
+    blend_mode: BlendMode,
+    generation: u64,
+    buffer: wgpu::Buffer,
+    /// File names of the textures, by slot.
+    texture_names: Vec<Option<String>>,
+    /// Textures in use; empty slots are bound to a white texture.
+    textures: Vec<Option<texture::Texture>>,
+    /// Files still being read by the asset server, by slot.
+    pending: Vec<Option<AssetHandle<Vec<u8>>>>,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+/// GPU side of the `MaterialManager`: the pipelines of each shader and a uniform buffer
+/// and bind group per material.
+pub(crate) struct MaterialRenderer {
+    shaders: HashMap<ShaderHandle, ShaderPipeline>,
+    materials: HashMap<MaterialHandle, MaterialBindings>,
+    /// Declarations shared with the built-in scene shader.
+    prelude: Cow<'static, str>,
+    sample_count: u32,
+    generation: u64,
+    default_texture: texture::Texture,
+}
+
+impl MaterialRenderer {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, prelude: Cow<'static, str>, sample_count: u32) -> Self {
+        Self {
+            shaders: HashMap::new(),
+            materials: HashMap::new(),
+            prelude,
+            sample_count,
+            generation: 0,
+            default_texture: texture::Texture::from_color(device, queue, [1.0; 4], Some("white"), true)
+                .expect("failed to create the default material texture"),
+        }
+    }
+
+    /// Replaces the prelude, rebuilding every pipeline on the next upload.
+    pub(crate) fn set_prelude(&mut self, prelude: Cow<'static, str>) {
+        self.prelude = prelude;
+        self.shaders.clear();
+    }
+
+    pub(crate) fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.shaders.clear();
+    }
+
+    /// Builds pipelines for new or changed shaders, then uploads the parameters of every
+    /// material and binds their textures once the asset server has read them. `scene_layouts` are the camera, light and
+    /// environment bind group layouts of the built-in scene pipeline.
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        materials: &MaterialManager,
+        scene_layouts: [&wgpu::BindGroupLayout; 3],
+        assets: &AssetServer,
+    ) {
+        self.shaders.retain(|handle, _| materials.shaders.contains(handle.0));
+        self.materials.retain(|handle, _| materials.materials.contains(handle.0));
+
+        for (index, shader) in materials.shaders.iter() {
+            let handle = ShaderHandle(index);
+            if self.shaders.get(&handle).is_none_or(|gpu| gpu.shader != *shader) {
+                self.generation += 1;
+                let gpu = self.build(device, shader, scene_layouts);
+                self.shaders.insert(handle, gpu);
+            }
+        }
+
+        for (index, material) in materials.materials.iter() {
+            let handle = MaterialHandle(index);
+            let Some(shader) = self.shaders.get(&material.shader) else {
+                self.materials.remove(&handle);
+                continue;
+            };
+
+            let uniforms = shader.shader.uniforms(&material.params);
+            let gpu = self.materials.entry(handle).or_insert_with(|| MaterialBindings {
+                shader: material.shader,
+                blend_mode: material.blend_mode,
+                generation: 0,
+                buffer: create_params_buffer(device, uniforms.len() as u64),
+                texture_names: Vec::new(),
+                textures: Vec::new(),
+                pending: Vec::new(),
+                bind_group: None,
+            });
+            let mut rebind = gpu.shader != material.shader || gpu.generation != shader.generation;
+            gpu.shader = material.shader;
+            gpu.blend_mode = material.blend_mode;
+            gpu.generation = shader.generation;
+
+            if uniforms.len() as u64 != gpu.buffer.size() {
+                gpu.buffer = create_params_buffer(device, uniforms.len() as u64);
+                rebind = true;
+            }
+            queue.write_buffer(&gpu.buffer, 0, &uniforms);
+
+            let slots = &shader.shader.textures;
+            gpu.texture_names.resize(slots.len(), None);
+            gpu.textures.resize_with(slots.len(), || None);
+            gpu.pending.resize_with(slots.len(), || None);
+            for (i, slot) in slots.iter().enumerate() {
+                let file_name = material.texture(&slot.name);
+                if file_name != gpu.texture_names[i].as_deref() {
+                    gpu.texture_names[i] = file_name.map(str::to_string);
+                    gpu.pending[i] = file_name.map(|file_name| assets.load(file_name));
+                    if file_name.is_none() {
+                        gpu.textures[i] = None;
+                        rebind = true;
+                    }
+                }
+
+                // The current texture stays bound until the new file is read
+                let Some(pending) = &gpu.pending[i] else {
+                    continue;
+                };
+                let loaded = match assets.load_state(pending) {
+                    LoadState::Loading => continue,
+                    LoadState::Loaded => match assets.get(pending) {
+                        Some(bytes) => texture::Texture::from_bytes(device, queue, &bytes, pending.file_name(), !slot.srgb),
+                        None => continue,
+                    },
+                    LoadState::Failed(error) => Err(anyhow::anyhow!(error)),
+                };
+                gpu.textures[i] = loaded
+                    .map_err(|error| log::error!("failed to load material texture {}: {}", pending.file_name(), error))
+                    .ok();
+                gpu.pending[i] = None;
+                rebind = true;
+            }
+
+            if rebind || gpu.bind_group.is_none() {
+                let textures = gpu.textures.iter().map(|texture| texture.as_ref().unwrap_or(&self.default_texture));
+                gpu.bind_group = Some(create_material_bind_group(device, &shader.layout, &gpu.buffer, textures));
+            }
+        }
+    }
+
+    /// Loads the textures of the materials using any of `files` again on the next upload.
+    pub(crate) fn reload(&mut self, files: &HashSet<String>) {
+        for gpu in self.materials.values_mut() {
+            for name in &mut gpu.texture_names {
+                if name.as_ref().is_some_and(|file_name| files.contains(file_name)) {
+                    *name = None;
+                }
+            }
+        }
+    }
+
+    /// Pipeline and bind group drawing with a material, if its shader compiled.
+    pub(crate) fn get(&self, handle: MaterialHandle) -> Option<(&wgpu::RenderPipeline, &wgpu::BindGroup)> {
+        let gpu = self.materials.get(&handle)?;
+        let pipelines = self.shaders.get(&gpu.shader)?.pipelines.as_ref()?;
+        Some((pipelines.get(gpu.blend_mode), gpu.bind_group.as_ref()?))
+    }
+
+    /// Blend mode a material is drawn with, if it can be drawn.
+    pub(crate) fn blend_mode(&self, handle: MaterialHandle) -> Option<BlendMode> {
+        self.get(handle)?;
+        Some(self.materials.get(&handle)?.blend_mode)
+    }
+
+    fn build(&self, device: &wgpu::Device, shader: &MaterialShader, scene_layouts: [&wgpu::BindGroupLayout; 3]) -> ShaderPipeline {
+        let layout = create_material_bind_group_layout(device, shader.textures.len());
+        let [camera_layout, light_layout, environment_layout] = scene_layouts;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Material Pipeline Layout"),
+            bind_group_layouts: &[&layout, camera_layout, light_layout, environment_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = post::catch_validation(device, || {
+            BlendPipelines::new(
+                device,
+                &pipeline_layout,
+                wgpu::ShaderModuleDescriptor {
+                    label: Some(&shader.name),
+                    source: wgpu::ShaderSource::Wgsl(shader.wgsl(&self.prelude).into()),
+                },
+                self.sample_count,
+            )
+        });
+        let pipelines = match pipelines {
+            Ok(pipelines) => Some(pipelines),
+            Err(error) => {
+                log::error!("failed to build material shader {}: {}", shader.name, error);
+                None
+            }
+        };
+
+        ShaderPipeline {
+            shader: shader.clone(),
+            layout,
+            pipelines,
+            generation: self.generation,
+        }
+    }
+}
+
+/// Layout of the parameter uniform followed by a texture and sampler per texture slot.
+fn create_material_bind_group_layout(device: &wgpu::Device, texture_count: usize) -> wgpu::BindGroupLayout {
+    let visibility = wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT;
+    let mut entries = vec![wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+    for i in 0..texture_count as u32 {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 1 + 2 * i,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 2 + 2 * i,
+            visibility,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        });
+    }
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &entries,
+        label: Some("custom_material_bind_group_layout"),
+    })
+}
+
+fn create_material_bind_group<'a>(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    textures: impl Iterator<Item = &'a texture::Texture>,
+) -> wgpu::BindGroup {
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 0,
+        resource: buffer.as_entire_binding(),
+    }];
+    for (i, texture) in textures.enumerate() {
+        entries.push(wgpu::BindGroupEntry {
+            binding: 1 + 2 * i as u32,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding: 2 + 2 * i as u32,
+            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+        });
+    }
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: Some("custom_material_bind_group"),
+    })
+}
+
+fn create_params_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Material Params Buffer"),
+        size,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytemuck::pod_collect_to_vec(bytes)
+    }
+
+    #[test]
+    fn uniforms_follow_wgsl_alignment() {
+        let shader = MaterialShader::new("packing", "")
+            .with_param("a", ParamValue::Float(1.0))
+            .with_param("b", ParamValue::Vec3([2.0, 3.0, 4.0]))
+            .with_param("c", ParamValue::Float(5.0))
+            .with_param("d", ParamValue::Vec2([6.0, 7.0]))
+            .with_param("e", ParamValue::Vec4([8.0, 9.0, 10.0, 11.0]));
+
+        // `c` fills the padding after the vec3, `d` starts at the next 8 bytes and `e` at 48
+        assert_eq!(
+            floats(&shader.uniforms(&[])),
+            [1.0, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 0.0, 0.0, 8.0, 9.0, 10.0, 11.0],
+        );
+    }
+
+    #[test]
+    fn uniforms_take_values_of_the_declared_type() {
+        let shader = MaterialShader::new("values", "")
+            .with_param("speed", ParamValue::Float(1.0))
+            .with_param("tint", ParamValue::Vec2([0.5, 0.5]));
+        let values = [
+            ("speed".to_string(), ParamValue::Float(3.0)),
+            ("tint".to_string(), ParamValue::Float(9.0)),
+            ("unknown".to_string(), ParamValue::Float(9.0)),
+        ];
+
+        // Padded to a whole vec4 like the struct in the shader
+        assert_eq!(floats(&shader.uniforms(&values)), [3.0, 0.0, 0.5, 0.5]);
+        assert_eq!(MaterialShader::new("empty", "").uniforms(&[]).len(), 16);
+    }
+
+    #[test]
+    fn wgsl_declares_params_and_textures_in_binding_order() {
+        let shader = MaterialShader::new("bindings", "// source")
+            .with_param("speed", ParamValue::Float(1.0))
+            .with_texture("albedo")
+            .with_linear_texture("noise");
+        let wgsl = shader.wgsl("// prelude");
+
+        assert!(wgsl.starts_with("// prelude\n"));
+        assert!(wgsl.ends_with("\n// source"));
+        assert!(wgsl.contains("struct MaterialParams {\n    speed: f32,\n}"));
+        assert!(wgsl.contains("@group(0) @binding(1)\nvar t_albedo: texture_2d<f32>;\n@group(0) @binding(2)\nvar s_albedo: sampler;"));
+        assert!(wgsl.contains("@group(0) @binding(3)\nvar t_noise: texture_2d<f32>;\n@group(0) @binding(4)\nvar s_noise: sampler;"));
+    }
+}
@@ -1,8 +1,46 @@
 pub mod window;
+mod animation;
+mod assets;
 mod camera;
+mod debug_lines;
+mod effects;
+mod environment;
+mod light;
+mod lod;
+mod material;
 mod model;
+mod post;
 mod resources;
+mod scene;
+mod settings;
+mod shadow;
+mod skybox;
 mod texture;
+mod ui;
 
 pub use model::*;
+pub use animation::{
+    AnimationClip, AnimationLayer, AnimationPlayer, Channel, Interpolation, Joint, Keyframes, NodeTransform, Skeleton,
+};
+pub use assets::{Asset, AssetHandle, AssetServer, Image, LoadState};
 pub use camera::*;
+pub use debug_lines::{DebugLines, DebugDraw};
+pub(crate) use debug_lines::DebugLinesRenderer;
+pub use environment::{Environment, Fog, FogMode, HeightFog};
+pub(crate) use environment::EnvironmentBuffers;
+pub use light::{Light, LightKind, LightHandle, LightManager};
+pub(crate) use light::LightBuffers;
+pub use effects::{ChromaticAberration, ColorGrading, Fxaa, Ssao, Vignette};
+pub use post::{Bloom, EffectHandle, EffectStage, PostEffect, PostProcessing, ToneMapper};
+pub(crate) use post::PostProcessor;
+pub use lod::LodGeneration;
+pub use material::{CustomMaterial, MaterialHandle, MaterialManager, MaterialShader, ParamValue, ShaderHandle};
+pub(crate) use material::MaterialRenderer;
+pub use scene::{InstanceHandle, LodSettings, ModelHandle, ModelInstance, RenderStats, SceneManager};
+pub(crate) use scene::{InstanceRaw, SceneRenderer};
+pub use settings::{AdapterPreference, Backend, PresentMode, RenderSettings};
+pub use shadow::ShadowSettings;
+pub use skybox::{Skybox, SkyboxSource};
+pub use texture::{AddressMode, FilterMode, SamplerSettings};
+pub use ui::DebugUi;
+pub(crate) use ui::UiRenderer;
@@ -1,6 +1,11 @@
 use std::ops::Range;
 
-use super::texture;
+use cgmath::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::animation::{AnimationClip, NodeTransform, Skeleton};
+use super::assets::AssetHandle;
+use super::texture::{self, SamplerSettings};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -14,6 +19,10 @@ pub struct ModelVertex {
     pub normal: [f32; 3],
     pub tangent: [f32; 3],
     pub bitangent: [f32; 3],
+    /// Indices into `Skeleton::joints` of the joints moving the vertex.
+    pub joints: [u32; 4],
+    /// Influence of each of `joints`; all zero for vertices that are not skinned.
+    pub weights: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -48,59 +57,342 @@ impl Vertex for ModelVertex {
                     shader_location: 4,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
+/// Scalar terms of a metallic-roughness material, multiplied with the matching textures.
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    /// Scales the tangent-space xy of the normal map.
+    pub normal_scale: f32,
+    /// How much of the occlusion map is applied, `0.0..=1.0`.
+    pub occlusion_strength: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        MaterialFactors {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: [0.0, 0.0, 0.0],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+        }
+    }
+}
+
+/// How a material's fragments are combined with what is already drawn.
+///
+/// Blended modes are drawn after all opaque meshes, sorted back to front, and do not
+/// write depth or cast shadows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlendMode {
+    /// Replaces the color behind it.
+    #[default]
+    Opaque,
+    /// Opaque, but fragments with a base color alpha below the cutoff are discarded, in
+    /// shadow maps too. Custom materials cast shadows without the cutout.
+    Cutout(f32),
+    /// Mixes with the color behind it by the base color alpha.
+    Alpha,
+    /// Like `Alpha`, for colors already multiplied by their alpha.
+    Premultiplied,
+    /// Adds to the color behind it, scaled by the base color alpha.
+    Additive,
+    /// Multiplies the color behind it.
+    Multiply,
+}
+
+impl BlendMode {
+    /// Whether the mode mixes with the color behind it and has to be drawn back to front.
+    pub fn is_transparent(self) -> bool {
+        !matches!(self, BlendMode::Opaque | BlendMode::Cutout(_))
+    }
+
+    /// Alpha below which the standard shader discards a fragment.
+    fn alpha_cutoff(self) -> f32 {
+        match self {
+            BlendMode::Cutout(cutoff) => cutoff,
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn blend_state(self) -> wgpu::BlendState {
+        let color = |src_factor, dst_factor| wgpu::BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: wgpu::BlendOperation::Add,
+        };
+        match self {
+            BlendMode::Opaque | BlendMode::Cutout(_) => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: color(wgpu::BlendFactor::SrcAlpha, wgpu::BlendFactor::One),
+                alpha: color(wgpu::BlendFactor::Zero, wgpu::BlendFactor::One),
+            },
+            BlendMode::Multiply => wgpu::BlendState {
+                color: color(wgpu::BlendFactor::Dst, wgpu::BlendFactor::Zero),
+                alpha: color(wgpu::BlendFactor::Zero, wgpu::BlendFactor::One),
+            },
+        }
+    }
+}
+
+/// Texture maps of a metallic-roughness material.
+///
+/// `metallic_roughness` follows the glTF convention: roughness in green, metallic in blue.
+pub struct MaterialTextures {
+    pub base_color: texture::Texture,
+    pub metallic_roughness: texture::Texture,
+    pub normal: texture::Texture,
+    pub occlusion: texture::Texture,
+    pub emissive: texture::Texture,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+}
+
+impl MaterialUniform {
+    fn new(factors: MaterialFactors, blend_mode: BlendMode) -> Self {
+        MaterialUniform {
+            base_color: factors.base_color,
+            emissive: factors.emissive,
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            normal_scale: factors.normal_scale,
+            occlusion_strength: factors.occlusion_strength,
+            alpha_cutoff: blend_mode.alpha_cutoff(),
+        }
+    }
+}
+
 pub struct Material {
     #[allow(unused)]
     pub name: String,
     #[allow(unused)]
-    pub diffuse_texture: texture::Texture,
-    #[allow(unused)]
-    pub normal_texture: texture::Texture,
+    pub textures: MaterialTextures,
+    pub factors: MaterialFactors,
+    pub blend_mode: BlendMode,
+    /// Blend mode the material was loaded with, restored when an override is cleared.
+    loaded_blend_mode: BlendMode,
+    factor_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl Material {
+    pub(crate) fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0),
+                sampler(1),
+                texture(2),
+                sampler(3),
+                texture(4),
+                sampler(5),
+                texture(6),
+                sampler(7),
+                texture(8),
+                sampler(9),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        })
+    }
+
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
+        blend_mode: BlendMode,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
+        let factor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(name),
+            contents: bytemuck::cast_slice(&[MaterialUniform::new(factors, blend_mode)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let bind_group = Self::create_bind_group(device, layout, name, &textures, &factor_buffer);
+
         Self {
             name: String::from(name),
-            diffuse_texture,
-            normal_texture,
+            textures,
+            factors,
+            blend_mode,
+            loaded_blend_mode: blend_mode,
+            factor_buffer,
             bind_group,
         }
     }
+
+    /// Draws the material with `blend_mode`, or with the mode it was loaded with for `None`.
+    pub(crate) fn set_blend_mode(&mut self, queue: &wgpu::Queue, blend_mode: Option<BlendMode>) {
+        let blend_mode = blend_mode.unwrap_or(self.loaded_blend_mode);
+        if blend_mode == self.blend_mode {
+            return;
+        }
+        self.blend_mode = blend_mode;
+        self.write_uniform(queue);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        textures: &MaterialTextures,
+        factor_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let maps = [
+            &textures.base_color,
+            &textures.normal,
+            &textures.metallic_roughness,
+            &textures.occlusion,
+            &textures.emissive,
+        ];
+        let mut entries = Vec::with_capacity(maps.len() * 2 + 1);
+        for (i, map) in maps.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&map.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32 * 2 + 1,
+                resource: wgpu::BindingResource::Sampler(&map.sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 10,
+            resource: factor_buffer.as_entire_binding(),
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some(name),
+        })
+    }
+
+    /// Samples all texture maps of the material with `settings`.
+    pub fn set_sampler(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, settings: &SamplerSettings) {
+        for map in [
+            &mut self.textures.base_color,
+            &mut self.textures.normal,
+            &mut self.textures.metallic_roughness,
+            &mut self.textures.occlusion,
+            &mut self.textures.emissive,
+        ] {
+            map.set_sampler(device, settings);
+        }
+        self.bind_group = Self::create_bind_group(device, layout, &self.name, &self.textures, &self.factor_buffer);
+    }
+
+    /// Changes the scalar factors of the material, e.g. to animate its color or emission.
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: MaterialFactors) {
+        self.factors = factors;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = MaterialUniform::new(self.factors, self.blend_mode);
+        queue.write_buffer(&self.factor_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+/// Axis-aligned box around a mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Bounds {
+    /// Smallest box containing every point; a zero-sized box at the origin when there are none.
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let mut points = points.into_iter().map(Vector3::from);
+        let Some(first) = points.next() else {
+            return Self { min: Vector3::new(0.0, 0.0, 0.0), max: Vector3::new(0.0, 0.0, 0.0) };
+        };
+
+        points.fold(Self { min: first, max: first }, |bounds, point| Self {
+            min: Vector3::new(bounds.min.x.min(point.x), bounds.min.y.min(point.y), bounds.min.z.min(point.z)),
+            max: Vector3::new(bounds.max.x.max(point.x), bounds.max.y.max(point.y), bounds.max.z.max(point.z)),
+        })
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half the size of the box along each axis.
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box around this one after `transform`, which may be larger than the transformed shape.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let center = (transform * self.center().extend(1.0)).truncate();
+        let extents = self.extents();
+        let radius = Vector3::new(
+            transform.x.x.abs() * extents.x + transform.y.x.abs() * extents.y + transform.z.x.abs() * extents.z,
+            transform.x.y.abs() * extents.x + transform.y.y.abs() * extents.y + transform.z.y.abs() * extents.z,
+            transform.x.z.abs() * extents.x + transform.y.z.abs() * extents.y + transform.z.z.abs() * extents.z,
+        );
+        Self { min: center - radius, max: center + radius }
+    }
 }
 
 pub struct Mesh {
@@ -110,11 +402,128 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Bounds of the vertices in model space, used for culling.
+    pub bounds: Bounds,
+}
+
+impl Mesh {
+    pub(crate) fn new(device: &wgpu::Device, name: &str, vertices: &[ModelVertex], indices: &[u32], material: usize) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            bounds: Bounds::from_points(vertices.iter().map(|vertex| vertex.position)),
+        }
+    }
+}
+
+/// A lower-detail version of a model's meshes.
+pub struct LodLevel {
+    /// Meshes drawn instead of `Model::meshes`, using the model's materials.
+    pub meshes: Vec<Mesh>,
+    /// The level is drawn once the model covers less than this fraction of the screen height.
+    pub screen_size: f32,
+}
+
+/// A node of the hierarchy a model was authored with.
+///
+/// Mesh vertices are already in model space, so `transform` is only needed to place
+/// things relative to a node, e.g. attaching an object to a bone or a wheel. Skinned
+/// meshes follow the joints of the model's skeleton instead.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Transform relative to the parent node.
+    pub transform: cgmath::Matrix4<f32>,
+    /// `transform` split into its parts, which animations replace.
+    pub rest: NodeTransform,
+    /// Indices into `Model::meshes` drawn at this node.
+    pub meshes: Vec<usize>,
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Nodes in parent-before-child order; models without a hierarchy have a single root.
+    pub nodes: Vec<Node>,
+    /// Lower-detail levels, from the largest `screen_size` to the smallest.
+    pub lods: Vec<LodLevel>,
+    /// Joints of the skinned meshes, if there are any.
+    pub skeleton: Option<Skeleton>,
+    pub animations: Vec<AnimationClip>,
+    /// Files the model was read from, which stay cached while it is alive.
+    pub(crate) sources: Vec<AssetHandle<Vec<u8>>>,
+}
+
+impl Model {
+    /// Indices of the nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(i, _)| i)
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&AnimationClip> {
+        self.animations.iter().find(|clip| clip.name == name)
+    }
+
+    /// Adds the meshes of `lod` as a level drawn below `screen_size`, taking over its materials.
+    pub fn add_lod(&mut self, lod: Model, screen_size: f32) {
+        let offset = self.materials.len();
+        self.materials.extend(lod.materials);
+        self.sources.extend(lod.sources);
+        let meshes = lod
+            .meshes
+            .into_iter()
+            .map(|mesh| Mesh { material: mesh.material + offset, ..mesh })
+            .collect();
+
+        self.lods.push(LodLevel { meshes, screen_size });
+        self.lods.sort_by(|a, b| b.screen_size.total_cmp(&a.screen_size));
+    }
+
+    /// Meshes of a level of detail, 0 being `meshes`; clamped to the lowest available level.
+    pub fn lod_meshes(&self, level: usize) -> &[Mesh] {
+        match level.min(self.lods.len()) {
+            0 => &self.meshes,
+            level => &self.lods[level - 1].meshes,
+        }
+    }
+
+    /// Bounds of all meshes of the full-detail level.
+    pub fn bounds(&self) -> Bounds {
+        Bounds::from_points(self.meshes.iter().flat_map(|mesh| [mesh.bounds.min.into(), mesh.bounds.max.into()]))
+    }
+
+    /// Transform of a node relative to the model origin.
+    pub fn node_world_transform(&self, index: usize) -> cgmath::Matrix4<f32> {
+        let node = &self.nodes[index];
+        match node.parent {
+            Some(parent) => self.node_world_transform(parent) * node.transform,
+            None => node.transform,
+        }
+    }
 }
 
 pub trait DrawModel<'a> {
@@ -0,0 +1,1049 @@
+use cgmath::{Matrix4, SquareMatrix};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+use wgpu::util::DeviceExt;
+use crate::physics::arena::*;
+use super::camera::Projection;
+use super::assets::AssetServer;
+use super::texture;
+
+/// Format of the offscreen target the scene is rendered into before post-processing.
+pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Format of the tone-mapped image the LDR effects run on, holding sRGB-encoded colors.
+const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const BLOOM_MIPS: u32 = 6;
+
+/// Curve mapping HDR scene colors onto the displayable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Colors are only clamped, so everything above 1.0 burns out to white.
+    None,
+    /// `c / (1 + c)`: simple and soft, but desaturates highlights.
+    Reinhard,
+    /// Fit of the ACES filmic curve, with punchy contrast and saturated colors.
+    #[default]
+    Aces,
+    /// The Uncharted 2 curve by John Hable, with a gentle toe and shoulder.
+    Filmic,
+}
+
+impl ToneMapper {
+    fn id(&self) -> u32 {
+        match self {
+            ToneMapper::None => 0,
+            ToneMapper::Reinhard => 1,
+            ToneMapper::Aces => 2,
+            ToneMapper::Filmic => 3,
+        }
+    }
+}
+
+/// Glow bleeding out of the brightest parts of the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    /// Brightness above which pixels start to glow.
+    pub threshold: f32,
+    /// Softness of the threshold, as a fraction of it; 0 is a hard cut.
+    pub knee: f32,
+    pub intensity: f32,
+    /// Spread of the blur, in texels of each bloom mip.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.1,
+            radius: 1.0,
+        }
+    }
+}
+
+impl Bloom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_knee(mut self, knee: f32) -> Self {
+        self.knee = knee;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+}
+
+/// Point in the frame at which a post effect runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EffectStage {
+    /// On the linear HDR scene, before bloom and tone mapping. Colors are unbounded.
+    Hdr,
+    /// On the tone-mapped, sRGB-encoded image, as it will appear on screen.
+    Ldr,
+}
+
+/// A full-screen pass run between rendering the scene and presenting the frame.
+///
+/// The WGSL returned by `shader` is appended to a prelude (`post_effect.wgsl`) that provides
+/// `VertexOutput { clip_position, uv }`, the previous pass as `t_source`, a linear clamping
+/// `s_linear` sampler, the scene depth as `t_depth`, the `frame` uniform with the projection,
+/// resolution and time, and helpers such as `sample_source`, `view_position` and `luminance`.
+/// With multisampling on, `t_depth` is a `texture_depth_multisampled_2d`, so read depth
+/// through `load_depth` rather than `textureLoad` directly.
+/// The effect defines `fs_main(in: VertexOutput) -> @location(0) vec4<f32>` and may declare
+/// its uniform at `@group(1) @binding(0)` and a texture at `@group(1) @binding(1)`.
+///
+/// Each enabled effect reads the output of the previous one and writes into the other of
+/// two ping-pong targets.
+pub trait PostEffect: Send + Sync {
+    /// Name used to find the effect in the stack and in error messages.
+    fn name(&self) -> &str;
+
+    fn shader(&self) -> &str;
+
+    fn stage(&self) -> EffectStage {
+        EffectStage::Ldr
+    }
+
+    /// Contents of the effect's uniform, uploaded every frame and padded to 16 bytes.
+    fn uniforms(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Image bound at `@group(1) @binding(1)`, relative to the `res` directory.
+    /// Without one, a single white texel is bound.
+    fn texture(&self) -> Option<&str> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EffectHandle(Index);
+
+struct EffectSlot {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+/// How the HDR frame is turned into the image on screen.
+///
+/// The scene is lit and rendered in linear HDR. At the end of the frame it runs through the
+/// HDR effects, is scaled by the exposure, optionally bloomed and tone mapped, and finally
+/// runs through the LDR effects before reaching the window. Effects run in stack order
+/// within their stage.
+pub struct PostProcessing {
+    tone_mapper: ToneMapper,
+    exposure: f32,
+    bloom: Option<Bloom>,
+    effects: Arena<EffectSlot>,
+    order: Vec<EffectHandle>,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::default(),
+            exposure: 1.0,
+            bloom: None,
+            effects: Arena::new(),
+            order: Vec::new(),
+        }
+    }
+}
+
+impl PostProcessing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_tone_mapper(&mut self, tone_mapper: ToneMapper) {
+        self.tone_mapper = tone_mapper;
+    }
+
+    pub fn tone_mapper(&self) -> ToneMapper {
+        self.tone_mapper
+    }
+
+    /// Sets the linear factor the scene is multiplied with before tone mapping.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure.max(0.0);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Sets the exposure in stops, where 0 leaves the scene unchanged and each stop doubles it.
+    pub fn set_exposure_ev(&mut self, ev: f32) {
+        self.exposure = ev.exp2();
+    }
+
+    pub fn set_bloom(&mut self, bloom: Bloom) {
+        self.bloom = Some(bloom);
+    }
+
+    pub fn clear_bloom(&mut self) {
+        self.bloom = None;
+    }
+
+    pub fn bloom(&self) -> Option<&Bloom> {
+        self.bloom.as_ref()
+    }
+
+    pub fn bloom_mut(&mut self) -> Option<&mut Bloom> {
+        self.bloom.as_mut()
+    }
+
+    /// Appends an enabled effect to the end of the stack.
+    ///
+    /// # Returns
+    ///
+    /// A handle used to reorder, toggle, modify or remove the effect later.
+    pub fn add_effect<E: PostEffect + 'static>(&mut self, effect: E) -> EffectHandle {
+        self.insert_effect(self.order.len(), effect)
+    }
+
+    /// Inserts an enabled effect at `index` in the stack, or at the end if `index` is past it.
+    pub fn insert_effect<E: PostEffect + 'static>(&mut self, index: usize, effect: E) -> EffectHandle {
+        let handle = EffectHandle(self.effects.insert(EffectSlot {
+            effect: Box::new(effect),
+            enabled: true,
+        }));
+        self.order.insert(index.min(self.order.len()), handle);
+        handle
+    }
+
+    /// Removes an effect, returning it if the handle was still valid.
+    pub fn remove_effect(&mut self, handle: EffectHandle) -> Option<Box<dyn PostEffect>> {
+        self.order.retain(|h| *h != handle);
+        self.effects.remove(handle.0).map(|slot| slot.effect)
+    }
+
+    /// Moves an effect to `index` in the stack, or to the end if `index` is past it.
+    /// Does nothing if the handle is no longer valid.
+    pub fn move_effect(&mut self, handle: EffectHandle, index: usize) {
+        if let Some(current) = self.order.iter().position(|h| *h == handle) {
+            self.order.remove(current);
+            self.order.insert(index.min(self.order.len()), handle);
+        }
+    }
+
+    /// Position of an effect in the stack.
+    pub fn effect_index(&self, handle: EffectHandle) -> Option<usize> {
+        self.order.iter().position(|h| *h == handle)
+    }
+
+    /// Enables or disables an effect. Does nothing if the handle is no longer valid.
+    pub fn set_effect_enabled(&mut self, handle: EffectHandle, enabled: bool) {
+        if let Some(slot) = self.effects.get_mut(handle.0) {
+            slot.enabled = enabled;
+        }
+    }
+
+    pub fn is_effect_enabled(&self, handle: EffectHandle) -> bool {
+        self.effects.get(handle.0).is_some_and(|slot| slot.enabled)
+    }
+
+    /// Flips an effect between enabled and disabled, returning whether it is now enabled.
+    pub fn toggle_effect(&mut self, handle: EffectHandle) -> bool {
+        let enabled = !self.is_effect_enabled(handle);
+        self.set_effect_enabled(handle, enabled);
+        self.is_effect_enabled(handle)
+    }
+
+    /// The effect behind `handle`, if it is still valid and of type `E`.
+    pub fn effect<E: PostEffect + 'static>(&self, handle: EffectHandle) -> Option<&E> {
+        self.effects.get(handle.0)?.effect.as_any().downcast_ref()
+    }
+
+    pub fn effect_mut<E: PostEffect + 'static>(&mut self, handle: EffectHandle) -> Option<&mut E> {
+        self.effects.get_mut(handle.0)?.effect.as_any_mut().downcast_mut()
+    }
+
+    /// First effect in the stack with the given name.
+    pub fn find_effect(&self, name: &str) -> Option<EffectHandle> {
+        self.effects().find(|(_, effect, _)| effect.name() == name).map(|(handle, _, _)| handle)
+    }
+
+    /// Every effect in stack order, with whether it is enabled.
+    pub fn effects(&self) -> impl Iterator<Item = (EffectHandle, &dyn PostEffect, bool)> {
+        self.order.iter().filter_map(|handle| {
+            self.effects.get(handle.0).map(|slot| (*handle, slot.effect.as_ref(), slot.enabled))
+        })
+    }
+
+    fn to_raw(&self, encode_srgb: bool) -> PostUniform {
+        let bloom = self.bloom.unwrap_or_default();
+        PostUniform {
+            exposure: self.exposure,
+            tone_mapper: self.tone_mapper.id(),
+            bloom_intensity: if self.bloom.is_some() { bloom.intensity } else { 0.0 },
+            bloom_radius: bloom.radius,
+            bloom_threshold: bloom.threshold,
+            bloom_knee: bloom.knee,
+            encode_srgb: encode_srgb as u32,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    exposure: f32,
+    tone_mapper: u32,
+    bloom_intensity: f32,
+    bloom_radius: f32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    encode_srgb: u32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrameUniform {
+    projection: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    resolution: [f32; 2],
+    texel_size: [f32; 2],
+    time: f32,
+    _padding: [f32; 3],
+}
+
+/// Layouts, buffers and the sampler that outlive the size-dependent targets.
+struct PostShared {
+    bloom_layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    frame_layout: wgpu::BindGroupLayout,
+    effect_layout: wgpu::BindGroupLayout,
+    /// Sample count of the scene depth buffer bound to the effects.
+    depth_samples: u32,
+    buffer: wgpu::Buffer,
+    frame_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+}
+
+/// Size-dependent textures: ping-pong HDR and LDR targets and the bloom mip chain.
+struct PostTargets {
+    width: u32,
+    height: u32,
+    hdr: [wgpu::TextureView; 2],
+    ldr: [wgpu::TextureView; 2],
+    /// One view per mip, the first at half the resolution of the frame.
+    bloom_mips: Vec<wgpu::TextureView>,
+    /// Bind each HDR target as the source of the bloom prefilter.
+    hdr_bind_groups: [wgpu::BindGroup; 2],
+    /// Bind each LDR target as the source of the final copy onto the surface.
+    ldr_bind_groups: [wgpu::BindGroup; 2],
+    /// Binds each bloom mip as the source of the pass writing the neighbouring mip.
+    bloom_bind_groups: Vec<wgpu::BindGroup>,
+    composite_bind_groups: [wgpu::BindGroup; 2],
+    /// Bind each target as the source of a post effect.
+    hdr_frame_bind_groups: [wgpu::BindGroup; 2],
+    ldr_frame_bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// GPU side of one `PostEffect`.
+struct EffectPipeline {
+    shader: String,
+    stage: EffectStage,
+    /// `None` when the shader failed to compile; the effect is skipped.
+    pipeline: Option<wgpu::RenderPipeline>,
+    buffer: wgpu::Buffer,
+    texture_name: Option<String>,
+    texture: Option<texture::Texture>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl EffectPipeline {
+    fn new(device: &wgpu::Device, shared: &PostShared, default_texture: &texture::Texture, effect: &dyn PostEffect) -> Self {
+        let format = match effect.stage() {
+            EffectStage::Hdr => HDR_FORMAT,
+            EffectStage::Ldr => LDR_FORMAT,
+        };
+        let prelude = include_str!("post_effect.wgsl");
+        let prelude = if shared.depth_samples > 1 {
+            prelude.replace("texture_depth_2d", "texture_depth_multisampled_2d")
+        } else {
+            prelude.to_string()
+        };
+        let source = format!("{}\n{}", prelude, effect.shader());
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Effect Pipeline Layout"),
+            bind_group_layouts: &[&shared.frame_layout, &shared.effect_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = catch_validation(device, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(effect.name()),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            create_post_pipeline(device, &layout, &shader, "vs_fullscreen", "fs_main", format, None)
+        });
+        let pipeline = match pipeline {
+            Ok(pipeline) => Some(pipeline),
+            Err(error) => {
+                log::error!("failed to build post effect {}: {}", effect.name(), error);
+                None
+            }
+        };
+
+        let buffer = create_effect_buffer(device, 16);
+        let bind_group = create_effect_bind_group(device, &shared.effect_layout, &buffer, &default_texture.view);
+
+        Self {
+            shader: effect.shader().to_string(),
+            stage: effect.stage(),
+            pipeline,
+            buffer,
+            texture_name: None,
+            texture: None,
+            bind_group,
+        }
+    }
+
+    fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shared: &PostShared,
+        default_texture: &texture::Texture,
+        effect: &dyn PostEffect,
+        assets: &AssetServer,
+    ) {
+        let mut rebind = false;
+
+        if effect.texture() != self.texture_name.as_deref() {
+            self.texture_name = effect.texture().map(str::to_string);
+            self.texture = effect.texture().and_then(|name| match load_effect_texture(device, queue, assets, name) {
+                Ok(texture) => Some(texture),
+                Err(error) => {
+                    log::error!("failed to load post effect texture {}: {}", name, error);
+                    None
+                }
+            });
+            rebind = true;
+        }
+
+        let mut uniforms = effect.uniforms();
+        uniforms.resize(uniforms.len().max(16).next_multiple_of(16), 0);
+        if uniforms.len() as u64 != self.buffer.size() {
+            self.buffer = create_effect_buffer(device, uniforms.len() as u64);
+            rebind = true;
+        }
+        queue.write_buffer(&self.buffer, 0, &uniforms);
+
+        if rebind {
+            let texture = self.texture.as_ref().unwrap_or(default_texture);
+            self.bind_group = create_effect_bind_group(device, &shared.effect_layout, &self.buffer, &texture.view);
+        }
+    }
+}
+
+/// Renders the HDR target onto the surface through the effect stack, bloom and tone mapping.
+pub(crate) struct PostProcessor {
+    shared: PostShared,
+    prefilter: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite_to_surface: wgpu::RenderPipeline,
+    composite_to_ldr: wgpu::RenderPipeline,
+    blit: wgpu::RenderPipeline,
+    targets: PostTargets,
+    default_texture: texture::Texture,
+    effects: HashMap<EffectHandle, EffectPipeline>,
+    /// Enabled effects that compiled, in stack order.
+    passes: Vec<(EffectHandle, EffectStage)>,
+    surface_srgb: bool,
+    bloom: bool,
+}
+
+impl PostProcessor {
+    fn create_bind_group_layout(device: &wgpu::Device, with_bloom: bool) -> wgpu::BindGroupLayout {
+        let mut entries = vec![
+            texture_entry(0),
+            sampler_entry(1),
+            uniform_entry(2),
+        ];
+        if with_bloom {
+            entries.push(texture_entry(3));
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(if with_bloom { "composite_bind_group_layout" } else { "bloom_bind_group_layout" }),
+        })
+    }
+
+    fn create_frame_bind_group_layout(device: &wgpu::Device, depth_samples: u32) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: depth_samples > 1,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                uniform_entry(3),
+            ],
+            label: Some("post_frame_bind_group_layout"),
+        })
+    }
+
+    fn create_effect_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(0), texture_entry(1)],
+            label: Some("post_effect_bind_group_layout"),
+        })
+    }
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface_format: wgpu::TextureFormat,
+        depth: &wgpu::TextureView,
+        depth_samples: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let surface_srgb = surface_format.is_srgb();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Processing Buffer"),
+            contents: bytemuck::cast_slice(&[PostProcessing::default().to_raw(!surface_srgb)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Frame Buffer"),
+            size: std::mem::size_of::<FrameUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Processing Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let shared = PostShared {
+            bloom_layout: Self::create_bind_group_layout(device, false),
+            composite_layout: Self::create_bind_group_layout(device, true),
+            frame_layout: Self::create_frame_bind_group_layout(device, depth_samples),
+            effect_layout: Self::create_effect_bind_group_layout(device),
+            depth_samples,
+            buffer,
+            frame_buffer,
+            sampler,
+        };
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Processing Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+
+        let bloom_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&shared.bloom_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Composite Pipeline Layout"),
+            bind_group_layouts: &[&shared.composite_layout],
+            push_constant_ranges: &[],
+        });
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+
+        let pipeline = |layout, entry_point, format, blend| {
+            create_post_pipeline(device, layout, &shader, "vs_fullscreen", entry_point, format, blend)
+        };
+        let prefilter = pipeline(&bloom_pipeline_layout, "fs_prefilter", HDR_FORMAT, None);
+        let downsample = pipeline(&bloom_pipeline_layout, "fs_downsample", HDR_FORMAT, None);
+        let upsample = pipeline(&bloom_pipeline_layout, "fs_upsample", HDR_FORMAT, Some(additive));
+        let composite_to_surface = pipeline(&composite_pipeline_layout, "fs_composite", surface_format, None);
+        let composite_to_ldr = pipeline(&composite_pipeline_layout, "fs_composite", LDR_FORMAT, None);
+        // The LDR targets already hold sRGB-encoded colors, which an sRGB surface would encode again
+        let blit_entry_point = if surface_srgb { "fs_blit_decode" } else { "fs_blit" };
+        let blit = pipeline(&bloom_pipeline_layout, blit_entry_point, surface_format, None);
+
+        let targets = Self::create_targets(device, &shared, depth, width, height);
+        let default_texture = texture::Texture::from_color(device, queue, [1.0; 4], Some("Post Effect Default Texture"), true)
+            .expect("failed to create default post effect texture");
+
+        Self {
+            shared,
+            prefilter,
+            downsample,
+            upsample,
+            composite_to_surface,
+            composite_to_ldr,
+            blit,
+            targets,
+            default_texture,
+            effects: HashMap::new(),
+            passes: Vec::new(),
+            surface_srgb,
+            bloom: false,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        shared: &PostShared,
+        depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> PostTargets {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        let target = |label, format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let hdr = [target("HDR Target", HDR_FORMAT), target("HDR Target", HDR_FORMAT)];
+        let ldr = [target("LDR Target", LDR_FORMAT), target("LDR Target", LDR_FORMAT)];
+
+        // Stop before the smallest mip would shrink below a couple of texels
+        let bloom_width = (width / 2).max(1);
+        let bloom_height = (height / 2).max(1);
+        let mip_count = (32 - bloom_width.min(bloom_height).leading_zeros()).saturating_sub(1).clamp(1, BLOOM_MIPS);
+        let bloom = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom Target"),
+            size: wgpu::Extent3d { width: bloom_width, height: bloom_height, depth_or_array_layers: 1 },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let bloom_mips = (0..mip_count)
+            .map(|mip| {
+                bloom.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom Mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let source_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &shared.bloom_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&shared.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: shared.buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("bloom_bind_group"),
+            })
+        };
+
+        let composite_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &shared.composite_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&shared.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: shared.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&bloom_mips[0]),
+                    },
+                ],
+                label: Some("composite_bind_group"),
+            })
+        };
+
+        let frame_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &shared.frame_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&shared.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(depth),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: shared.frame_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("post_frame_bind_group"),
+            })
+        };
+
+        PostTargets {
+            width,
+            height,
+            hdr_bind_groups: [source_bind_group(&hdr[0]), source_bind_group(&hdr[1])],
+            ldr_bind_groups: [source_bind_group(&ldr[0]), source_bind_group(&ldr[1])],
+            bloom_bind_groups: bloom_mips.iter().map(source_bind_group).collect(),
+            composite_bind_groups: [composite_bind_group(&hdr[0]), composite_bind_group(&hdr[1])],
+            hdr_frame_bind_groups: [frame_bind_group(&hdr[0]), frame_bind_group(&hdr[1])],
+            ldr_frame_bind_groups: [frame_bind_group(&ldr[0]), frame_bind_group(&ldr[1])],
+            hdr,
+            ldr,
+            bloom_mips,
+        }
+    }
+
+    /// Recreates the render targets for a new frame size and depth buffer. A depth buffer with
+    /// a different sample count also rebuilds the effect pipelines, which read it.
+    pub(crate) fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth: &wgpu::TextureView,
+        depth_samples: u32,
+        width: u32,
+        height: u32,
+    ) {
+        if depth_samples != self.shared.depth_samples {
+            self.shared.frame_layout = Self::create_frame_bind_group_layout(device, depth_samples);
+            self.shared.depth_samples = depth_samples;
+            self.effects.clear();
+            self.passes.clear();
+        }
+        self.targets = Self::create_targets(device, &self.shared, depth, width, height);
+    }
+
+    /// Loads the textures of the effects using any of `files` again on the next upload.
+    pub(crate) fn reload(&mut self, files: &HashSet<String>) {
+        for gpu in self.effects.values_mut() {
+            if gpu.texture_name.as_ref().is_some_and(|file_name| files.contains(file_name)) {
+                gpu.texture_name = None;
+            }
+        }
+    }
+
+    /// Uploads the settings and brings the GPU side of the effect stack up to date,
+    /// building pipelines for new or changed effects and dropping removed ones.
+    pub(crate) fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        post_processing: &PostProcessing,
+        projection: &Projection,
+        time: f32,
+        assets: &AssetServer,
+    ) {
+        self.effects.retain(|handle, _| post_processing.effects.contains(handle.0));
+        self.passes.clear();
+
+        for (handle, effect, enabled) in post_processing.effects() {
+            if !enabled {
+                continue;
+            }
+
+            let stale = self
+                .effects
+                .get(&handle)
+                .is_none_or(|gpu| gpu.shader != effect.shader() || gpu.stage != effect.stage());
+            if stale {
+                let gpu = EffectPipeline::new(device, &self.shared, &self.default_texture, effect);
+                self.effects.insert(handle, gpu);
+            }
+
+            let gpu = self.effects.get_mut(&handle).expect("effect pipeline was just created");
+            gpu.upload(device, queue, &self.shared, &self.default_texture, effect, assets);
+            if gpu.pipeline.is_some() {
+                self.passes.push((handle, effect.stage()));
+            }
+        }
+
+        let has_ldr_passes = self.passes.iter().any(|(_, stage)| *stage == EffectStage::Ldr);
+        let encode_srgb = !self.surface_srgb || has_ldr_passes;
+        self.bloom = post_processing.bloom.is_some();
+        queue.write_buffer(&self.shared.buffer, 0, bytemuck::cast_slice(&[post_processing.to_raw(encode_srgb)]));
+
+        let projection = projection.calc_matrix();
+        let frame = FrameUniform {
+            projection: projection.into(),
+            inverse_projection: projection.invert().unwrap_or(Matrix4::identity()).into(),
+            resolution: [self.targets.width as f32, self.targets.height as f32],
+            texel_size: [1.0 / self.targets.width as f32, 1.0 / self.targets.height as f32],
+            time,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.shared.frame_buffer, 0, bytemuck::cast_slice(&[frame]));
+    }
+
+    /// View the scene is rendered into.
+    pub(crate) fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.targets.hdr[0]
+    }
+
+    /// Runs the effect stack and the bloom chain, and writes the final frame to `output`.
+    pub(crate) fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let targets = &self.targets;
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        let stage_passes = |stage: EffectStage| {
+            self.passes
+                .iter()
+                .filter(move |(_, pass_stage)| *pass_stage == stage)
+                .filter_map(|(handle, _)| self.effects.get(handle))
+                .filter_map(|gpu| gpu.pipeline.as_ref().map(|pipeline| (pipeline, &gpu.bind_group)))
+        };
+
+        let mut hdr = 0;
+        for (pipeline, bind_group) in stage_passes(EffectStage::Hdr) {
+            run_pass(encoder, pipeline, &[&targets.hdr_frame_bind_groups[hdr], bind_group], &targets.hdr[1 - hdr], clear);
+            hdr = 1 - hdr;
+        }
+
+        if self.bloom {
+            run_pass(encoder, &self.prefilter, &[&targets.hdr_bind_groups[hdr]], &targets.bloom_mips[0], clear);
+            for mip in 1..targets.bloom_mips.len() {
+                run_pass(encoder, &self.downsample, &[&targets.bloom_bind_groups[mip - 1]], &targets.bloom_mips[mip], clear);
+            }
+            for mip in (1..targets.bloom_mips.len()).rev() {
+                run_pass(encoder, &self.upsample, &[&targets.bloom_bind_groups[mip]], &targets.bloom_mips[mip - 1], wgpu::LoadOp::Load);
+            }
+        }
+
+        if stage_passes(EffectStage::Ldr).next().is_none() {
+            run_pass(encoder, &self.composite_to_surface, &[&targets.composite_bind_groups[hdr]], output, clear);
+            return;
+        }
+
+        run_pass(encoder, &self.composite_to_ldr, &[&targets.composite_bind_groups[hdr]], &targets.ldr[0], clear);
+        let mut ldr = 0;
+        for (pipeline, bind_group) in stage_passes(EffectStage::Ldr) {
+            run_pass(encoder, pipeline, &[&targets.ldr_frame_bind_groups[ldr], bind_group], &targets.ldr[1 - ldr], clear);
+            ldr = 1 - ldr;
+        }
+        run_pass(encoder, &self.blit, &[&targets.ldr_bind_groups[ldr]], output, clear);
+    }
+}
+
+/// Runs `create` inside a validation error scope, returning the first error instead of
+/// handing it to the device's uncaptured error handler, which panics.
+pub(crate) fn catch_validation<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    // Native error scopes resolve immediately, so a single poll is enough
+    let mut error = std::pin::pin!(device.pop_error_scope());
+    match error.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Some(error)) => Err(error),
+        _ => Ok(value),
+    }
+}
+
+fn load_effect_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    assets: &AssetServer,
+    file_name: &str,
+) -> anyhow::Result<texture::Texture> {
+    let image = image::open(assets.resolve(file_name)?)?;
+    // Effect textures hold data such as lookup tables, so they are neither sRGB decoded nor
+    // filtered into mipmaps
+    texture::Texture::from_image(device, queue, &image, Some(file_name), true, false)
+}
+
+fn create_effect_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Post Effect Buffer"),
+        size,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_effect_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    texture: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(texture),
+            },
+        ],
+        label: Some("post_effect_bind_group"),
+    })
+}
+
+fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn run_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Post Processing Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        render_pass.set_bind_group(index as u32, bind_group, &[]);
+    }
+    render_pass.draw(0..3, 0..1);
+}
+
+fn create_post_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex_entry_point: &str,
+    fragment_entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(fragment_entry_point),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: vertex_entry_point,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend.unwrap_or(wgpu::BlendState::REPLACE)),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,192 @@
+// Post-processing of the HDR scene: bloom down/upsampling and the final tone-mapped composite.
+// The bloom passes only use bindings 0-2; the composite additionally reads the bloom at binding 3.
+
+struct PostParams {
+    exposure: f32,
+    tone_mapper: u32,
+    bloom_intensity: f32,
+    // upsample filter radius in source texels
+    bloom_radius: f32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    // 1 when the output is not an sRGB surface encoding on write
+    encode_srgb: u32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_post: sampler;
+@group(0) @binding(2)
+var<uniform> params: PostParams;
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+const TONE_MAPPER_NONE: u32 = 0u;
+const TONE_MAPPER_REINHARD: u32 = 1u;
+const TONE_MAPPER_ACES: u32 = 2u;
+const TONE_MAPPER_FILMIC: u32 = 3u;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the whole screen
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_post, uv, 0.0).rgb;
+}
+
+// 13-tap filter that halves the resolution without the flickering of a plain box filter
+fn downsample13(uv: vec2<f32>) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_source));
+
+    let a = sample_source(uv + t * vec2<f32>(-2.0, 2.0));
+    let b = sample_source(uv + t * vec2<f32>(0.0, 2.0));
+    let c = sample_source(uv + t * vec2<f32>(2.0, 2.0));
+    let d = sample_source(uv + t * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + t * vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv + t * vec2<f32>(-2.0, -2.0));
+    let h = sample_source(uv + t * vec2<f32>(0.0, -2.0));
+    let i = sample_source(uv + t * vec2<f32>(2.0, -2.0));
+    let j = sample_source(uv + t * vec2<f32>(-1.0, 1.0));
+    let k = sample_source(uv + t * vec2<f32>(1.0, 1.0));
+    let l = sample_source(uv + t * vec2<f32>(-1.0, -1.0));
+    let m = sample_source(uv + t * vec2<f32>(1.0, -1.0));
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// Keeps what is brighter than the threshold, with a quadratic knee instead of a hard cut
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample13(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+
+    let knee = params.bloom_threshold * params.bloom_knee + 0.00001;
+    var soft = clamp(brightness - params.bloom_threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    let contribution = max(soft, brightness - params.bloom_threshold) / max(brightness, 0.00001);
+
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample13(in.uv), 1.0);
+}
+
+// 3x3 tent filter, added onto the next larger mip by the blend state
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = params.bloom_radius / vec2<f32>(textureDimensions(t_source));
+
+    var color = sample_source(in.uv) * 4.0;
+    color += (sample_source(in.uv + vec2<f32>(0.0, t.y))
+        + sample_source(in.uv - vec2<f32>(0.0, t.y))
+        + sample_source(in.uv + vec2<f32>(t.x, 0.0))
+        + sample_source(in.uv - vec2<f32>(t.x, 0.0))) * 2.0;
+    color += sample_source(in.uv + t)
+        + sample_source(in.uv - t)
+        + sample_source(in.uv + vec2<f32>(t.x, -t.y))
+        + sample_source(in.uv + vec2<f32>(-t.x, t.y));
+
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return output * (a / b);
+}
+
+// John Hable's Uncharted 2 curve
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+fn filmic(color: vec3<f32>) -> vec3<f32> {
+    let white = 11.2;
+    return hable(color * 2.0) / hable(vec3<f32>(white));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let bloom = textureSampleLevel(t_bloom, s_post, in.uv, 0.0).rgb;
+    var color = (sample_source(in.uv) + bloom * params.bloom_intensity) * params.exposure;
+
+    switch params.tone_mapper {
+        case TONE_MAPPER_REINHARD: { color = reinhard(color); }
+        case TONE_MAPPER_ACES: { color = aces(color); }
+        case TONE_MAPPER_FILMIC: { color = filmic(color); }
+        default: {}
+    }
+    color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    if params.encode_srgb == 1u {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Final copy of the LDR targets, which already hold sRGB-encoded colors
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sample_source(in.uv), 1.0);
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// For sRGB surfaces, which encode on write
+@fragment
+fn fs_blit_decode(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(srgb_to_linear(sample_source(in.uv)), 1.0);
+}
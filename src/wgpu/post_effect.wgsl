@@ -0,0 +1,63 @@
+// Prelude shared by every post effect. The effect's own WGSL is appended below and defines
+// `fs_main(in: VertexOutput) -> @location(0) vec4<f32>`; group 1 holds its uniform (binding 0)
+// and optional texture (binding 1).
+
+struct Frame {
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    resolution: vec2<f32>,
+    texel_size: vec2<f32>,
+    // seconds since the game started
+    time: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+// Output of the previous pass
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+// Linear, clamp to edge
+@group(0) @binding(1)
+var s_linear: sampler;
+@group(0) @binding(2)
+var t_depth: texture_depth_2d;
+@group(0) @binding(3)
+var<uniform> frame: Frame;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+fn sample_source(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_source, s_linear, uv, 0.0);
+}
+
+// Raw depth buffer value; 1.0 where nothing was drawn
+fn load_depth(uv: vec2<f32>) -> f32 {
+    let texel = clamp(vec2<i32>(uv * frame.resolution), vec2<i32>(0), vec2<i32>(frame.resolution) - 1);
+    return textureLoad(t_depth, texel, 0);
+}
+
+// View space position of the surface visible at `uv`
+fn view_position(uv: vec2<f32>) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, load_depth(uv), 1.0);
+    let position = frame.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
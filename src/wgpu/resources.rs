@@ -1,39 +1,67 @@
 use std::io::{BufReader, Cursor};
 
-use std::env;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use wgpu::util::DeviceExt;
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3, Vector4};
 
+use super::animation::{AnimationClip, Channel, Interpolation, Joint, Keyframes, NodeTransform, Skeleton};
+use super::assets::{AssetHandle, AssetServer};
 use super::*;
 
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(&env::current_dir()?)
-        .join("res")
-        .join(file_name);
-    println!("{}", path.display());
-    let txt = std::fs::read_to_string(path)?;
-
-    Ok(txt)
+/// Reads the files of a model through the asset server, holding on to their handles so
+/// they stay cached while the model is alive.
+struct Sources<'a> {
+    assets: &'a AssetServer,
+    handles: Mutex<Vec<AssetHandle<Vec<u8>>>>,
+    /// Textures decoded for the model so far, by file name and whether they are normal maps,
+    /// so materials sharing an image decode and upload it once.
+    textures: Mutex<HashMap<(String, bool), texture::Texture>>,
 }
 
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(&env::current_dir()?)
-        .join("res")
-        .join(file_name);
-    println!("{}", path.display());
-    let data = std::fs::read(path)?;
+impl<'a> Sources<'a> {
+    fn new(assets: &'a AssetServer) -> Self {
+        Self {
+            assets,
+            handles: Mutex::new(Vec::new()),
+            textures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn read(&self, file_name: &str) -> anyhow::Result<Arc<Vec<u8>>> {
+        let (handle, bytes) = self.assets.load_async(file_name).await?;
+        let mut handles = self.handles.lock().unwrap();
+        if !handles.contains(&handle) {
+            handles.push(handle);
+        }
+        Ok(bytes)
+    }
+
+    async fn read_string(&self, file_name: &str) -> anyhow::Result<String> {
+        let bytes = self.read(file_name).await?;
+        Ok(std::str::from_utf8(&bytes).map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", file_name))?.to_string())
+    }
 
-    Ok(data)
+    fn into_handles(self) -> Vec<AssetHandle<Vec<u8>>> {
+        self.handles.into_inner().unwrap()
+    }
 }
 
-pub async fn load_texture(
+async fn load_texture(
     file_name: &str,
     is_normal_map: bool,
+    sources: &Sources<'_>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    let key = (file_name.to_string(), is_normal_map);
+    if let Some(texture) = sources.textures.lock().unwrap().get(&key) {
+        return Ok(texture.clone());
+    }
+    let data = sources.read(file_name).await?;
+    let texture = texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)?;
+    sources.textures.lock().unwrap().insert(key, texture.clone());
+    Ok(texture)
 }
 
 pub async fn load_texture_color(
@@ -45,26 +73,90 @@ pub async fn load_texture_color(
     texture::Texture::from_color(device, queue, color, Some("color"), is_normal_map)
 }
 
-pub async fn load_default_texture(
-    is_normal_map: bool,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
-    let data = load_binary("block.jpg").await?;
-    texture::Texture::from_bytes(device, queue, &data, "block.jpg", is_normal_map)
+/// Geometry of a mesh before it is uploaded, kept around to simplify it into LOD levels.
+pub(crate) struct MeshData {
+    pub name: String,
+    pub vertices: Vec<model::ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material: usize,
+}
+
+impl MeshData {
+    fn upload(&self, device: &wgpu::Device) -> model::Mesh {
+        model::Mesh::new(device, &self.name, &self.vertices, &self.indices, self.material)
+    }
 }
 
+/// Everything read from a model file, before the meshes are uploaded.
+struct ModelData {
+    meshes: Vec<MeshData>,
+    materials: Vec<model::Material>,
+    nodes: Vec<model::Node>,
+    skeleton: Option<Skeleton>,
+    animations: Vec<AnimationClip>,
+}
 
 pub async fn load_model(
     file_name: &str,
+    assets: &AssetServer,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
+    load_model_with_lods(file_name, None, assets, device, queue, layout).await
+}
+
+/// Like `load_model`, additionally simplifying the meshes into the LOD levels `generation` asks for.
+pub async fn load_model_with_lods(
+    file_name: &str,
+    generation: Option<&lod::LodGeneration>,
+    assets: &AssetServer,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let sources = Sources::new(assets);
+    let data = if matches!(extension.as_deref(), Some("gltf" | "glb")) {
+        load_gltf(file_name, &sources, device, queue, layout).await?
+    } else {
+        load_obj(file_name, &sources, device, queue, layout).await?
+    };
+
+    let meshes = data.meshes.iter().map(|mesh| mesh.upload(device)).collect();
+    let lods = match generation {
+        Some(generation) => lod::generate_levels(device, &data.meshes, generation),
+        None => Vec::new(),
+    };
+
+    Ok(model::Model {
+        meshes,
+        materials: data.materials,
+        nodes: data.nodes,
+        lods,
+        skeleton: data.skeleton,
+        animations: data.animations,
+        sources: sources.into_handles(),
+    })
+}
+
+async fn load_obj(
+    file_name: &str,
+    sources: &Sources<'_>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<ModelData> {
+
+    let obj_text = sources.read_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
+    // tobj only takes a loader error without a message, so the actual one is kept aside
+    let mtl_error = Mutex::new(None);
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
@@ -72,47 +164,29 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mtl_error = &mtl_error;
+            async move {
+                match sources.read_string(&p).await {
+                    Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                    Err(error) => {
+                        *mtl_error.lock().unwrap() = Some(error);
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
     )
         .await?;
+    let obj_materials = obj_materials.map_err(|error| match mtl_error.into_inner().unwrap() {
+        Some(error) => error.context(format!("failed to load the materials of {}", file_name)),
+        None => anyhow::Error::new(error),
+    })?;
 
     let mut materials = Vec::new();
 
-    for m in obj_materials? {
-        let diffuse_texture = if !m.diffuse_texture.is_empty() {
-            load_texture(&m.diffuse_texture, false, device, queue).await?
-        } else if m.diffuse.len() >= 3 {
-            load_texture_color(
-                [ m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0 ],
-                false,
-                device,
-                queue,
-            ).await?
-        } else {
-            load_default_texture(false, device, queue).await?
-        };
-
-        let normal_texture = if !m.normal_texture.is_empty() {
-            load_texture(&m.normal_texture, true, device, queue).await?
-        } else {
-            load_texture_color(
-                [0.0, 0.0, 0.0, 0.0],
-                false,
-                device,
-                queue,
-            ).await?
-        };
-
-        materials.push(model::Material::new(
-                device,
-                &m.name,
-                diffuse_texture,
-                normal_texture,
-                layout,
-        ));
+    for m in obj_materials {
+        materials.push(obj_material(&m, sources, device, queue, layout).await?);
     }
 
     let meshes = models.into_iter().map(|m| {
@@ -131,74 +205,713 @@ pub async fn load_model(
                 ],
                 tangent: [0.0; 3],
                 bitangent: [0.0; 3],
+                joints: [0; 4],
+                weights: [0.0; 4],
             })
         .collect::<Vec<_>>();
 
-        let indices = &m.mesh.indices;
-        let mut triangles_included = vec![0; vertices.len()];
+        compute_tangents(&mut vertices, &m.mesh.indices);
 
-        for c in indices.chunks(3) {
-            let v0 = vertices[c[0] as usize];
-            let v1 = vertices[c[1] as usize];
-            let v2 = vertices[c[2] as usize];
+        MeshData {
+            name: file_name.to_string(),
+            vertices,
+            indices: m.mesh.indices,
+            material: m.mesh.material_id.unwrap_or(0),
+        }
+    })
+    .collect::<Vec<_>>();
 
-            let pos0: cgmath::Vector3<_> = v0.position.into();
-            let pos1: cgmath::Vector3<_> = v1.position.into();
-            let pos2: cgmath::Vector3<_> = v2.position.into();
+    let nodes = vec![model::Node {
+        name: file_name.to_string(),
+        parent: None,
+        children: Vec::new(),
+        transform: Matrix4::identity(),
+        rest: NodeTransform::default(),
+        meshes: (0..meshes.len()).collect(),
+    }];
 
-            let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-            let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-            let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+    Ok(ModelData {
+        meshes,
+        materials,
+        nodes,
+        skeleton: None,
+        animations: Vec::new(),
+    })
+}
 
-            let delta_pos1 = pos1 - pos0;
-            let delta_pos2 = pos2 - pos0;
+/// Maps a Wavefront material onto metallic-roughness parameters.
+///
+/// The PBR extension statements (`Pr`, `Pm`, `Ke` and their `map_` variants) are used when
+/// present. Otherwise the material is treated as a dielectric whose roughness is derived
+/// from the specular exponent `Ns`.
+async fn obj_material(
+    m: &tobj::Material,
+    sources: &Sources<'_>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let mut factors = model::MaterialFactors::default();
+    if m.diffuse_texture.is_empty() {
+        factors.base_color = [m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0];
+    }
+    factors.base_color[3] = m.dissolve.clamp(0.0, 1.0);
+
+    // An alpha map is stored in the alpha channel of the base color
+    let base_color = if !m.dissolve_texture.is_empty() {
+        let color = match m.diffuse_texture.is_empty() {
+            true => None,
+            false => Some(image::load_from_memory(&sources.read(&m.diffuse_texture).await?)?),
+        };
+        let alpha = image::load_from_memory(&sources.read(&m.dissolve_texture).await?)?;
+        let combined = combine_alpha(color, alpha);
+        texture::Texture::from_image(device, queue, &combined, Some(&m.dissolve_texture), false, true)?
+    } else if !m.diffuse_texture.is_empty() {
+        load_texture(&m.diffuse_texture, false, sources, device, queue).await?
+    } else {
+        load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?
+    };
+
+    // `d` below one blends the material, an alpha map on its own only cuts out holes
+    let blend_mode = if factors.base_color[3] < 1.0 {
+        model::BlendMode::Alpha
+    } else if !m.dissolve_texture.is_empty() {
+        model::BlendMode::Cutout(0.5)
+    } else {
+        model::BlendMode::Opaque
+    };
 
-            let delta_uv1 = uv1 - uv0;
-            let delta_uv2 = uv2 - uv0;
+    let normal = if !m.normal_texture.is_empty() {
+        load_texture(&m.normal_texture, true, sources, device, queue).await?
+    } else {
+        load_texture_color([0.5, 0.5, 1.0, 1.0], true, device, queue).await?
+    };
 
-            let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+    factors.roughness = mtl_scalar(m, "Pr")
+        .unwrap_or_else(|| (2.0 / (m.shininess.max(0.0) + 2.0)).powf(0.25))
+        .clamp(0.04, 1.0);
+    factors.metallic = mtl_scalar(m, "Pm").unwrap_or(0.0).clamp(0.0, 1.0);
 
-            vertices[c[0] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-            vertices[c[1] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-            vertices[c[2] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-            vertices[c[0] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-            vertices[c[1] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-            vertices[c[2] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+    let roughness_map = mtl_map(m, "map_Pr");
+    let metallic_map = mtl_map(m, "map_Pm");
+    if roughness_map.is_some() {
+        factors.roughness = mtl_scalar(m, "Pr").unwrap_or(1.0);
+    }
+    if metallic_map.is_some() {
+        factors.metallic = mtl_scalar(m, "Pm").unwrap_or(1.0);
+    }
+    let metallic_roughness = if roughness_map.is_some() || metallic_map.is_some() {
+        let roughness = match roughness_map {
+            Some(file) => Some(image::load_from_memory(&sources.read(file).await?)?),
+            None => None,
+        };
+        let metallic = match metallic_map {
+            Some(file) => Some(image::load_from_memory(&sources.read(file).await?)?),
+            None => None,
+        };
+        let combined = combine_metallic_roughness(roughness, metallic);
+        texture::Texture::from_image(device, queue, &combined, Some("metallic_roughness"), true, true)?
+    } else {
+        load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?
+    };
 
-            triangles_included[c[0] as usize] += 1;
-            triangles_included[c[1] as usize] += 1;
-            triangles_included[c[2] as usize] += 1;
+    let emissive = match mtl_map(m, "map_Ke") {
+        Some(file) => {
+            factors.emissive = mtl_color(m, "Ke").unwrap_or([1.0, 1.0, 1.0]);
+            load_texture(file, false, sources, device, queue).await?
+        }
+        None => {
+            factors.emissive = mtl_color(m, "Ke").unwrap_or([0.0, 0.0, 0.0]);
+            load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?
         }
+    };
+
+    let occlusion = load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?;
+
+    Ok(model::Material::new(
+        device,
+        &m.name,
+        model::MaterialTextures {
+            base_color,
+            metallic_roughness,
+            normal,
+            occlusion,
+            emissive,
+        },
+        factors,
+        blend_mode,
+        layout,
+    ))
+}
+
+fn mtl_scalar(m: &tobj::Material, key: &str) -> Option<f32> {
+    m.unknown_param.get(key)?.split_whitespace().next()?.parse().ok()
+}
+
+fn mtl_color(m: &tobj::Material, key: &str) -> Option<[f32; 3]> {
+    let values: Vec<f32> = m
+        .unknown_param
+        .get(key)?
+        .split_whitespace()
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    match values.as_slice() {
+        [r, g, b, ..] => Some([*r, *g, *b]),
+        [v] => Some([*v, *v, *v]),
+        _ => None,
+    }
+}
+
+/// File name of a texture statement, skipping any options in front of it.
+fn mtl_map<'a>(m: &'a tobj::Material, key: &str) -> Option<&'a str> {
+    m.unknown_param.get(key)?.split_whitespace().last()
+}
+
+/// Packs separate roughness and metallic images into the green and blue channels.
+fn combine_metallic_roughness(roughness: Option<image::DynamicImage>, metallic: Option<image::DynamicImage>) -> image::DynamicImage {
+    let (width, height) = roughness
+        .as_ref()
+        .or(metallic.as_ref())
+        .map(|image| (image.width(), image.height()))
+        .unwrap_or((1, 1));
+    let channel = |image: Option<image::DynamicImage>| {
+        image.map(|image| {
+            image
+                .resize_exact(width, height, image::imageops::FilterType::Triangle)
+                .to_luma8()
+        })
+    };
+    let roughness = channel(roughness);
+    let metallic = channel(metallic);
 
-        for (i, n) in triangles_included.into_iter().enumerate() {
-            let denom = 1.0 / n as f32;
-            let v = &mut vertices[i];
-            v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-            v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(width, height, |x, y| {
+        let g = roughness.as_ref().map(|image| image.get_pixel(x, y).0[0]).unwrap_or(255);
+        let b = metallic.as_ref().map(|image| image.get_pixel(x, y).0[0]).unwrap_or(255);
+        image::Rgba([255, g, b, 255])
+    }))
+}
+
+/// Puts the alpha channel of `alpha`, or its luminance if it has none, into the alpha
+/// channel of `color`, or of plain white.
+fn combine_alpha(color: Option<image::DynamicImage>, alpha: image::DynamicImage) -> image::DynamicImage {
+    let (width, height) = color
+        .as_ref()
+        .map(|image| (image.width(), image.height()))
+        .unwrap_or((alpha.width(), alpha.height()));
+    // Exporters often point `map_d` at the RGBA diffuse image; grayscale maps store the
+    // alpha as brightness
+    let alpha = alpha.resize_exact(width, height, image::imageops::FilterType::Triangle);
+    let alpha: image::GrayImage = match alpha.color().has_alpha() {
+        true => {
+            let rgba = alpha.to_rgba8();
+            image::GrayImage::from_fn(width, height, |x, y| image::Luma([rgba.get_pixel(x, y).0[3]]))
         }
+        false => alpha.to_luma8(),
+    };
+    let mut color = color
+        .map(|image| image.to_rgba8())
+        .unwrap_or_else(|| image::RgbaImage::from_pixel(width, height, image::Rgba([255, 255, 255, 255])));
+
+    for (x, y, pixel) in color.enumerate_pixels_mut() {
+        pixel.0[3] = alpha.get_pixel(x, y).0[0];
+    }
+    image::DynamicImage::ImageRgba8(color)
+}
+
+/// Fills in `tangent` and `bitangent` of every vertex by averaging them over the
+/// triangles that share it.
+fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        vertices[c[0] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent = (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent = (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let denom = 1.0 / n as f32;
+        let v = &mut vertices[i];
+        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    }
+}
+
+/// Loads a glTF 2.0 (`.gltf` with external or embedded buffers, or binary `.glb`) model.
+///
+/// Every primitive becomes a `Mesh` whose vertices are baked into model space using the
+/// world transform of the node that references it; the node hierarchy itself is kept in
+/// `Model::nodes`. Skinned primitives are left in bind space, and the joints of all skins
+/// are merged into one `Skeleton`. Node animations become `AnimationClip`s; morph target
+/// animations are skipped.
+async fn load_gltf(
+    file_name: &str,
+    sources: &Sources<'_>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<ModelData> {
+    // External buffers and images are read by the importer, relative to the file
+    let path = sources.assets.resolve(file_name)?;
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&sources.read(file_name).await?)?;
+    let buffers = gltf::import_buffers(&document, path.parent(), blob)?;
+    let images = gltf::import_images(&document, path.parent(), &buffers)?;
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Vertex Buffer", file_name)),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+    // Images used by several textures or materials are uploaded once
+    let mut uploaded: HashMap<(usize, bool), texture::Texture> = HashMap::new();
+    let mut materials = Vec::new();
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let mut load = |texture: gltf::Texture, is_normal_map| -> anyhow::Result<texture::Texture> {
+            let key = (texture.source().index(), is_normal_map);
+            let mut map = match uploaded.get(&key) {
+                Some(map) => map.clone(),
+                None => {
+                    let map = gltf_texture(&images[key.0], is_normal_map, device, queue)?;
+                    uploaded.insert(key, map.clone());
+                    map
+                }
+            };
+            map.set_sampler(device, &gltf_sampler(&texture.sampler()));
+            Ok(map)
+        };
+
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => load(info.texture(), false)?,
+            None => load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?,
+        };
+        let metallic_roughness = match pbr.metallic_roughness_texture() {
+            Some(info) => load(info.texture(), true)?,
+            None => load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?,
+        };
+        let normal = match material.normal_texture() {
+            Some(info) => load(info.texture(), true)?,
+            None => load_texture_color([0.5, 0.5, 1.0, 1.0], true, device, queue).await?,
+        };
+        let occlusion = match material.occlusion_texture() {
+            Some(info) => load(info.texture(), true)?,
+            None => load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?,
+        };
+        let emissive = match material.emissive_texture() {
+            Some(info) => load(info.texture(), false)?,
+            None => load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?,
+        };
+
+        let factors = model::MaterialFactors {
+            base_color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: material.emissive_factor(),
+            normal_scale: material.normal_texture().map(|info| info.scale()).unwrap_or(1.0),
+            occlusion_strength: material.occlusion_texture().map(|info| info.strength()).unwrap_or(1.0),
+        };
+        let blend_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => model::BlendMode::Opaque,
+            gltf::material::AlphaMode::Mask => model::BlendMode::Cutout(material.alpha_cutoff().unwrap_or(0.5)),
+            gltf::material::AlphaMode::Blend => model::BlendMode::Alpha,
+        };
+
+        materials.push(model::Material::new(
+            device,
+            material.name().unwrap_or(file_name),
+            model::MaterialTextures {
+                base_color,
+                metallic_roughness,
+                normal,
+                occlusion,
+                emissive,
+            },
+            factors,
+            blend_mode,
+            layout,
+        ));
+    }
+
+    // Primitives without a material use the glTF default: white, fully metallic and rough
+    let default_material = materials.len();
+    materials.push(model::Material::new(
+        device,
+        "default",
+        model::MaterialTextures {
+            base_color: load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?,
+            metallic_roughness: load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?,
+            normal: load_texture_color([0.5, 0.5, 1.0, 1.0], true, device, queue).await?,
+            occlusion: load_texture_color([1.0, 1.0, 1.0, 1.0], true, device, queue).await?,
+            emissive: load_texture_color([1.0, 1.0, 1.0, 1.0], false, device, queue).await?,
+        },
+        model::MaterialFactors {
+            metallic: 1.0,
+            roughness: 1.0,
+            ..Default::default()
+        },
+        model::BlendMode::Opaque,
+        layout,
+    ));
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow::anyhow!("{} contains no scene", file_name))?;
+
+    // Every skin's joints follow those of the skins before it in the merged skeleton
+    let mut joint_offsets = Vec::new();
+    let mut joint_count = 0;
+    for skin in document.skins() {
+        joint_offsets.push(joint_count);
+        joint_count += skin.joints().count() as u32;
+    }
+
+    let mut meshes = Vec::new();
+    let mut nodes = Vec::new();
+    let mut node_indices = HashMap::new();
+    let mut stack: Vec<(gltf::Node, Option<usize>)> = scene.nodes().map(|node| (node, None)).collect();
+    stack.reverse();
+
+    while let Some((node, parent)) = stack.pop() {
+        let index = nodes.len();
+        node_indices.insert(node.index(), index);
+        let transform = Matrix4::from(node.transform().matrix());
+        let world = match parent {
+            Some(parent) => node_world(&nodes, parent) * transform,
+            None => transform,
+        };
+
+        // The joints place skinned meshes, so the transform of their own node is ignored
+        let joint_offset = node.skin().map(|skin| joint_offsets[skin.index()]);
+        let mesh_transform = if joint_offset.is_some() { Matrix4::identity() } else { world };
+
+        let mut node_meshes = Vec::new();
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let name = mesh.name().or(node.name()).unwrap_or(file_name);
+                let material = primitive.material().index().unwrap_or(default_material);
+                if let Some(mesh) = gltf_mesh(&primitive, &buffers, mesh_transform, joint_offset, name, material) {
+                    node_meshes.push(meshes.len());
+                    meshes.push(mesh);
+                }
+            }
+        }
+
+        if let Some(parent) = parent {
+            nodes[parent].children.push(index);
+        }
+        nodes.push(model::Node {
+            name: node.name().map(str::to_string).unwrap_or_else(|| format!("node{}", node.index())),
+            parent,
+            children: Vec::new(),
+            transform,
+            rest: gltf_node_transform(&node),
+            meshes: node_meshes,
         });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Index Buffer", file_name)),
-            contents: bytemuck::cast_slice(&m.mesh.indices),
-            usage: wgpu::BufferUsages::INDEX,
+
+        for child in node.children().collect::<Vec<_>>().into_iter().rev() {
+            stack.push((child, Some(index)));
+        }
+    }
+
+    let mut joints = Vec::new();
+    for skin in document.skins() {
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        let inverse_bind_matrices: Vec<Matrix4<f32>> = reader
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(Matrix4::from).collect())
+            .unwrap_or_default();
+        for (i, joint) in skin.joints().enumerate() {
+            let node = *node_indices
+                .get(&joint.index())
+                .ok_or_else(|| anyhow::anyhow!("joint {} of {} is not in the scene", joint.index(), file_name))?;
+            joints.push(Joint {
+                node,
+                inverse_bind_matrix: inverse_bind_matrices.get(i).copied().unwrap_or_else(Matrix4::identity),
+            });
+        }
+    }
+    let skeleton = (!joints.is_empty()).then_some(Skeleton { joints });
+
+    let animations = document
+        .animations()
+        .map(|animation| gltf_animation(&animation, &buffers, &node_indices))
+        .collect();
+
+    Ok(ModelData {
+        meshes,
+        materials,
+        nodes,
+        skeleton,
+        animations,
+    })
+}
+
+fn gltf_node_transform(node: &gltf::Node) -> NodeTransform {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    NodeTransform {
+        translation: translation.into(),
+        rotation: Quaternion::new(w, x, y, z),
+        scale: scale.into(),
+    }
+}
+
+fn gltf_animation(
+    animation: &gltf::Animation,
+    buffers: &[gltf::buffer::Data],
+    node_indices: &HashMap<usize, usize>,
+) -> AnimationClip {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let Some(&node) = node_indices.get(&channel.target().node().index()) else {
+            continue;
+        };
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(times) = reader.read_inputs() else {
+            continue;
+        };
+        let keyframes = match reader.read_outputs() {
+            Some(ReadOutputs::Translations(values)) => Keyframes::Translation(values.map(Vector3::from).collect()),
+            Some(ReadOutputs::Rotations(values)) => {
+                Keyframes::Rotation(values.into_f32().map(|[x, y, z, w]| Quaternion::new(w, x, y, z)).collect())
+            }
+            Some(ReadOutputs::Scales(values)) => Keyframes::Scale(values.map(Vector3::from).collect()),
+            _ => continue,
+        };
+        let interpolation = match channel.sampler().interpolation() {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        };
+
+        channels.push(Channel {
+            node,
+            times: times.collect(),
+            keyframes,
+            interpolation,
         });
+    }
 
-        model::Mesh {
-            name: file_name.to_string(),
-            vertex_buffer,
-            index_buffer,
-            num_elements: m.mesh.indices.len() as u32,
-            material: m.mesh.material_id.unwrap_or(0),
+    AnimationClip {
+        name: animation.name().map(str::to_string).unwrap_or_else(|| format!("animation{}", animation.index())),
+        duration: channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max),
+        channels,
+    }
+}
+
+fn node_world(nodes: &[model::Node], index: usize) -> Matrix4<f32> {
+    let node = &nodes[index];
+    match node.parent {
+        Some(parent) => node_world(nodes, parent) * node.transform,
+        None => node.transform,
+    }
+}
+
+fn gltf_mesh(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    world: Matrix4<f32>,
+    joint_offset: Option<u32>,
+    name: &str,
+    material: usize,
+) -> Option<MeshData> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = reader.read_positions()?.collect();
+
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let tex_coords: Vec<[f32; 2]> = reader
+        .read_tex_coords(0)
+        .map(|coords| coords.into_f32().collect())
+        .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+    let normals: Vec<[f32; 3]> = reader
+        .read_normals()
+        .map(|normals| normals.collect())
+        .unwrap_or_else(|| flat_normals(&positions, &indices));
+    let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|tangents| tangents.collect());
+    let joints: Vec<[u32; 4]> = match (joint_offset, reader.read_joints(0)) {
+        (Some(offset), Some(joints)) => joints.into_u16().map(|joint| joint.map(|j| j as u32 + offset)).collect(),
+        _ => vec![[0; 4]; positions.len()],
+    };
+    let weights: Vec<[f32; 4]> = match (joint_offset, reader.read_weights(0)) {
+        (Some(_), Some(weights)) => weights.into_f32().collect(),
+        _ => vec![[0.0; 4]; positions.len()],
+    };
+
+    let mut vertices = (0..positions.len())
+        .map(|i| model::ModelVertex {
+            position: positions[i],
+            tex_coords: tex_coords[i],
+            normal: normals[i],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+            joints: joints[i],
+            weights: weights[i],
+        })
+        .collect::<Vec<_>>();
+
+    match &tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                let normal = Vector3::from(vertex.normal);
+                let t = Vector3::new(tangent[0], tangent[1], tangent[2]);
+                vertex.tangent = t.into();
+                vertex.bitangent = (normal.cross(t) * tangent[3]).into();
+            }
+        }
+        None => compute_tangents(&mut vertices, &indices),
+    }
+
+    // Bake the node transform; normals use the inverse transpose to survive non-uniform scale
+    let linear = Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate());
+    let normal_matrix = linear.invert().map(|m| m.transpose()).unwrap_or(linear);
+    for vertex in &mut vertices {
+        let position = world * Vector4::new(vertex.position[0], vertex.position[1], vertex.position[2], 1.0);
+        vertex.position = position.truncate().into();
+        vertex.normal = normalize_or_zero(normal_matrix * Vector3::from(vertex.normal)).into();
+        vertex.tangent = normalize_or_zero(linear * Vector3::from(vertex.tangent)).into();
+        vertex.bitangent = normalize_or_zero(linear * Vector3::from(vertex.bitangent)).into();
+    }
+
+    // Mirroring transforms flip the winding order
+    if linear.determinant() < 0.0 {
+        for triangle in indices.chunks_mut(3) {
+            if triangle.len() == 3 {
+                triangle.swap(1, 2);
+            }
         }
+    }
+
+    Some(MeshData {
+        name: name.to_string(),
+        vertices,
+        indices,
+        material,
     })
-    .collect::<Vec<_>>();
+}
+
+fn normalize_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// Per-vertex normals averaged from the faces around each vertex, for primitives without normals.
+fn flat_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    for c in indices.chunks_exact(3) {
+        let p0 = Vector3::from(positions[c[0] as usize]);
+        let p1 = Vector3::from(positions[c[1] as usize]);
+        let p2 = Vector3::from(positions[c[2] as usize]);
+        let face = (p1 - p0).cross(p2 - p0);
+        for &i in c {
+            normals[i as usize] += face;
+        }
+    }
+    normals.into_iter().map(|n| normalize_or_zero(n).into()).collect()
+}
+
+/// Sampler a glTF texture asks for. Filters the file leaves unset are linear, and mipmaps
+/// are used even with the filters that ask for none.
+fn gltf_sampler(sampler: &gltf::texture::Sampler) -> texture::SamplerSettings {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use texture::{AddressMode, FilterMode};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::Repeat => AddressMode::Repeat,
+        WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+        WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+    };
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => (FilterMode::Nearest, FilterMode::Nearest),
+        Some(MinFilter::NearestMipmapLinear) => (FilterMode::Nearest, FilterMode::Linear),
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => (FilterMode::Linear, FilterMode::Nearest),
+        Some(MinFilter::LinearMipmapLinear) | None => (FilterMode::Linear, FilterMode::Linear),
+    };
+    texture::SamplerSettings {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => FilterMode::Linear,
+        },
+        min_filter,
+        mipmap_filter,
+        ..Default::default()
+    }
+}
+
+/// Uploads an image decoded by the glTF importer, whatever its channel layout.
+fn gltf_texture(
+    data: &gltf::image::Data,
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    use gltf::image::Format;
+
+    let pixels = data.width as usize * data.height as usize;
+    let rgba: Vec<u8> = match data.format {
+        Format::R8G8B8A8 => data.pixels.clone(),
+        Format::R8G8B8 => data.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        Format::R8G8 => data.pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+        Format::R8 => data.pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        Format::R16G16B16A16 => data.pixels.chunks_exact(2).map(|c| c[1]).collect(),
+        Format::R16G16B16 => data.pixels.chunks_exact(6).flat_map(|p| [p[1], p[3], p[5], 255]).collect(),
+        Format::R16G16 => data.pixels.chunks_exact(4).flat_map(|p| [p[1], p[3], 0, 255]).collect(),
+        Format::R16 => data.pixels.chunks_exact(2).flat_map(|p| [p[1], p[1], p[1], 255]).collect(),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            let channels = if data.format == Format::R32G32B32FLOAT { 3 } else { 4 };
+            data.pixels
+                .chunks_exact(4 * channels)
+                .flat_map(|p| {
+                    let channel = |i: usize| {
+                        let value = f32::from_le_bytes([p[i * 4], p[i * 4 + 1], p[i * 4 + 2], p[i * 4 + 3]]);
+                        (value.clamp(0.0, 1.0) * 255.0) as u8
+                    };
+                    let alpha = if channels == 4 { channel(3) } else { 255 };
+                    [channel(0), channel(1), channel(2), alpha]
+                })
+                .collect()
+        }
+    };
+    anyhow::ensure!(rgba.len() == pixels * 4, "unexpected glTF image size");
 
-    Ok(model::Model { meshes, materials })
+    let image = image::RgbaImage::from_raw(data.width, data.height, rgba)
+        .ok_or_else(|| anyhow::anyhow!("invalid glTF image"))?;
+    texture::Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(image), None, is_normal_map, true)
 }
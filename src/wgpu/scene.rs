@@ -0,0 +1,1054 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Matrix4, One, Quaternion, Vector3, Vector4};
+use tokio::sync::oneshot;
+
+use crate::physics::arena::{Arena, Index};
+use super::animation::{self, AnimationPlayer};
+use super::assets::{AssetServer, LoadState};
+use super::camera::{Camera, Projection};
+use super::lod::{self, LodGeneration};
+use super::material::{MaterialHandle, MaterialRenderer};
+use super::model::{self, BlendMode, DrawModel};
+use super::resources;
+use super::texture::SamplerSettings;
+use super::window::BlendPipelines;
+
+/// A model file added to the scene, shared by all of its instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelHandle(Index);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceHandle(Index);
+
+/// One placement of a model in the world.
+#[derive(Clone, Debug)]
+pub struct ModelInstance {
+    pub model: ModelHandle,
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    /// Hidden instances are neither drawn nor cast shadows.
+    pub visible: bool,
+    /// Poses the skinned meshes of the model; without one they show the bind pose.
+    pub animation: Option<AnimationPlayer>,
+}
+
+impl ModelInstance {
+    pub fn new(model: ModelHandle) -> Self {
+        Self {
+            model,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            visible: true,
+            animation: None,
+        }
+    }
+
+    pub fn with_position(mut self, position: Vector3<f32>) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_animation(mut self, animation: AnimationPlayer) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    pub fn transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    fn to_raw(&self, joint_offset: u32) -> InstanceRaw {
+        InstanceRaw {
+            model: self.transform().into(),
+            normal: Matrix3::from(self.rotation).into(),
+            joint_offset,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    /// Index of the instance's first skinning matrix in the joint buffer.
+    joint_offset: u32,
+}
+
+impl model::Vertex for InstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+/// How a model switches to lower detail as it gets smaller on screen.
+///
+/// Screen sizes are the fraction of the screen height covered by the model's bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodSettings {
+    /// Hand-made lower-detail files from the `res` directory, each with the screen size
+    /// below which it is drawn.
+    pub files: Vec<(String, f32)>,
+    /// Levels simplified from the model itself when it is loaded.
+    pub generate: Option<LodGeneration>,
+    /// How far past a threshold, as a fraction of it, the screen size has to move before
+    /// the level changes; stops models near a threshold from popping back and forth.
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            generate: None,
+            hysteresis: 0.1,
+        }
+    }
+}
+
+impl LodSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(mut self, file_name: &str, screen_size: f32) -> Self {
+        self.files.push((file_name.to_string(), screen_size));
+        self
+    }
+
+    pub fn with_generated(mut self, generation: LodGeneration) -> Self {
+        self.generate = Some(generation);
+        self
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+}
+
+/// Settings replacing those a model's materials were loaded with, for all of them or by
+/// material name.
+#[derive(Clone, Debug, PartialEq)]
+struct MaterialOverrides<T> {
+    all: Option<T>,
+    materials: HashMap<String, T>,
+}
+
+impl<T> Default for MaterialOverrides<T> {
+    fn default() -> Self {
+        Self {
+            all: None,
+            materials: HashMap::new(),
+        }
+    }
+}
+
+impl<T> MaterialOverrides<T> {
+    fn get(&self, material: &str) -> Option<&T> {
+        self.materials.get(material).or(self.all.as_ref())
+    }
+}
+
+struct SceneModel {
+    file_name: String,
+    lods: LodSettings,
+    samplers: MaterialOverrides<SamplerSettings>,
+    blend_modes: MaterialOverrides<BlendMode>,
+    custom_materials: MaterialOverrides<MaterialHandle>,
+    state: LoadState,
+}
+
+/// What the renderer drew in the last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Draw calls for scene meshes in the main pass.
+    pub draw_calls: u32,
+    /// Draw calls for scene meshes across all shadow map layers.
+    pub shadow_draw_calls: u32,
+    /// Triangles of scene meshes drawn in the main pass.
+    pub triangles: u64,
+    /// Instances with at least one mesh inside the view.
+    pub visible_instances: u32,
+    /// Instances skipped because none of their meshes are inside the view.
+    pub culled_instances: u32,
+}
+
+pub struct SceneManager {
+    models: Arena<SceneModel>,
+    instances: Arena<ModelInstance>,
+    stats: RenderStats,
+}
+
+impl SceneManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            models: Arena::new(),
+            instances: Arena::new(),
+            stats: RenderStats::default(),
+        }
+    }
+
+    /// Adds an OBJ, glTF or GLB file from the asset roots to the scene.
+    ///
+    /// The renderer starts loading the file in the background on the next frame, and
+    /// draws its instances once it is loaded; adding the same file again returns the
+    /// existing handle.
+    pub fn load_model(&mut self, file_name: &str) -> ModelHandle {
+        let existing = self.models.iter().find(|(_, model)| model.file_name == file_name).map(|(index, _)| index);
+        ModelHandle(existing.unwrap_or_else(|| {
+            self.models.insert(SceneModel {
+                file_name: file_name.to_string(),
+                lods: LodSettings::default(),
+                samplers: MaterialOverrides::default(),
+                blend_modes: MaterialOverrides::default(),
+                custom_materials: MaterialOverrides::default(),
+                state: LoadState::Loading,
+            })
+        }))
+    }
+
+    /// Sets the levels of detail of a model, reloading it if it was already loaded. Does
+    /// nothing if the handle is no longer valid.
+    pub fn set_lods(&mut self, handle: ModelHandle, lods: LodSettings) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.lods = lods;
+        }
+    }
+
+    pub fn lods(&self, handle: ModelHandle) -> Option<&LodSettings> {
+        self.models.get(handle.0).map(|model| &model.lods)
+    }
+
+    /// Whether a model is loaded, as of the last frame.
+    pub fn load_state(&self, handle: ModelHandle) -> Option<&LoadState> {
+        self.models.get(handle.0).map(|model| &model.state)
+    }
+
+    pub(crate) fn set_load_state(&mut self, handle: ModelHandle, state: LoadState) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.state = state;
+        }
+    }
+
+    /// Samples the textures of all materials of a model with `sampler`, replacing the
+    /// samplers of the file and those set with `set_material_sampler`. Does nothing if the
+    /// handle is no longer valid.
+    pub fn set_sampler(&mut self, handle: ModelHandle, sampler: SamplerSettings) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.samplers = MaterialOverrides {
+                all: Some(sampler),
+                materials: HashMap::new(),
+            };
+        }
+    }
+
+    /// Samples the textures of the materials of a model named `material` with `sampler`.
+    /// Does nothing if the handle is no longer valid.
+    pub fn set_material_sampler(&mut self, handle: ModelHandle, material: &str, sampler: SamplerSettings) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.samplers.materials.insert(material.to_string(), sampler);
+        }
+    }
+
+    /// Blends all materials of a model with `blend_mode`, replacing the modes of the file
+    /// and those set with `set_material_blend_mode`. Custom materials keep their own. Does
+    /// nothing if the handle is no longer valid.
+    pub fn set_blend_mode(&mut self, handle: ModelHandle, blend_mode: BlendMode) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.blend_modes = MaterialOverrides {
+                all: Some(blend_mode),
+                materials: HashMap::new(),
+            };
+        }
+    }
+
+    /// Blends the materials of a model named `material` with `blend_mode`. Does nothing if
+    /// the handle is no longer valid.
+    pub fn set_material_blend_mode(&mut self, handle: ModelHandle, material: &str, blend_mode: BlendMode) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.blend_modes.materials.insert(material.to_string(), blend_mode);
+        }
+    }
+
+    /// Goes back to blending a model's materials the way its file says.
+    pub fn clear_blend_modes(&mut self, handle: ModelHandle) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.blend_modes = MaterialOverrides::default();
+        }
+    }
+
+    /// Draws all meshes of a model with a custom material, replacing the materials of the
+    /// file and those set with `set_named_material`. Does nothing if the handle is no
+    /// longer valid.
+    pub fn set_material(&mut self, handle: ModelHandle, material: MaterialHandle) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.custom_materials = MaterialOverrides {
+                all: Some(material),
+                materials: HashMap::new(),
+            };
+        }
+    }
+
+    /// Draws the meshes of a model using its material named `name` with a custom material.
+    /// Does nothing if the handle is no longer valid.
+    pub fn set_named_material(&mut self, handle: ModelHandle, name: &str, material: MaterialHandle) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.custom_materials.materials.insert(name.to_string(), material);
+        }
+    }
+
+    /// Goes back to drawing a model with the materials of its file.
+    pub fn clear_materials(&mut self, handle: ModelHandle) {
+        if let Some(model) = self.models.get_mut(handle.0) {
+            model.custom_materials = MaterialOverrides::default();
+        }
+    }
+
+    /// Removes a model together with all of its instances.
+    pub fn remove_model(&mut self, handle: ModelHandle) {
+        if self.models.remove(handle.0).is_some() {
+            let instances: Vec<Index> = self
+                .instances
+                .iter()
+                .filter(|(_, instance)| instance.model == handle)
+                .map(|(index, _)| index)
+                .collect();
+            for index in instances {
+                self.instances.remove(index);
+            }
+        }
+    }
+
+    pub fn model_file(&self, handle: ModelHandle) -> Option<&str> {
+        self.models.get(handle.0).map(|model| model.file_name.as_str())
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = (ModelHandle, &str)> {
+        self.models.iter().map(|(index, model)| (ModelHandle(index), model.file_name.as_str()))
+    }
+
+    /// Places a model in the world. Instances of models that are no longer in the scene
+    /// are ignored.
+    pub fn add_instance(&mut self, instance: ModelInstance) -> InstanceHandle {
+        InstanceHandle(self.instances.insert(instance))
+    }
+
+    pub fn remove_instance(&mut self, handle: InstanceHandle) -> Option<ModelInstance> {
+        self.instances.remove(handle.0)
+    }
+
+    pub fn instance(&self, handle: InstanceHandle) -> Option<&ModelInstance> {
+        self.instances.get(handle.0)
+    }
+
+    pub fn instance_mut(&mut self, handle: InstanceHandle) -> Option<&mut ModelInstance> {
+        self.instances.get_mut(handle.0)
+    }
+
+    /// Moves an instance. Does nothing if the handle is no longer valid.
+    pub fn set_transform(&mut self, handle: InstanceHandle, position: Vector3<f32>, rotation: Quaternion<f32>) {
+        if let Some(instance) = self.instances.get_mut(handle.0) {
+            instance.position = position;
+            instance.rotation = rotation;
+        }
+    }
+
+    pub fn instances(&self) -> impl Iterator<Item = (InstanceHandle, &ModelInstance)> {
+        self.instances.iter().map(|(index, instance)| (InstanceHandle(index), instance))
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub(crate) fn update_animations(&mut self, dt: f32) {
+        for (_, instance) in self.instances.iter_mut() {
+            if let Some(animation) = &mut instance.animation {
+                animation.advance(dt);
+            }
+        }
+    }
+
+    /// Statistics of the last rendered frame.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    pub(crate) fn set_stats(&mut self, stats: RenderStats) {
+        self.stats = stats;
+    }
+}
+
+/// Planes of the camera's view volume, pointing inwards.
+struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(view_proj.x[i], view_proj.y[i], view_proj.z[i], view_proj.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+        Self { planes }
+    }
+
+    fn intersects(&self, bounds: &model::Bounds) -> bool {
+        let center = bounds.center();
+        let extents = bounds.extents();
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let radius = extents.x * normal.x.abs() + extents.y * normal.y.abs() + extents.z * normal.z.abs();
+            normal.dot(center) + plane.w >= -radius
+        })
+    }
+}
+
+/// Instances of one mesh of a level of detail drawn with a single call.
+struct Batch {
+    model: ModelHandle,
+    level: usize,
+    mesh: usize,
+    instances: Range<u32>,
+    /// Custom material replacing the mesh's own.
+    material: Option<MaterialHandle>,
+}
+
+impl Batch {
+    /// Whether both batches draw the same mesh with the same material.
+    fn same_mesh(&self, other: &Batch) -> bool {
+        self.model == other.model && self.level == other.level && self.mesh == other.mesh && self.material == other.material
+    }
+}
+
+/// A visible instance of a mesh with a transparent material, waiting to be sorted.
+struct TransparentDraw {
+    batch: Batch,
+    distance: f32,
+    instance: InstanceRaw,
+}
+
+struct LoadedModel {
+    lods: LodSettings,
+    samplers: MaterialOverrides<SamplerSettings>,
+    blend_modes: MaterialOverrides<BlendMode>,
+    /// Receives the model from the task loading it in the background.
+    pending: Option<oneshot::Receiver<anyhow::Result<model::Model>>>,
+    /// `None` until the model is loaded or when it failed to load. While a model loads
+    /// again with new levels of detail, the previous one is still drawn.
+    model: Option<model::Model>,
+    state: LoadState,
+    /// Set when one of the model's files changed on disk, to load it again.
+    stale: bool,
+}
+
+/// GPU side of the `SceneManager`: the loaded models and one instance buffer holding the
+/// visible instances of every mesh, followed by all instances of every model for the
+/// shadow passes and the transparent instances from back to front.
+///
+/// The skinning matrices of every skinned instance share a storage buffer, which the
+/// main pass binds next to the camera and the shadow passes bind on their own.
+pub(crate) struct SceneRenderer {
+    assets: AssetServer,
+    /// Layout of the bind groups of the standard materials.
+    layout: Arc<wgpu::BindGroupLayout>,
+    models: HashMap<ModelHandle, LoadedModel>,
+    /// Level of detail each instance was drawn with last frame.
+    levels: HashMap<InstanceHandle, usize>,
+    instance_buffer: wgpu::Buffer,
+    capacity: usize,
+    joint_buffer: wgpu::Buffer,
+    joint_capacity: usize,
+    joint_bind_group: wgpu::BindGroup,
+    /// Bumped whenever the joint buffer is recreated.
+    pub joint_generation: u64,
+    batches: Vec<Batch>,
+    /// Batches of transparent meshes, ordered back to front.
+    transparent_batches: Vec<Batch>,
+    shadow_batches: Vec<Batch>,
+    /// Shadow casters with a cutout material, drawn with an alpha test.
+    cutout_shadow_batches: Vec<Batch>,
+}
+
+impl SceneRenderer {
+    const INITIAL_CAPACITY: usize = 256;
+    const INITIAL_JOINT_CAPACITY: usize = 256;
+
+    pub(crate) fn new(device: &wgpu::Device, assets: &AssetServer, layout: &Arc<wgpu::BindGroupLayout>) -> Self {
+        let joint_buffer = Self::create_joint_buffer(device, Self::INITIAL_JOINT_CAPACITY);
+        Self {
+            assets: assets.clone(),
+            layout: layout.clone(),
+            models: HashMap::new(),
+            levels: HashMap::new(),
+            instance_buffer: Self::create_instance_buffer(device, Self::INITIAL_CAPACITY),
+            capacity: Self::INITIAL_CAPACITY,
+            joint_bind_group: Self::create_joint_bind_group(device, &joint_buffer),
+            joint_buffer,
+            joint_capacity: Self::INITIAL_JOINT_CAPACITY,
+            joint_generation: 0,
+            batches: Vec::new(),
+            transparent_batches: Vec::new(),
+            shadow_batches: Vec::new(),
+            cutout_shadow_batches: Vec::new(),
+        }
+    }
+
+    /// Layout of the bind group holding nothing but the joint buffer.
+    pub(crate) fn create_joint_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[Self::joint_layout_entry(0)],
+            label: Some("joint_bind_group_layout"),
+        })
+    }
+
+    /// Layout entry of the joint buffer, also used by the camera bind group.
+    pub(crate) fn joint_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    fn create_joint_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint Buffer"),
+            size: (capacity * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_joint_bind_group(device: &wgpu::Device, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &Self::create_joint_bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("joint_bind_group"),
+        })
+    }
+
+    pub(crate) fn joint_buffer(&self) -> &wgpu::Buffer {
+        &self.joint_buffer
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Starts loading new models, takes over the ones done loading, culls the instances
+    /// against the camera and rebuilds the batches. `materials` tells which custom
+    /// materials are transparent.
+    pub(crate) fn upload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        queue: &Arc<wgpu::Queue>,
+        scene: &SceneManager,
+        materials: &MaterialRenderer,
+        camera: &Camera,
+        projection: &Projection,
+    ) -> RenderStats {
+        self.models.retain(|handle, _| scene.models.contains(handle.0));
+        for (index, scene_model) in scene.models.iter() {
+            let handle = ModelHandle(index);
+            if self.models.get(&handle).is_none_or(|loaded| loaded.lods != scene_model.lods || loaded.stale) {
+                let pending = Self::spawn_load(device, queue, &self.layout, &self.assets, scene_model);
+                let loaded = self.models.entry(handle).or_insert_with(|| LoadedModel {
+                    lods: scene_model.lods.clone(),
+                    samplers: MaterialOverrides::default(),
+                    blend_modes: MaterialOverrides::default(),
+                    pending: None,
+                    model: None,
+                    state: LoadState::Loading,
+                    stale: false,
+                });
+                loaded.lods = scene_model.lods.clone();
+                loaded.stale = false;
+                loaded.pending = Some(pending);
+                loaded.state = LoadState::Loading;
+            }
+            let Some(loaded) = self.models.get_mut(&handle) else {
+                continue;
+            };
+
+            if let Some(pending) = &mut loaded.pending {
+                match pending.try_recv() {
+                    Ok(Ok(mut model)) => {
+                        Self::apply_samplers(device, &self.layout, &mut model, &scene_model.samplers);
+                        Self::apply_blend_modes(queue, &mut model, &scene_model.blend_modes);
+                        loaded.samplers = scene_model.samplers.clone();
+                        loaded.blend_modes = scene_model.blend_modes.clone();
+                        loaded.model = Some(model);
+                        loaded.state = LoadState::Loaded;
+                        loaded.pending = None;
+                    }
+                    Ok(Err(error)) => {
+                        log::error!("failed to load model {}: {:#}", scene_model.file_name, error);
+                        loaded.state = LoadState::Failed(format!("{:#}", error));
+                        loaded.pending = None;
+                    }
+                    Err(oneshot::error::TryRecvError::Empty) => {}
+                    Err(oneshot::error::TryRecvError::Closed) => {
+                        loaded.state = LoadState::Failed("the loading task stopped".to_string());
+                        loaded.pending = None;
+                    }
+                }
+            }
+
+            // Changed samplers only need new bind groups
+            if loaded.samplers != scene_model.samplers {
+                if let Some(model) = &mut loaded.model {
+                    Self::apply_samplers(device, &self.layout, model, &scene_model.samplers);
+                }
+                loaded.samplers = scene_model.samplers.clone();
+            }
+            if loaded.blend_modes != scene_model.blend_modes {
+                if let Some(model) = &mut loaded.model {
+                    Self::apply_blend_modes(queue, model, &scene_model.blend_modes);
+                }
+                loaded.blend_modes = scene_model.blend_modes.clone();
+            }
+        }
+
+        let mut by_model: HashMap<ModelHandle, Vec<(InstanceHandle, &ModelInstance)>> = HashMap::new();
+        for (handle, instance) in scene.instances() {
+            if instance.visible {
+                by_model.entry(instance.model).or_default().push((handle, instance));
+            }
+        }
+        self.levels.retain(|handle, _| scene.instances.contains(handle.0));
+
+        let frustum = Frustum::from_matrix(projection.calc_matrix() * camera.calc_matrix());
+        let tan_half_fovy = (projection.fovy().0 * 0.5).tan();
+        let mut stats = RenderStats::default();
+        let mut raw = Vec::new();
+        let mut joints: Vec<[[f32; 4]; 4]> = Vec::new();
+        let mut transparent = Vec::new();
+        self.batches.clear();
+        self.transparent_batches.clear();
+        self.shadow_batches.clear();
+        self.cutout_shadow_batches.clear();
+
+        for (index, scene_model) in scene.models.iter() {
+            let handle = ModelHandle(index);
+            let Some(instances) = by_model.get(&handle) else {
+                continue;
+            };
+            let Some(LoadedModel { lods, model: Some(model), .. }) = self.models.get(&handle) else {
+                continue;
+            };
+
+            // Pick the level of every instance from the size of its bounds on screen, and
+            // pose its skeleton
+            let bounds = model.bounds();
+            let mut by_level: Vec<Vec<(&ModelInstance, Matrix4<f32>, u32)>> =
+                (0..=model.lods.len()).map(|_| Vec::new()).collect();
+            for (instance_handle, instance) in instances {
+                let joint_offset = joints.len() as u32;
+                if let Some(skeleton) = &model.skeleton {
+                    let matrices = animation::joint_matrices(model, skeleton, instance.animation.as_ref());
+                    joints.extend(matrices.into_iter().map(Into::<[[f32; 4]; 4]>::into));
+                }
+
+                let transform = instance.transform();
+                let world = bounds.transformed(&transform);
+                let distance = (world.center() - camera.position.to_vec()).magnitude().max(f32::EPSILON);
+                let screen_size = world.extents().magnitude() / (distance * tan_half_fovy);
+
+                let previous = self.levels.get(instance_handle).copied().unwrap_or(0);
+                let level = lod::select_level(&model.lods, screen_size, previous, lods.hysteresis);
+                self.levels.insert(*instance_handle, level);
+                by_level[level].push((instance, transform, joint_offset));
+            }
+
+            for (level, instances) in by_level.iter().enumerate() {
+                if instances.is_empty() {
+                    continue;
+                }
+                let meshes = model.lod_meshes(level);
+                let mut seen = vec![false; instances.len()];
+
+                for (mesh_index, mesh) in meshes.iter().enumerate() {
+                    let (material, blend_mode) = Self::mesh_material(model, mesh, scene_model, materials);
+
+                    // Transparent instances are sorted with those of every other model later
+                    if blend_mode.is_transparent() {
+                        for (i, (instance, transform, joint_offset)) in instances.iter().enumerate() {
+                            let world = mesh.bounds.transformed(transform);
+                            if frustum.intersects(&world) {
+                                seen[i] = true;
+                                stats.triangles += (mesh.num_elements / 3) as u64;
+                                transparent.push(TransparentDraw {
+                                    batch: Batch { model: handle, level, mesh: mesh_index, instances: 0..0, material },
+                                    distance: (world.center() - camera.position.to_vec()).magnitude2(),
+                                    instance: instance.to_raw(*joint_offset),
+                                });
+                            }
+                        }
+                        continue;
+                    }
+
+                    let start = raw.len() as u32;
+                    for (i, (instance, transform, joint_offset)) in instances.iter().enumerate() {
+                        if frustum.intersects(&mesh.bounds.transformed(transform)) {
+                            seen[i] = true;
+                            raw.push(instance.to_raw(*joint_offset));
+                        }
+                    }
+                    let end = raw.len() as u32;
+                    if end > start {
+                        stats.draw_calls += 1;
+                        stats.triangles += (mesh.num_elements / 3) as u64 * (end - start) as u64;
+                        self.batches.push(Batch { model: handle, level, mesh: mesh_index, instances: start..end, material });
+                    }
+                }
+
+                let visible = seen.iter().filter(|seen| **seen).count() as u32;
+                stats.visible_instances += visible;
+                stats.culled_instances += instances.len() as u32 - visible;
+
+                // Shadow casters outside the view can still throw shadows into it, unlike
+                // transparent meshes
+                let start = raw.len() as u32;
+                raw.extend(instances.iter().map(|(instance, _, joint_offset)| instance.to_raw(*joint_offset)));
+                let end = raw.len() as u32;
+                for (mesh_index, mesh) in meshes.iter().enumerate() {
+                    let batch = Batch { model: handle, level, mesh: mesh_index, instances: start..end, material: None };
+                    // Custom materials do their own alpha test, which the shadow pass can't run
+                    match Self::mesh_material(model, mesh, scene_model, materials) {
+                        (_, blend_mode) if blend_mode.is_transparent() => {}
+                        (None, BlendMode::Cutout(_)) => self.cutout_shadow_batches.push(batch),
+                        _ => self.shadow_batches.push(batch),
+                    }
+                }
+            }
+        }
+
+        // Transparent instances are drawn one after another from back to front, in one
+        // call while they follow each other with the same mesh
+        transparent.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+        for draw in transparent {
+            let index = raw.len() as u32;
+            raw.push(draw.instance);
+            match self.transparent_batches.last_mut() {
+                Some(last) if last.same_mesh(&draw.batch) => {
+                    last.instances.end = index + 1;
+                }
+                _ => {
+                    stats.draw_calls += 1;
+                    self.transparent_batches.push(Batch { instances: index..index + 1, ..draw.batch });
+                }
+            }
+        }
+
+        if raw.len() > self.capacity {
+            self.capacity = raw.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        if !raw.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+
+        if joints.len() > self.joint_capacity {
+            self.joint_capacity = joints.len().next_power_of_two();
+            self.joint_buffer = Self::create_joint_buffer(device, self.joint_capacity);
+            self.joint_bind_group = Self::create_joint_bind_group(device, &self.joint_buffer);
+            self.joint_generation += 1;
+        }
+        if !joints.is_empty() {
+            queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&joints));
+        }
+
+        stats
+    }
+
+    /// Marks the models reading any of `files` to be loaded again on the next upload. The
+    /// current model is drawn until the new one is loaded, or kept if it fails to load.
+    pub(crate) fn reload(&mut self, scene: &SceneManager, files: &HashSet<String>) {
+        for (handle, loaded) in &mut self.models {
+            let Some(scene_model) = scene.models.get(handle.0) else {
+                continue;
+            };
+            // A model that failed to load has no sources, but its own files are known
+            let own_files = std::iter::once(scene_model.file_name.as_str())
+                .chain(scene_model.lods.files.iter().map(|(file_name, _)| file_name.as_str()));
+            let sources = loaded.model.iter().flat_map(|model| model.sources.iter().map(|source| source.file_name()));
+            if own_files.chain(sources).any(|file_name| files.contains(file_name)) {
+                loaded.stale = true;
+            }
+        }
+    }
+
+    /// Loads a model and its levels of detail on a blocking thread of the tokio runtime, as
+    /// decoding images, generating mipmaps and simplifying meshes would stall its workers.
+    fn spawn_load(
+        device: &Arc<wgpu::Device>,
+        queue: &Arc<wgpu::Queue>,
+        layout: &Arc<wgpu::BindGroupLayout>,
+        assets: &AssetServer,
+        scene_model: &SceneModel,
+    ) -> oneshot::Receiver<anyhow::Result<model::Model>> {
+        let (sender, receiver) = oneshot::channel();
+        let (device, queue, layout, assets) = (device.clone(), queue.clone(), layout.clone(), assets.clone());
+        let file_name = scene_model.file_name.clone();
+        let lods = scene_model.lods.clone();
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let load = async {
+                let mut model =
+                    resources::load_model_with_lods(&file_name, lods.generate.as_ref(), &assets, &device, &queue, &layout)
+                        .await?;
+                for (file_name, screen_size) in &lods.files {
+                    let lod = resources::load_model(file_name, &assets, &device, &queue, &layout).await?;
+                    model.add_lod(lod, *screen_size);
+                }
+                Ok(model)
+            };
+            let _ = sender.send(runtime.block_on(load));
+        });
+        receiver
+    }
+
+    pub(crate) fn load_states(&self) -> impl Iterator<Item = (ModelHandle, LoadState)> + '_ {
+        self.models.iter().map(|(handle, loaded)| (*handle, loaded.state.clone()))
+    }
+
+    fn apply_samplers(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        model: &mut model::Model,
+        samplers: &MaterialOverrides<SamplerSettings>,
+    ) {
+        for material in &mut model.materials {
+            if let Some(sampler) = samplers.get(&material.name) {
+                material.set_sampler(device, layout, sampler);
+            }
+        }
+    }
+
+    fn apply_blend_modes(queue: &wgpu::Queue, model: &mut model::Model, blend_modes: &MaterialOverrides<BlendMode>) {
+        for material in &mut model.materials {
+            material.set_blend_mode(queue, blend_modes.get(&material.name).copied());
+        }
+    }
+
+    /// The custom material a mesh is drawn with, if any, and how it is blended. A custom
+    /// material that cannot be drawn falls back to the mesh's own.
+    fn mesh_material(
+        model: &model::Model,
+        mesh: &model::Mesh,
+        scene_model: &SceneModel,
+        materials: &MaterialRenderer,
+    ) -> (Option<MaterialHandle>, BlendMode) {
+        let Some(material) = model.materials.get(mesh.material) else {
+            return (None, BlendMode::Opaque);
+        };
+        let custom = scene_model.custom_materials.get(&material.name).copied();
+        let blend_mode = custom.and_then(|custom| materials.blend_mode(custom)).unwrap_or(material.blend_mode);
+        (custom, blend_mode)
+    }
+
+    fn mesh(&self, batch: &Batch) -> Option<(&model::Model, &model::Mesh)> {
+        let model = self.models.get(&batch.model)?.model.as_ref()?;
+        Some((model, model.lod_meshes(batch.level).get(batch.mesh)?))
+    }
+
+    /// Draws the visible opaque and cutout batches with the main pipelines, or with the
+    /// pipelines of their custom materials.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a BlendPipelines,
+        materials: &'a MaterialRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        environment_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let bind_groups = [camera_bind_group, light_bind_group, environment_bind_group];
+        self.draw_batches(pass, &self.batches, pipelines, materials, bind_groups);
+    }
+
+    /// Draws the visible transparent batches from back to front, after everything else
+    /// that writes depth.
+    pub(crate) fn draw_transparent<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a BlendPipelines,
+        materials: &'a MaterialRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        environment_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let bind_groups = [camera_bind_group, light_bind_group, environment_bind_group];
+        self.draw_batches(pass, &self.transparent_batches, pipelines, materials, bind_groups);
+    }
+
+    /// `bind_groups` are the camera, light and environment bind groups.
+    fn draw_batches<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        batches: &'a [Batch],
+        pipelines: &'a BlendPipelines,
+        materials: &'a MaterialRenderer,
+        bind_groups: [&'a wgpu::BindGroup; 3],
+    ) {
+        if batches.is_empty() {
+            return;
+        }
+        let [camera_bind_group, light_bind_group, environment_bind_group] = bind_groups;
+        pass.set_bind_group(3, environment_bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        let mut current: Option<&wgpu::RenderPipeline> = None;
+        for batch in batches {
+            let Some((model, mesh)) = self.mesh(batch) else {
+                continue;
+            };
+            let custom = batch.material.and_then(|material| materials.get(material));
+            let batch_pipeline = match custom {
+                Some((pipeline, _)) => pipeline,
+                None => pipelines.get(model.materials[mesh.material].blend_mode),
+            };
+            if current.is_none_or(|current| !std::ptr::eq(current, batch_pipeline)) {
+                pass.set_pipeline(batch_pipeline);
+                current = Some(batch_pipeline);
+            }
+
+            match custom {
+                Some((_, bind_group)) => {
+                    pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    pass.set_bind_group(0, bind_group, &[]);
+                    pass.set_bind_group(1, camera_bind_group, &[]);
+                    pass.set_bind_group(2, light_bind_group, &[]);
+                    pass.draw_indexed(0..mesh.num_elements, 0, batch.instances.clone());
+                }
+                None => {
+                    let material = &model.materials[mesh.material];
+                    pass.draw_mesh_instanced(mesh, material, batch.instances.clone(), camera_bind_group, light_bind_group);
+                }
+            }
+        }
+    }
+
+    /// Draws every shadow caster into a shadow map with the light's matrix already bound:
+    /// solid ones with `pipeline`, cutouts with `cutout_pipeline` and their material.
+    pub(crate) fn draw_shadow_casters<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        cutout_pipeline: &'a wgpu::RenderPipeline,
+    ) {
+        pass.set_bind_group(1, &self.joint_bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+        pass.set_pipeline(pipeline);
+        for batch in &self.shadow_batches {
+            if let Some((_, mesh)) = self.mesh(batch) {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, batch.instances.clone());
+            }
+        }
+
+        if self.cutout_shadow_batches.is_empty() {
+            return;
+        }
+        pass.set_pipeline(cutout_pipeline);
+        for batch in &self.cutout_shadow_batches {
+            let Some((model, mesh)) = self.mesh(batch) else {
+                continue;
+            };
+            let Some(material) = model.materials.get(mesh.material) else {
+                continue;
+            };
+            pass.set_bind_group(2, &material.bind_group, &[]);
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_elements, 0, batch.instances.clone());
+        }
+    }
+
+    /// Shadow draw calls per shadow map layer.
+    pub(crate) fn shadow_draw_calls(&self) -> u32 {
+        (self.shadow_batches.len() + self.cutout_shadow_batches.len()) as u32
+    }
+}
@@ -0,0 +1,327 @@
+// Declarations shared by the built-in scene shader and custom material shaders: the
+// camera, skinning, lights, shadows and environment bind groups, the vertex layout, and
+// helpers for lighting and fog. Material shaders bind their own data at group 0.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+@group(1) @binding(1)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: vec3<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+    shadow_layer: i32,
+    shadow_count: u32,
+}
+struct LightHeader {
+    ambient: vec3<f32>,
+    count: u32,
+}
+@group(2) @binding(0)
+var<uniform> light_header: LightHeader;
+@group(2) @binding(1)
+var<storage, read> lights: array<Light>;
+
+struct ShadowLayer {
+    view_proj: mat4x4<f32>,
+    // depth bias, normal bias, cascade far distance, pcf radius
+    params: vec4<f32>,
+    // uv offset and scale of the layer's tile in its atlas page, texel size
+    tile: vec4<f32>,
+    page: i32,
+}
+@group(2) @binding(2)
+var<storage, read> shadow_layers: array<ShadowLayer>;
+@group(2) @binding(3)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(4)
+var s_shadow: sampler_comparison;
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(14) joint_offset: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+}
+
+// Weighted sum of the joint matrices moving a vertex; identity when it is not skinned
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>, offset: u32) -> mat4x4<f32> {
+    if (dot(weights, vec4<f32>(1.0)) <= 0.0) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_matrices[offset + joints.x] * weights.x
+        + joint_matrices[offset + joints.y] * weights.y
+        + joint_matrices[offset + joints.z] * weights.z
+        + joint_matrices[offset + joints.w] * weights.w;
+}
+
+// Transforms a vertex into world and clip space, skinning it if it has joint weights
+fn model_vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    let skin = skin_matrix(model.joints, model.weights, instance.joint_offset);
+    let skin_linear = mat3x3<f32>(skin[0].xyz, skin[1].xyz, skin[2].xyz);
+
+    let world_position = model_matrix * skin * vec4<f32>(model.position, 1.0);
+
+    // Lighting happens in world space, so only the tangent frame is passed on
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize(normal_matrix * skin_linear * model.normal);
+    out.world_tangent = normalize(normal_matrix * skin_linear * model.tangent);
+    out.world_bitangent = normalize(normal_matrix * skin_linear * model.bitangent);
+    return out;
+}
+
+const PI: f32 = 3.14159265359;
+
+struct Environment {
+    fog_color: vec3<f32>,
+    fog_mode: u32,
+    // start, end, density
+    fog_params: vec4<f32>,
+    // base, falloff, density, enabled
+    height_params: vec4<f32>,
+    // intensity, max lod, enabled, sky intensity
+    ibl_params: vec4<f32>,
+}
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+@group(3) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(2)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(3)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(4)
+var s_environment: sampler;
+
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 3u;
+
+// Fraction of the surface color that survives the fog between the camera and `position`
+fn fog_visibility(position: vec3<f32>) -> f32 {
+    let eye = camera.view_pos.xyz;
+    let distance = length(position - eye);
+    let params = environment.fog_params;
+
+    var visibility = 1.0;
+    if environment.fog_mode == FOG_LINEAR {
+        visibility = clamp((params.y - distance) / max(params.y - params.x, 0.0001), 0.0, 1.0);
+    } else if environment.fog_mode == FOG_EXPONENTIAL {
+        visibility = exp(-params.z * distance);
+    } else if environment.fog_mode == FOG_EXPONENTIAL_SQUARED {
+        let d = params.z * distance;
+        visibility = exp(-d * d);
+    }
+
+    // Density integrated along the view ray through fog that thins out exponentially with height
+    let height = environment.height_params;
+    if height.w > 0.0 {
+        let falloff = max(height.y, 0.0001);
+        let rise = falloff * (position.y - eye.y);
+        var ray_factor = 1.0;
+        if abs(rise) > 0.0001 {
+            ray_factor = (1.0 - exp(-rise)) / rise;
+        }
+        let amount = height.z * exp(-falloff * (eye.y - height.x)) * ray_factor * distance;
+        visibility *= exp(-max(amount, 0.0));
+    }
+
+    return visibility;
+}
+
+// Attenuation of a point or spot light at `distance`, faded smoothly to zero at `range`
+fn distance_attenuation(light: Light, distance: f32) -> f32 {
+    let terms = light.attenuation;
+    var attenuation = 1.0 / max(terms.x + terms.y * distance + terms.z * distance * distance, 0.0001);
+    if light.range > 0.0 {
+        let ratio = distance / light.range;
+        let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+        attenuation *= window * window;
+    }
+    return attenuation;
+}
+
+// Direction towards `light` from `position` in xyz, and the light's distance and spot
+// cone attenuation in w
+fn light_incidence(light: Light, position: vec3<f32>) -> vec4<f32> {
+    if light.kind == LIGHT_DIRECTIONAL {
+        return vec4<f32>(normalize(-light.direction), 1.0);
+    }
+
+    let to_light = light.position - position;
+    let distance = length(to_light);
+    let light_dir = to_light / max(distance, 0.0001);
+    var attenuation = distance_attenuation(light, distance);
+    if light.kind == LIGHT_SPOT {
+        let theta = dot(light_dir, normalize(-light.direction));
+        attenuation *= smoothstep(light.cos_outer, light.cos_inner, theta);
+    }
+    return vec4<f32>(light_dir, attenuation);
+}
+
+// Shadow layer of `light` covering `position`: a cascade picked by view depth for
+// directional lights, a cube face picked by the major axis for point lights
+fn shadow_layer_index(light: Light, position: vec3<f32>, view_depth: f32) -> i32 {
+    if light.kind == LIGHT_DIRECTIONAL {
+        for (var i = 0u; i < light.shadow_count; i += 1u) {
+            if view_depth <= shadow_layers[light.shadow_layer + i32(i)].params.z {
+                return light.shadow_layer + i32(i);
+            }
+        }
+        return -1;
+    }
+
+    if light.kind == LIGHT_POINT {
+        let d = position - light.position;
+        let a = abs(d);
+        var face = 0;
+        if a.x >= a.y && a.x >= a.z {
+            face = select(1, 0, d.x > 0.0);
+        } else if a.y >= a.z {
+            face = select(3, 2, d.y > 0.0);
+        } else {
+            face = select(5, 4, d.z > 0.0);
+        }
+        return light.shadow_layer + face;
+    }
+
+    return light.shadow_layer;
+}
+
+// Fraction of `light` reaching `position`, filtered with a 3x3 PCF kernel
+fn shadow_factor(light: Light, position: vec3<f32>, normal: vec3<f32>, view_depth: f32) -> f32 {
+    if light.shadow_layer < 0 {
+        return 1.0;
+    }
+    let index = shadow_layer_index(light, position, view_depth);
+    if index < 0 {
+        return 1.0;
+    }
+
+    let layer = shadow_layers[index];
+    let offset_position = position + normal * layer.params.y;
+    let clip = layer.view_proj * vec4<f32>(offset_position, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    if ndc.z > 1.0 || any(abs(ndc.xy) > vec2<f32>(1.0)) {
+        return 1.0;
+    }
+
+    let uv = layer.tile.xy + (ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * layer.tile.z;
+    let depth = ndc.z - layer.params.x;
+    let texel = layer.tile.w;
+    let step = texel * layer.params.w;
+    // Keep the kernel inside the tile so it never reads a neighbouring light's map
+    let min_uv = layer.tile.xy + vec2<f32>(texel * 0.5);
+    let max_uv = layer.tile.xy + vec2<f32>(layer.tile.z - texel * 0.5);
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let sample_uv = clamp(uv + vec2<f32>(f32(x), f32(y)) * step, min_uv, max_uv);
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, sample_uv, layer.page, depth);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Cook-Torrance terms: GGX normal distribution, Smith-Schlick geometry, Schlick Fresnel
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel averaged over the microfacets of a rough surface, for light from the environment
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Ambient light from the sky: irradiance for the diffuse part, the prefiltered
+// environment and the split-sum lookup table for the specular part
+fn image_based_lighting(normal: vec3<f32>, view_dir: vec3<f32>, base_color: vec3<f32>, metallic: f32, roughness: f32, f0: vec3<f32>) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_diffuse = (vec3<f32>(1.0) - f) * (1.0 - metallic);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let diffuse = k_diffuse * irradiance * base_color;
+
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, roughness * environment.ibl_params.y).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let specular = prefiltered * (f * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.ibl_params.x;
+}
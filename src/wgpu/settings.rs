@@ -0,0 +1,192 @@
+/// When finished frames are shown on screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Waits for the vertical blank and never tears. Supported everywhere.
+    #[default]
+    Vsync,
+    /// Like `Vsync`, but a late frame is shown immediately, tearing instead of stuttering.
+    AdaptiveVsync,
+    /// Renders as fast as possible and shows the newest frame at the vertical blank, without tearing.
+    Mailbox,
+    /// Shows every frame as soon as it is done, tearing.
+    Immediate,
+}
+
+impl PresentMode {
+    fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Vsync => wgpu::PresentMode::Fifo,
+            PresentMode::AdaptiveVsync => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+/// Graphics API used to talk to the GPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The native API of the platform: Vulkan, Metal or DirectX 12.
+    #[default]
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    /// OpenGL or OpenGL ES, for old hardware and drivers.
+    Gl,
+}
+
+impl Backend {
+    pub(crate) fn to_wgpu(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto => wgpu::Backends::PRIMARY,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+/// Which GPU to pick when there are several.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AdapterPreference {
+    /// The fastest GPU, usually the discrete one.
+    #[default]
+    HighPerformance,
+    /// The most power efficient GPU, usually the integrated one.
+    LowPower,
+    /// A software renderer running on the CPU, if the system has one.
+    Software,
+}
+
+impl AdapterPreference {
+    pub(crate) fn power_preference(self) -> wgpu::PowerPreference {
+        match self {
+            AdapterPreference::HighPerformance => wgpu::PowerPreference::HighPerformance,
+            AdapterPreference::LowPower | AdapterPreference::Software => wgpu::PowerPreference::LowPower,
+        }
+    }
+}
+
+/// How the renderer sets up the GPU and the window surface.
+///
+/// Settings can be changed while the game runs: the sample count, present mode and
+/// resolution scale recreate only the affected render targets and pipelines, while a new
+/// backend or adapter preference restarts the renderer on the next frame. A backend or
+/// adapter that can't be used falls back to the defaults; if the renderer can't restart at
+/// all, it keeps the current device and `backend` and `adapter` are reset to match it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+    /// Samples per pixel for multisample anti-aliasing; 1 turns it off. 4 is supported
+    /// everywhere, other counts fall back to the closest lower one the GPU supports.
+    pub sample_count: u32,
+    pub present_mode: PresentMode,
+    pub backend: Backend,
+    pub adapter: AdapterPreference,
+    /// Size of the 3D scene relative to the window, e.g. 0.5 renders at half resolution
+    /// and upscales; the post-processed image always covers the whole window.
+    pub resolution_scale: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            present_mode: PresentMode::default(),
+            backend: Backend::default(),
+            adapter: AdapterPreference::default(),
+            resolution_scale: 1.0,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn with_vsync(mut self, vsync: bool) -> Self {
+        self.present_mode = if vsync { PresentMode::Vsync } else { PresentMode::Immediate };
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_adapter(mut self, adapter: AdapterPreference) -> Self {
+        self.adapter = adapter;
+        self
+    }
+
+    pub fn with_resolution_scale(mut self, resolution_scale: f32) -> Self {
+        self.resolution_scale = resolution_scale;
+        self
+    }
+
+    /// Whether going from `self` to `other` needs a new device rather than new resources.
+    pub(crate) fn needs_new_device(&self, other: &RenderSettings) -> bool {
+        self.backend != other.backend || self.adapter != other.adapter
+    }
+
+    /// `present_mode` if the surface supports it, otherwise vsync, which always is.
+    pub(crate) fn surface_present_mode(&self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let wanted = self.present_mode.to_wgpu();
+        if supported.contains(&wanted) {
+            wanted
+        } else {
+            log::warn!("present mode {:?} is not supported, using vsync", self.present_mode);
+            wgpu::PresentMode::Fifo
+        }
+    }
+
+    /// Highest sample count up to `sample_count` that every multisampled target format supports.
+    pub(crate) fn supported_sample_count(&self, adapter: &wgpu::Adapter, features: wgpu::Features, formats: &[wgpu::TextureFormat]) -> u32 {
+        // OpenGL does not resolve into a multisampled framebuffer whose depth buffer can also
+        // be sampled, which the post effects need
+        if self.sample_count > 1 && adapter.get_info().backend == wgpu::Backend::Gl {
+            log::warn!("multisampling is not supported on OpenGL, using 1x");
+            return 1;
+        }
+
+        let adapter_specific = features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let supported = |count: u32| {
+            count == 1
+                || (!adapter_specific && count == 4)
+                || (adapter_specific
+                    && formats.iter().all(|format| {
+                        adapter.get_texture_format_features(*format).flags.sample_count_supported(count)
+                    }))
+        };
+
+        let count = [16, 8, 4, 2, 1]
+            .into_iter()
+            .find(|count| *count <= self.sample_count.max(1) && supported(*count))
+            .unwrap_or(1);
+        if count != self.sample_count.max(1) {
+            log::warn!("{}x multisampling is not supported, using {}x", self.sample_count, count);
+        }
+        count
+    }
+
+    /// Size of the scene render targets for a window of `width` x `height`.
+    pub(crate) fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = self.resolution_scale.clamp(0.1, 4.0);
+        (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        )
+    }
+}
@@ -1,115 +1,114 @@
-// Vertex shader
+// Standard PBR material, appended to scene_prelude.wgsl
 
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-}
-@group(1) @binding(0)
-var<uniform> camera: Camera;
-
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-@group(2) @binding(0)
-var<uniform> light: Light;
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-    @location(3) tangent: vec3<f32>,
-    @location(4) bitangent: vec3<f32>,
-}
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-    @location(9) normal_matrix_0: vec3<f32>,
-    @location(10) normal_matrix_1: vec3<f32>,
-    @location(11) normal_matrix_2: vec3<f32>,
-}
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-    @location(1) tangent_position: vec3<f32>,
-    @location(2) tangent_light_position: vec3<f32>,
-    @location(3) tangent_view_position: vec3<f32>,
+@group(0) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_base_color: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+
+struct MaterialFactors {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
 }
+@group(0) @binding(10)
+var<uniform> material: MaterialFactors;
 
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-    let normal_matrix = mat3x3<f32>(
-        instance.normal_matrix_0,
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
+    return model_vertex(model, instance);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords) * material.base_color;
+    if base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+    let roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+    let occlusion = mix(1.0, textureSample(t_occlusion, s_occlusion, in.tex_coords).r, material.occlusion_strength);
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive;
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
     );
+    var tangent_normal = object_normal.xyz * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+    let normal = normalize(tangent_matrix * tangent_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
 
-    // Construct the tangent matrix
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
-
-    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
-
-    var out: VertexOutput;
-    out.clip_position = camera.view_proj * world_position;
-    out.tex_coords = model.tex_coords;
-    out.tangent_position = tangent_matrix * world_position.xyz;
-    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-    out.tangent_light_position = tangent_matrix * light.position;
-    return out;
-}
+    let geometric_normal = normalize(in.world_normal);
+    let view_depth = (camera.view_proj * vec4<f32>(in.world_position, 1.0)).w;
 
-// Fragment shader
+    // Dielectrics reflect about 4% head-on; metals tint the reflection with their base color
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
 
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0)@binding(1)
-var s_diffuse: sampler;
-@group(0)@binding(2)
-var t_normal: texture_2d<f32>;
-@group(0) @binding(3)
-var s_normal: sampler;
+    var radiance_out = vec3<f32>(0.0);
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
-    
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    for (var i = 0u; i < light_header.count; i += 1u) {
+        let light = lights[i];
+
+        let incidence = light_incidence(light, in.world_position);
+        let light_dir = incidence.xyz;
+        var attenuation = incidence.w;
+
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+        if n_dot_l <= 0.0 || attenuation <= 0.0 {
+            continue;
+        }
+
+        attenuation *= shadow_factor(light, in.world_position, geometric_normal, view_depth);
+
+        let half_dir = normalize(view_dir + light_dir);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
 
-    // Create the lighting vectors
-    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
-    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-    let half_dir = normalize(view_dir + light_dir);
+        let d = distribution_ggx(n_dot_h, roughness);
+        let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+        let f = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
 
-    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+        let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+        let k_diffuse = (vec3<f32>(1.0) - f) * (1.0 - metallic);
+        let diffuse = k_diffuse * base_color.rgb / PI;
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+        radiance_out += (diffuse + specular) * light.color * light.intensity * attenuation * n_dot_l;
+    }
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    // A skybox replaces the flat ambient term with light from the sky
+    var ambient = light_header.ambient * base_color.rgb;
+    if environment.ibl_params.z > 0.0 {
+        ambient = image_based_lighting(normal, view_dir, base_color.rgb, metallic, roughness, f0);
+    }
+    ambient *= occlusion;
+    let lit_color = ambient + radiance_out + emissive;
+    let result = mix(environment.fog_color, lit_color, fog_visibility(in.world_position));
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(result, base_color.a);
 }
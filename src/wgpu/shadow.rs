@@ -0,0 +1,668 @@
+use cgmath::{
+    Matrix4,
+    Point3,
+    Vector3,
+    Vector4,
+    Deg,
+    Rad,
+    EuclideanSpace,
+    InnerSpace,
+    SquareMatrix,
+};
+use super::light::{Light, LightKind, LightManager};
+use super::model::{self, Vertex};
+use super::texture;
+use super::camera::{Camera, Projection};
+use super::scene::SceneRenderer;
+
+/// cgmath builds OpenGL clip space (`z` in `[-1, 1]`); wgpu expects `z` in `[0, 1]`.
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Uniform buffer offsets must be aligned to this many bytes.
+const CASTER_STRIDE: u64 = 256;
+const MAX_ATLAS_RESOLUTION: u32 = 4096;
+/// Smallest atlas page; pages only grow past this when a light asks for a larger map.
+const MIN_PAGE_RESOLUTION: u32 = 2048;
+const SHADOW_NEAR: f32 = 0.1;
+const DEFAULT_SHADOW_FAR: f32 = 200.0;
+
+/// Per-light shadow configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Width and height of each shadow map rendered for the light. The map takes a tile of the
+    /// shadow atlas rounded up to the next power of two.
+    pub resolution: u32,
+    /// Depth offset subtracted before comparing, against shadow acne.
+    pub depth_bias: f32,
+    /// World-space offset along the surface normal applied before lookup.
+    pub normal_bias: f32,
+    /// Spacing of the 3x3 PCF kernel in texels; `0.0` gives hard shadows.
+    pub pcf_radius: f32,
+    /// Number of cascades for directional lights, `1..=4`. Ignored for other lights.
+    pub cascades: u32,
+    /// Distance from the camera covered by directional light cascades.
+    pub max_distance: f32,
+    /// How cascade splits are spread over `max_distance`, from `0.0` for even slices to
+    /// `1.0` for logarithmic ones, which give nearby cascades more detail.
+    pub cascade_split: f32,
+    /// How far towards a directional light, past the slice of the view a cascade covers,
+    /// casters are still drawn into it. Casters further out, such as tall buildings or
+    /// mountains, cast no shadow into the cascade.
+    pub caster_distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 1024,
+            depth_bias: 0.002,
+            normal_bias: 0.05,
+            pcf_radius: 1.0,
+            cascades: 3,
+            max_distance: 150.0,
+            cascade_split: 0.6,
+            caster_distance: 100.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowLayerRaw {
+    view_proj: [[f32; 4]; 4],
+    /// depth bias, normal bias, cascade far distance, pcf radius
+    params: [f32; 4],
+    /// uv offset, uv scale, texel size
+    tile: [f32; 4],
+    page: i32,
+    _padding: [u32; 3],
+}
+
+/// Square region of an atlas page holding one shadow map.
+#[derive(Clone, Copy, Debug)]
+struct Tile {
+    page: u32,
+    x: u32,
+    y: u32,
+    size: u32,
+}
+
+/// Packs power-of-two tiles into square pages by repeatedly splitting free squares
+/// into quadrants. Tiles have to be allocated largest first for the packing to stay tight.
+struct TilePacker {
+    page_resolution: u32,
+    pages: u32,
+    free: Vec<Tile>,
+}
+
+impl TilePacker {
+    fn new(page_resolution: u32) -> Self {
+        TilePacker { page_resolution, pages: 0, free: Vec::new() }
+    }
+
+    fn allocate(&mut self, size: u32) -> Tile {
+        let best = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| tile.size >= size)
+            .min_by_key(|(_, tile)| (tile.size, tile.page))
+            .map(|(i, _)| i);
+
+        let mut tile = match best {
+            Some(i) => self.free.swap_remove(i),
+            None => {
+                self.pages += 1;
+                Tile { page: self.pages - 1, x: 0, y: 0, size: self.page_resolution }
+            }
+        };
+
+        while tile.size > size {
+            let half = tile.size / 2;
+            for (dx, dy) in [(half, 0), (0, half), (half, half)] {
+                self.free.push(Tile { page: tile.page, x: tile.x + dx, y: tile.y + dy, size: half });
+            }
+            tile.size = half;
+        }
+        tile
+    }
+}
+
+/// Depth atlas shared by every shadow-casting light.
+///
+/// Each cascade, spot light and point light face is a shadow layer that gets its own square
+/// tile, sized to the light's resolution, packed into the pages of a 2D array texture. Small
+/// maps share a page instead of each taking a full one.
+///
+/// Point lights use six tiles picked by the major axis in the shader rather than a cube map,
+/// so they pack together with the other lights and work on backends without cube arrays.
+pub(crate) struct ShadowMaps {
+    pipeline: wgpu::RenderPipeline,
+    /// Alpha tests the base color of cutout materials, so their holes let light through.
+    cutout_pipeline: wgpu::RenderPipeline,
+    pub atlas_view: wgpu::TextureView,
+    page_views: Vec<wgpu::TextureView>,
+    pub sampler: wgpu::Sampler,
+    pub layer_buffer: wgpu::Buffer,
+    caster_buffer: wgpu::Buffer,
+    caster_bind_group_layout: wgpu::BindGroupLayout,
+    caster_bind_group: wgpu::BindGroup,
+    page_resolution: u32,
+    page_count: u32,
+    layer_capacity: u32,
+    /// Tile and viewport size of every layer rendered this frame.
+    layer_tiles: Vec<(Tile, u32)>,
+    /// Bumped whenever a resource bound by the light bind group is recreated.
+    pub generation: u64,
+}
+
+impl ShadowMaps {
+    pub(crate) fn new(device: &wgpu::Device, material_layout: &wgpu::BindGroupLayout) -> Self {
+        let caster_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                },
+                count: None,
+            }],
+            label: Some("shadow_caster_bind_group_layout"),
+        });
+
+        let joint_bind_group_layout = SceneRenderer::create_joint_bind_group_layout(device);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&caster_bind_group_layout, &joint_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let cutout_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cutout Shadow Pipeline Layout"),
+            bind_group_layouts: &[&caster_bind_group_layout, &joint_bind_group_layout, material_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+
+        let pipeline = Self::create_pipeline(device, &layout, &shader, "vs_main", None);
+        let cutout_pipeline = Self::create_pipeline(device, &cutout_layout, &shader, "vs_cutout", Some("fs_cutout"));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let (atlas_view, page_views) = Self::create_atlas(device, 1, 1);
+        let layer_buffer = Self::create_layer_buffer(device, 1);
+        let caster_buffer = Self::create_caster_buffer(device, 1);
+        let caster_bind_group = Self::create_caster_bind_group(device, &caster_bind_group_layout, &caster_buffer);
+
+        Self {
+            pipeline,
+            cutout_pipeline,
+            atlas_view,
+            page_views,
+            sampler,
+            layer_buffer,
+            caster_buffer,
+            caster_bind_group_layout,
+            caster_bind_group,
+            page_resolution: 1,
+            page_count: 1,
+            layer_capacity: 1,
+            layer_tiles: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        vertex_entry: &str,
+        fragment_entry: Option<&str>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: vertex_entry,
+                buffers: &[model::ModelVertex::desc(), super::scene::InstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: fragment_entry.map(|entry_point| wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_atlas(device: &wgpu::Device, resolution: u32, pages: u32) -> (wgpu::TextureView, Vec<wgpu::TextureView>) {
+        let atlas = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Atlas"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: pages,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let atlas_view = atlas.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Atlas View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let page_views = (0..pages)
+            .map(|page| {
+                atlas.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Page View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: page,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        (atlas_view, page_views)
+    }
+
+    fn create_layer_buffer(device: &wgpu::Device, layers: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Layer Buffer"),
+            size: layers as u64 * std::mem::size_of::<ShadowLayerRaw>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_caster_buffer(device: &wgpu::Device, layers: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Caster Buffer"),
+            size: layers as u64 * CASTER_STRIDE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_caster_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                }),
+            }],
+            label: Some("shadow_caster_bind_group"),
+        })
+    }
+
+    /// Dynamic offset selecting the caster matrix of `layer`.
+    fn caster_offset(layer: usize) -> u32 {
+        (layer as u64 * CASTER_STRIDE) as u32
+    }
+
+    /// Computes the light matrices for this frame and uploads them.
+    ///
+    /// # Returns
+    ///
+    /// For every light, in `LightManager::iter` order, its first shadow layer
+    /// (`-1` if it casts no shadow) and the number of layers it uses.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &LightManager,
+        camera: &Camera,
+        projection: &Projection,
+    ) -> Vec<(i32, u32)> {
+        let max_resolution = device.limits().max_texture_dimension_2d.min(MAX_ATLAS_RESOLUTION);
+        let mut layers: Vec<(Matrix4<f32>, u32, ShadowSettings, f32)> = Vec::new();
+        let mut assignments = Vec::with_capacity(lights.count());
+
+        for (_, light) in lights.iter() {
+            let Some(settings) = light.shadow else {
+                assignments.push((-1, 0));
+                continue;
+            };
+            let resolution = settings.resolution.clamp(16, max_resolution);
+            let first = layers.len() as i32;
+
+            match light.kind {
+                LightKind::Directional => {
+                    for (view_proj, far) in directional_cascades(light, &settings, resolution, camera, projection) {
+                        layers.push((view_proj, resolution, settings, far));
+                    }
+                }
+                LightKind::Spot => {
+                    let far = if light.range > 0.0 { light.range } else { DEFAULT_SHADOW_FAR };
+                    let view = look_to(light.position, light.direction);
+                    let fov = Rad((light.outer_cone.0 * 2.0).clamp(0.1, 3.0));
+                    let proj = cgmath::perspective(fov, 1.0, SHADOW_NEAR, far);
+                    layers.push((OPENGL_TO_WGPU_MATRIX * proj * view, resolution, settings, far));
+                }
+                LightKind::Point => {
+                    let far = if light.range > 0.0 { light.range } else { DEFAULT_SHADOW_FAR };
+                    let proj = cgmath::perspective(Deg(90.0), 1.0, SHADOW_NEAR, far);
+                    for (direction, up) in CUBE_FACES {
+                        let view = Matrix4::look_to_rh(Point3::from_vec(light.position), direction, up);
+                        layers.push((OPENGL_TO_WGPU_MATRIX * proj * view, resolution, settings, far));
+                    }
+                }
+            }
+
+            assignments.push((first, layers.len() as u32 - first as u32));
+        }
+
+        // Largest tiles first, so the quadrant packing leaves no gaps.
+        let tile_size = |resolution: u32| resolution.next_power_of_two().min(max_resolution);
+        let mut order: Vec<usize> = (0..layers.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(tile_size(layers[i].1)));
+
+        let largest = order.first().map(|&i| tile_size(layers[i].1)).unwrap_or(1);
+        let page_resolution = largest.max(MIN_PAGE_RESOLUTION.min(max_resolution)).max(self.page_resolution);
+        let mut packer = TilePacker::new(page_resolution);
+        let mut tiles = vec![None; layers.len()];
+        for &i in &order {
+            tiles[i] = Some(packer.allocate(tile_size(layers[i].1)));
+        }
+        let tiles: Vec<Tile> = tiles.into_iter().flatten().collect();
+
+        let needed_pages = packer.pages.max(1);
+        if page_resolution > self.page_resolution || needed_pages > self.page_count {
+            self.page_resolution = page_resolution;
+            self.page_count = self.page_count.max(needed_pages);
+            let (atlas_view, page_views) = Self::create_atlas(device, self.page_resolution, self.page_count);
+            self.atlas_view = atlas_view;
+            self.page_views = page_views;
+            self.generation += 1;
+        }
+
+        let needed_layers = (layers.len() as u32).max(1);
+        if needed_layers > self.layer_capacity {
+            self.layer_capacity = needed_layers.next_power_of_two();
+            self.layer_buffer = Self::create_layer_buffer(device, self.layer_capacity);
+            self.caster_buffer = Self::create_caster_buffer(device, self.layer_capacity);
+            self.caster_bind_group = Self::create_caster_bind_group(device, &self.caster_bind_group_layout, &self.caster_buffer);
+            self.generation += 1;
+        }
+
+        let page_resolution = self.page_resolution as f32;
+        let mut raw = Vec::with_capacity(layers.len());
+        let mut casters = vec![0u8; layers.len() * CASTER_STRIDE as usize];
+
+        for (i, ((view_proj, resolution, settings, far), tile)) in layers.iter().zip(&tiles).enumerate() {
+            let matrix: [[f32; 4]; 4] = (*view_proj).into();
+            raw.push(ShadowLayerRaw {
+                view_proj: matrix,
+                params: [settings.depth_bias, settings.normal_bias, *far, settings.pcf_radius],
+                tile: [
+                    tile.x as f32 / page_resolution,
+                    tile.y as f32 / page_resolution,
+                    *resolution as f32 / page_resolution,
+                    1.0 / page_resolution,
+                ],
+                page: tile.page as i32,
+                _padding: [0; 3],
+            });
+
+            let start = i * CASTER_STRIDE as usize;
+            let bytes = bytemuck::cast_slice::<[[f32; 4]; 4], u8>(std::slice::from_ref(&matrix));
+            casters[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        if !raw.is_empty() {
+            queue.write_buffer(&self.layer_buffer, 0, bytemuck::cast_slice(&raw));
+            queue.write_buffer(&self.caster_buffer, 0, &casters);
+        }
+
+        self.layer_tiles = layers.iter().zip(tiles).map(|(layer, tile)| (tile, layer.1)).collect();
+
+        assignments
+    }
+
+    /// Renders the shadow casters into the tile of every shadow layer prepared this frame.
+    pub(crate) fn render(&self, encoder: &mut wgpu::CommandEncoder, scene: &SceneRenderer) {
+        for (page, view) in self.page_views.iter().enumerate() {
+            let mut layers = self
+                .layer_tiles
+                .iter()
+                .enumerate()
+                .filter(|(_, (tile, _))| tile.page as usize == page)
+                .peekable();
+            if layers.peek().is_none() {
+                continue;
+            }
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            for (layer, (tile, resolution)) in layers {
+                pass.set_viewport(tile.x as f32, tile.y as f32, *resolution as f32, *resolution as f32, 0.0, 1.0);
+                pass.set_scissor_rect(tile.x, tile.y, *resolution, *resolution);
+                pass.set_bind_group(0, &self.caster_bind_group, &[Self::caster_offset(layer)]);
+                scene.draw_shadow_casters(&mut pass, &self.pipeline, &self.cutout_pipeline);
+            }
+        }
+    }
+
+    /// Number of shadow map layers rendered each frame.
+    pub(crate) fn layer_count(&self) -> u32 {
+        self.layer_tiles.len() as u32
+    }
+}
+
+/// Face order matches the selection in `shader.wgsl`: +X, -X, +Y, -Y, +Z, -Z.
+const CUBE_FACES: [(Vector3<f32>, Vector3<f32>); 6] = [
+    (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)),
+    (Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, -1.0)),
+    (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, -1.0, 0.0)),
+    (Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, -1.0, 0.0)),
+];
+
+fn look_to(position: Vector3<f32>, direction: Vector3<f32>) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    Matrix4::look_to_rh(Point3::from_vec(position), direction, up)
+}
+
+/// Splits the camera frustum into cascades and fits an orthographic light frustum
+/// around the bounding sphere of each slice.
+///
+/// The sphere only changes size with the projection, and its center is snapped to whole
+/// texels of a `resolution` sized map in light space, so shadow edges stay put while the
+/// camera moves or turns.
+fn directional_cascades(
+    light: &Light,
+    settings: &ShadowSettings,
+    resolution: u32,
+    camera: &Camera,
+    projection: &Projection,
+) -> Vec<(Matrix4<f32>, f32)> {
+    let cascades = settings.cascades.clamp(1, 4);
+    let near = projection.znear();
+    let far = settings.max_distance.min(projection.zfar()).max(near + 1.0);
+    let inverse_view = camera.calc_matrix().invert().unwrap_or(Matrix4::identity());
+    let tan_half = (projection.fovy().0 / 2.0).tan();
+    let aspect = projection.aspect();
+
+    // Practical split scheme: blend of logarithmic and uniform splits
+    let lambda = settings.cascade_split.clamp(0.0, 1.0);
+    let split = |i: u32| {
+        let t = i as f32 / cascades as f32;
+        let log = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        lambda * log + (1.0 - lambda) * uniform
+    };
+
+    (0..cascades)
+        .map(|i| {
+            let slice_near = split(i);
+            let slice_far = split(i + 1);
+
+            let mut corners = Vec::with_capacity(8);
+            for depth in [slice_near, slice_far] {
+                let h = depth * tan_half;
+                let w = h * aspect;
+                for (x, y) in [(-w, -h), (w, -h), (w, h), (-w, h)] {
+                    let world = inverse_view * Vector4::new(x, y, -depth, 1.0);
+                    corners.push(world.truncate());
+                }
+            }
+
+            let center = corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, c| acc + c) / corners.len() as f32;
+            let radius = corners.iter().map(|c| (c - center).magnitude()).fold(0.0, f32::max).max(1.0);
+            // Rounded up so float error in the corners doesn't rescale the map every frame
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let direction = light.direction.normalize();
+            let light_rotation = look_to(Vector3::new(0.0, 0.0, 0.0), direction);
+            let texel = 2.0 * radius / resolution as f32;
+            let light_center = light_rotation * center.extend(1.0);
+            let snapped = Vector4::new(
+                (light_center.x / texel).floor() * texel,
+                (light_center.y / texel).floor() * texel,
+                light_center.z,
+                1.0,
+            );
+            let center = (light_rotation.invert().unwrap_or(Matrix4::identity()) * snapped).truncate();
+
+            // Pull the eye back so casters between the light and the slice are included.
+            let backoff = radius + settings.caster_distance.max(0.0);
+            let view = look_to(center - direction * backoff, direction);
+            let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, backoff + radius);
+
+            (OPENGL_TO_WGPU_MATRIX * proj * view, slice_far)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlap(a: &Tile, b: &Tile) -> bool {
+        a.page == b.page && a.x < b.x + b.size && b.x < a.x + a.size && a.y < b.y + b.size && b.y < a.y + a.size
+    }
+
+    #[test]
+    fn small_tiles_share_a_page() {
+        let mut packer = TilePacker::new(2048);
+        let tiles: Vec<Tile> = [1024, 1024, 512, 512, 512, 512, 1024].iter().map(|&size| packer.allocate(size)).collect();
+        assert_eq!(packer.pages, 1);
+        for (i, a) in tiles.iter().enumerate() {
+            assert!(a.x + a.size <= 2048 && a.y + a.size <= 2048);
+            for b in &tiles[i + 1..] {
+                assert!(!overlap(a, b), "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn full_pages_open_a_new_one() {
+        let mut packer = TilePacker::new(1024);
+        let first = packer.allocate(1024);
+        let second = packer.allocate(512);
+        assert_eq!((first.page, first.size), (0, 1024));
+        assert_eq!((second.page, second.x, second.y, second.size), (1, 0, 0, 512));
+
+        // The remaining quadrants of the second page are used before a third one opens
+        for _ in 0..3 {
+            assert_eq!(packer.allocate(512).page, 1);
+        }
+        assert_eq!(packer.pages, 2);
+        assert_eq!(packer.allocate(256).page, 2);
+    }
+
+    /// Texel coordinates of a world point in each cascade, as seen from `camera`.
+    fn texel_positions(camera: &Camera, point: Vector3<f32>) -> Vec<(f32, f32)> {
+        let light = Light::directional(Vector3::new(-0.3, -1.0, -0.4), [1.0; 3]);
+        let projection = Projection::new(1280, 720, Deg(45.0), 0.1, 500.0);
+        directional_cascades(&light, &ShadowSettings::default(), 1024, camera, &projection)
+            .into_iter()
+            .map(|(view_proj, _)| {
+                let clip = view_proj * point.extend(1.0);
+                ((clip.x / clip.w + 1.0) * 512.0, (clip.y / clip.w + 1.0) * 512.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cascades_stay_on_the_texel_grid_while_the_camera_moves() {
+        let point = Vector3::new(3.0, 0.0, -8.0);
+        let still = texel_positions(&Camera::new((0.0, 2.0, 0.0), Deg(-90.0), Deg(-10.0)), point);
+        let moved = texel_positions(&Camera::new((0.37, 2.21, -0.13), Deg(-83.0), Deg(-12.5)), point);
+        for ((x, y), (moved_x, moved_y)) in still.iter().zip(&moved) {
+            // The point may land on another texel, but always at the same spot within it
+            assert!(((x - moved_x).round() - (x - moved_x)).abs() < 0.01, "{x} and {moved_x}");
+            assert!(((y - moved_y).round() - (y - moved_y)).abs() < 0.01, "{y} and {moved_y}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Depth-only pass rendering shadow casters from a light
+
+@group(0) @binding(0)
+var<uniform> light_view_proj: mat4x4<f32>;
+@group(1) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+// Only bound for cutout casters, which discard the same fragments as in `shader.wgsl`
+@group(2) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(2) @binding(1)
+var s_base_color: sampler;
+
+struct MaterialFactors {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+}
+@group(2) @binding(10)
+var<uniform> material: MaterialFactors;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(14) joint_offset: u32,
+}
+
+struct CutoutOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+fn light_clip_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var position = vec4<f32>(model.position, 1.0);
+    if (dot(model.weights, vec4<f32>(1.0)) > 0.0) {
+        let offset = instance.joint_offset;
+        position = (joint_matrices[offset + model.joints.x] * model.weights.x
+            + joint_matrices[offset + model.joints.y] * model.weights.y
+            + joint_matrices[offset + model.joints.z] * model.weights.z
+            + joint_matrices[offset + model.joints.w] * model.weights.w) * position;
+    }
+    return light_view_proj * model_matrix * position;
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return light_clip_position(model, instance);
+}
+
+@vertex
+fn vs_cutout(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> CutoutOutput {
+    var out: CutoutOutput;
+    out.clip_position = light_clip_position(model, instance);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_cutout(in: CutoutOutput) {
+    let alpha = textureSample(t_base_color, s_base_color, in.tex_coords).a * material.base_color.a;
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+}
@@ -0,0 +1,620 @@
+use super::assets::AssetServer;
+use super::texture;
+
+const CUBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIPS: u32 = 6;
+const PREFILTER_SAMPLES: u32 = 256;
+const BRDF_LUT_SIZE: u32 = 256;
+
+/// Images a skybox is built from, relative to the `res` directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkyboxSource {
+    /// A single latitude-longitude panorama, typically an `.hdr` file.
+    Equirectangular(String),
+    /// Six square faces in the order +X, -X, +Y, -Y, +Z, -Z.
+    Cubemap([String; 6]),
+}
+
+/// Sky drawn behind the scene, which also lights it through image-based lighting.
+///
+/// Changing `source` or `resolution` reloads the sky and rebakes the lighting maps on the
+/// next frame; the intensities can be changed freely.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skybox {
+    pub source: SkyboxSource,
+    /// Brightness of the visible sky.
+    pub intensity: f32,
+    /// Strength of the ambient diffuse and specular light taken from the sky.
+    pub ibl_intensity: f32,
+    /// Edge length of the sky cube map faces.
+    pub resolution: u32,
+}
+
+impl Skybox {
+    pub fn equirectangular(file_name: &str) -> Self {
+        Skybox {
+            source: SkyboxSource::Equirectangular(file_name.to_string()),
+            intensity: 1.0,
+            ibl_intensity: 1.0,
+            resolution: 512,
+        }
+    }
+
+    pub fn cubemap(faces: [&str; 6]) -> Self {
+        Skybox {
+            source: SkyboxSource::Cubemap(faces.map(str::to_string)),
+            ..Skybox::equirectangular("")
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_ibl_intensity(mut self, intensity: f32) -> Self {
+        self.ibl_intensity = intensity;
+        self
+    }
+
+    pub fn with_resolution(mut self, resolution: u32) -> Self {
+        self.resolution = resolution;
+        self
+    }
+}
+
+/// Cube maps sampled by the material and sky shaders.
+pub(crate) struct IblMaps {
+    pub sky: wgpu::TextureView,
+    pub irradiance: wgpu::TextureView,
+    pub prefiltered: wgpu::TextureView,
+    pub prefiltered_mips: u32,
+}
+
+impl IblMaps {
+    /// Black 1x1 maps bound while no skybox is set.
+    pub(crate) fn placeholder(device: &wgpu::Device) -> Self {
+        let black = || {
+            let texture = create_cube(device, 1, 1, "Placeholder Cube");
+            cube_view(&texture)
+        };
+        // Fresh textures are zero-initialized, which is black in every format
+        Self {
+            sky: black(),
+            irradiance: black(),
+            prefiltered: black(),
+            prefiltered_mips: 1,
+        }
+    }
+}
+
+fn create_cube(device: &wgpu::Device, size: u32, mips: u32, label: &str) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: CUBE_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+/// All six faces of one mip level, as the storage target of a compute pass.
+fn mip_view(texture: &wgpu::Texture, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParams {
+    roughness: f32,
+    source_size: f32,
+    sample_count: u32,
+    _padding: u32,
+}
+
+struct BakePass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+/// Compute pipelines turning sky images into the maps of `IblMaps`.
+pub(crate) struct IblBaker {
+    equirect_to_cube: BakePass,
+    faces_to_cube: BakePass,
+    downsample: BakePass,
+    irradiance: BakePass,
+    prefilter: BakePass,
+    sampler: wgpu::Sampler,
+    pub brdf_lut: wgpu::TextureView,
+}
+
+impl IblBaker {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("IBL Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ibl.wgsl").into()),
+        });
+
+        let source_texture = |binding, view_dimension, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension,
+            },
+            count: None,
+        };
+        let storage = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: CUBE_FORMAT,
+                view_dimension,
+            },
+            count: None,
+        };
+        let sampler = wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let params = wgpu::BindGroupLayoutEntry {
+            binding: 7,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cube_target = storage(5, wgpu::TextureViewDimension::D2Array);
+
+        let pass = |entry_point: &str, entries: &[wgpu::BindGroupLayoutEntry]| {
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(entry_point),
+                entries,
+            });
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(entry_point),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            });
+            BakePass { layout, pipeline }
+        };
+
+        let equirect_to_cube = pass("equirect_to_cube", &[
+            source_texture(0, wgpu::TextureViewDimension::D2, false),
+            cube_target,
+        ]);
+        let faces_to_cube = pass("faces_to_cube", &[
+            source_texture(1, wgpu::TextureViewDimension::D2Array, false),
+            cube_target,
+        ]);
+        let downsample = pass("downsample", &[
+            source_texture(2, wgpu::TextureViewDimension::D2Array, false),
+            cube_target,
+        ]);
+        let irradiance = pass("irradiance", &[
+            source_texture(3, wgpu::TextureViewDimension::Cube, true),
+            sampler,
+            cube_target,
+        ]);
+        let prefilter = pass("prefilter", &[
+            source_texture(3, wgpu::TextureViewDimension::Cube, true),
+            sampler,
+            cube_target,
+            params,
+        ]);
+        let brdf = pass("brdf_lut", &[storage(6, wgpu::TextureViewDimension::D2)]);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Bake Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The lookup table does not depend on the sky, so it is baked once
+        let lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: CUBE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let brdf_lut = lut.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("brdf_lut_bind_group"),
+            layout: &brdf.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&brdf_lut),
+            }],
+        });
+        dispatch(&mut encoder, &brdf.pipeline, &bind_group, BRDF_LUT_SIZE, 1);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self {
+            equirect_to_cube,
+            faces_to_cube,
+            downsample,
+            irradiance,
+            prefilter,
+            sampler,
+            brdf_lut,
+        }
+    }
+
+    /// Loads the sky images and bakes the sky, irradiance and prefiltered cube maps.
+    pub(crate) fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        skybox: &Skybox,
+        assets: &AssetServer,
+    ) -> anyhow::Result<IblMaps> {
+        let size = skybox.resolution.clamp(16, device.limits().max_texture_dimension_2d);
+        let sky_mips = size.ilog2() + 1;
+        let sky = create_cube(device, size, sky_mips, "Sky Cube");
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Bake Encoder"),
+        });
+
+        // Source images into the top mip of the sky
+        let target = mip_view(&sky, 0);
+        let (pass, source_binding, source) = match &skybox.source {
+            SkyboxSource::Equirectangular(file_name) => {
+                let image = load_linear_image(assets, file_name)?;
+                let view = upload_float_texture(device, queue, &[image], file_name)?;
+                (&self.equirect_to_cube, 0, view)
+            }
+            SkyboxSource::Cubemap(faces) => {
+                let images = faces
+                    .iter()
+                    .map(|face| load_linear_image(assets, face))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let view = upload_float_texture(device, queue, &images, &faces[0])?;
+                (&self.faces_to_cube, 1, view)
+            }
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sky_source_bind_group"),
+            layout: &pass.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: source_binding,
+                    resource: wgpu::BindingResource::TextureView(&source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&target),
+                },
+            ],
+        });
+        dispatch(&mut encoder, &pass.pipeline, &bind_group, size, 6);
+
+        // Mip chain, so filtering passes can read a resolution matching their footprint
+        for mip in 1..sky_mips {
+            let source = mip_view(&sky, mip - 1);
+            let target = mip_view(&sky, mip);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("sky_downsample_bind_group"),
+                layout: &self.downsample.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(&target),
+                    },
+                ],
+            });
+            dispatch(&mut encoder, &self.downsample.pipeline, &bind_group, (size >> mip).max(1), 6);
+        }
+
+        let sky_view = cube_view(&sky);
+
+        let irradiance = create_cube(device, IRRADIANCE_SIZE, 1, "Irradiance Cube");
+        let target = mip_view(&irradiance, 0);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("irradiance_bind_group"),
+            layout: &self.irradiance.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&sky_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&target),
+                },
+            ],
+        });
+        dispatch(&mut encoder, &self.irradiance.pipeline, &bind_group, IRRADIANCE_SIZE, 6);
+
+        let prefiltered = create_cube(device, PREFILTERED_SIZE, PREFILTERED_MIPS, "Prefiltered Cube");
+        for mip in 0..PREFILTERED_MIPS {
+            let params = FilterParams {
+                roughness: mip as f32 / (PREFILTERED_MIPS - 1) as f32,
+                source_size: size as f32,
+                sample_count: PREFILTER_SAMPLES,
+                _padding: 0,
+            };
+            let params = wgpu::util::DeviceExt::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: Some("Prefilter Params"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let target = mip_view(&prefiltered, mip);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("prefilter_bind_group"),
+                layout: &self.prefilter.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&sky_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(&target),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            });
+            dispatch(&mut encoder, &self.prefilter.pipeline, &bind_group, (PREFILTERED_SIZE >> mip).max(1), 6);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(IblMaps {
+            sky: sky_view,
+            irradiance: cube_view(&irradiance),
+            prefiltered: cube_view(&prefiltered),
+            prefiltered_mips: PREFILTERED_MIPS,
+        })
+    }
+}
+
+fn dispatch(encoder: &mut wgpu::CommandEncoder, pipeline: &wgpu::ComputePipeline, bind_group: &wgpu::BindGroup, size: u32, layers: u32) {
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+        label: Some("IBL Bake Pass"),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.dispatch_workgroups(size.div_ceil(8), size.div_ceil(8), layers);
+}
+
+/// Loads an image as linear RGBA floats; 8-bit images are assumed to be sRGB encoded.
+fn load_linear_image(assets: &AssetServer, file_name: &str) -> anyhow::Result<image::Rgba32FImage> {
+    let path = assets.resolve(file_name)?;
+    let image = image::load_from_memory(&std::fs::read(&path)?)?;
+    let is_float = matches!(image, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+    let mut rgba = image.to_rgba32f();
+
+    if !is_float {
+        for pixel in rgba.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = if *channel <= 0.04045 {
+                    *channel / 12.92
+                } else {
+                    ((*channel + 0.055) / 1.055).powf(2.4)
+                };
+            }
+        }
+    }
+
+    Ok(rgba)
+}
+
+/// Uploads equally sized images as the layers of an unfilterable `Rgba32Float` texture.
+fn upload_float_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    images: &[image::Rgba32FImage],
+    label: &str,
+) -> anyhow::Result<wgpu::TextureView> {
+    let (width, height) = images[0].dimensions();
+    anyhow::ensure!(
+        images.iter().all(|image| image.dimensions() == (width, height)),
+        "all skybox faces must have the same size",
+    );
+
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: images.len() as u32,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    for (layer, image) in images.iter().enumerate() {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+            },
+            bytemuck::cast_slice(image.as_raw()),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    let dimension = if images.len() == 1 {
+        wgpu::TextureViewDimension::D2
+    } else {
+        wgpu::TextureViewDimension::D2Array
+    };
+    Ok(texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(dimension),
+        ..Default::default()
+    }))
+}
+
+/// Draws the sky cube behind the scene.
+pub(crate) struct SkyboxRenderer {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkyboxRenderer {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        environment_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, environment_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Drawn after the opaque geometry, only where nothing else was rendered
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self { pipeline }
+    }
+
+    pub(crate) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        environment_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, environment_bind_group, &[]);
+        render_pass.draw(0..36, 0..1);
+    }
+}
@@ -0,0 +1,66 @@
+// Sky cube drawn around the camera at the far plane, behind everything else
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Environment {
+    fog_color: vec3<f32>,
+    fog_mode: u32,
+    fog_params: vec4<f32>,
+    height_params: vec4<f32>,
+    // intensity, max lod, enabled, sky intensity
+    ibl_params: vec4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> environment: Environment;
+@group(1) @binding(4)
+var s_environment: sampler;
+@group(1) @binding(5)
+var t_sky: texture_cube<f32>;
+
+var<private> CUBE_CORNERS: array<vec3<f32>, 8> = array<vec3<f32>, 8>(
+    vec3<f32>(-1.0, -1.0, -1.0),
+    vec3<f32>(1.0, -1.0, -1.0),
+    vec3<f32>(1.0, 1.0, -1.0),
+    vec3<f32>(-1.0, 1.0, -1.0),
+    vec3<f32>(-1.0, -1.0, 1.0),
+    vec3<f32>(1.0, -1.0, 1.0),
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(-1.0, 1.0, 1.0),
+);
+
+var<private> CUBE_INDICES: array<u32, 36> = array<u32, 36>(
+    0u, 1u, 2u, 2u, 3u, 0u,
+    4u, 6u, 5u, 6u, 4u, 7u,
+    0u, 3u, 7u, 7u, 4u, 0u,
+    1u, 5u, 6u, 6u, 2u, 1u,
+    3u, 2u, 6u, 6u, 7u, 3u,
+    0u, 4u, 5u, 5u, 1u, 0u,
+);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let corner = CUBE_CORNERS[CUBE_INDICES[index]];
+    let clip = camera.view_proj * vec4<f32>(camera.view_pos.xyz + corner, 1.0);
+
+    var out: VertexOutput;
+    // z = w puts every fragment exactly on the far plane
+    out.clip_position = clip.xyww;
+    out.direction = corner;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_sky, s_environment, normalize(in.direction), 0.0).rgb;
+    return vec4<f32>(color * environment.ibl_params.w, 1.0);
+}
@@ -1,11 +1,132 @@
 use anyhow::*;
 use image::GenericImageView;
+use std::sync::Arc;
 
+/// How texture coordinates outside `0.0..=1.0` are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressMode {
+    /// Tiles the texture.
+    #[default]
+    Repeat,
+    /// Tiles the texture, flipping every other copy.
+    MirrorRepeat,
+    /// Stretches the edge texels.
+    ClampToEdge,
+}
+
+impl AddressMode {
+    fn to_wgpu(self) -> wgpu::AddressMode {
+        match self {
+            AddressMode::Repeat => wgpu::AddressMode::Repeat,
+            AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+            AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Takes the closest texel, for a blocky look.
+    Nearest,
+    /// Blends the closest texels.
+    #[default]
+    Linear,
+}
+
+impl FilterMode {
+    fn to_wgpu(self) -> wgpu::FilterMode {
+        match self {
+            FilterMode::Nearest => wgpu::FilterMode::Nearest,
+            FilterMode::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// How the textures of a material are sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerSettings {
+    pub address_mode_u: AddressMode,
+    pub address_mode_v: AddressMode,
+    /// Filter used when a texel covers more than a pixel.
+    pub mag_filter: FilterMode,
+    /// Filter used when a pixel covers more than a texel.
+    pub min_filter: FilterMode,
+    /// Filter between mipmap levels; `Nearest` shows a seam where the level changes.
+    pub mipmap_filter: FilterMode,
+    /// Maximum anisotropy, `1..=16`. Higher values keep surfaces seen at a grazing angle,
+    /// such as floors, sharp in the distance. Only used when all filters are `Linear`, and
+    /// ignored by GPUs without anisotropic filtering.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            anisotropy: 8,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_address_mode(mut self, address_mode: AddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self.address_mode_v = address_mode;
+        self
+    }
+
+    /// Uses `filter` for magnification, minification and between mipmap levels.
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.mag_filter = filter;
+        self.min_filter = filter;
+        self.mipmap_filter = filter;
+        self
+    }
+
+    pub fn with_anisotropy(mut self, anisotropy: u16) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub(crate) fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&self.descriptor())
+    }
+
+    fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        // Anisotropic sampling is a validation error unless every filter is linear
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|filter| *filter == FilterMode::Linear);
+        wgpu::SamplerDescriptor {
+            label: Some("texture_sampler"),
+            address_mode_u: self.address_mode_u.to_wgpu(),
+            address_mode_v: self.address_mode_v.to_wgpu(),
+            address_mode_w: self.address_mode_u.to_wgpu(),
+            mag_filter: self.mag_filter.to_wgpu(),
+            min_filter: self.min_filter.to_wgpu(),
+            mipmap_filter: self.mipmap_filter.to_wgpu(),
+            anisotropy_clamp: if linear { self.anisotropy.clamp(1, 16) } else { 1 },
+            ..Default::default()
+        }
+    }
+}
+
+/// A texture with a view and sampler. Clones share the GPU texture, so one image can be
+/// used by several materials, each with a sampler of its own.
+#[derive(Clone)]
 pub struct Texture {
     #[allow(unused)]
-    pub texture: wgpu::Texture,
-    pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    pub texture: Arc<wgpu::Texture>,
+    pub view: Arc<wgpu::TextureView>,
+    pub sampler: Arc<wgpu::Sampler>,
 }
 
 impl Texture {
@@ -13,19 +134,21 @@ impl Texture {
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        width: u32,
+        height: u32,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -47,13 +170,14 @@ impl Texture {
         });
 
         Self {
-            texture,
-            view,
-            sampler,
+            texture: Arc::new(texture),
+            view: Arc::new(view),
+            sampler: Arc::new(sampler),
         }
     }
 
-    #[allow(dead_code)]
+    /// Decodes a PNG, JPEG or HDR image and generates its mipmaps, or uploads a KTX2 or DDS
+    /// file with the BCn compressed levels it contains.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -61,16 +185,25 @@ impl Texture {
         label: &str,
         is_normal_map: bool,
     ) -> Result<Self> {
+        if bytes.starts_with(&KTX2_MAGIC) {
+            return Self::from_ktx2(device, queue, bytes, label, is_normal_map);
+        }
+        if bytes.starts_with(b"DDS ") {
+            return Self::from_dds(device, queue, bytes, label, is_normal_map);
+        }
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_image(device, queue, &img, Some(label), is_normal_map, true)
     }
 
+    /// Uploads an image, followed by successively halved copies of it down to 1x1 when
+    /// `mipmaps` is set. Images that are not normal maps are filtered in linear space.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
         is_normal_map: bool,
+        mipmaps: bool,
     ) -> Result<Self> {
         let dimensions = img.dimensions();
         let rgba = img.to_rgba8();
@@ -85,10 +218,15 @@ impl Texture {
         } else {
             wgpu::TextureFormat::Rgba8UnormSrgb
         };
+        let levels = if mipmaps {
+            generate_mipmaps(&rgba, !is_normal_map)
+        } else {
+            Vec::new()
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: 1 + levels.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
@@ -96,38 +234,146 @@ impl Texture {
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
+        let data = std::iter::once(&rgba).chain(&levels).map(|level| level.as_raw().as_slice());
+        write_levels(queue, &texture, data);
+
+        Ok(Self::from_texture(device, texture))
+    }
+
+    /// Uploads a KTX2 file holding a 2D texture in a BCn or RGBA8 format, with all of its
+    /// mipmap levels. Supercompressed files are not supported.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|error| anyhow!("{}: invalid KTX2 file: {}", label, error))?;
+        let header = reader.header();
+        ensure!(header.supercompression_scheme.is_none(), "{}: supercompressed KTX2 files are not supported", label);
+        ensure!(
+            header.pixel_depth <= 1 && header.layer_count <= 1 && header.face_count == 1,
+            "{}: only 2D KTX2 textures are supported",
+            label
         );
+        let format = header
+            .format
+            .and_then(ktx2_format)
+            .ok_or_else(|| anyhow!("{}: unsupported KTX2 format {:?}", label, header.format))?;
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
+        let size = wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let levels: Vec<&[u8]> = reader.levels().map(|level| level.data).collect();
+        Self::from_compressed(device, queue, label, size, format, is_normal_map, &levels)
+    }
+
+    /// Uploads a DDS file holding a 2D texture in a BCn format, with all of its mipmap levels.
+    pub fn from_dds(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes).map_err(|error| anyhow!("{}: invalid DDS file: {}", label, error))?;
+        ensure!(
+            dds.get_depth() <= 1 && dds.get_num_array_layers() <= 1,
+            "{}: only 2D DDS textures are supported",
+            label
+        );
+        let format = dds
+            .get_dxgi_format()
+            .and_then(dds_format)
+            .ok_or_else(|| anyhow!("{}: unsupported DDS format {:?}", label, dds.get_dxgi_format()))?;
+
+        let size = wgpu::Extent3d {
+            width: dds.get_width(),
+            height: dds.get_height(),
+            depth_or_array_layers: 1,
+        };
+        // The levels of the first layer are stored one after the other
+        let mut data = dds.get_data(0).map_err(|error| anyhow!("{}: invalid DDS file: {}", label, error))?;
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let length = level_byte_length(size, format, level);
+            ensure!(data.len() >= length, "{}: DDS file is truncated", label);
+            let (level, rest) = data.split_at(length);
+            levels.push(level);
+            data = rest;
+        }
+        Self::from_compressed(device, queue, label, size, format, is_normal_map, &levels)
+    }
+
+    fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        is_normal_map: bool,
+        levels: &[&[u8]],
+    ) -> Result<Self> {
+        // Normal maps hold vectors rather than colors, whatever the file says
+        let format = if is_normal_map { format.remove_srgb_suffix() } else { format };
+        let required = format.required_features();
+        ensure!(
+            device.features().contains(required),
+            "{}: {:?} textures are not supported by this GPU",
+            label,
+            format
+        );
+        let (block_width, block_height) = format.block_dimensions();
+        ensure!(
+            size.width.is_multiple_of(block_width) && size.height.is_multiple_of(block_height),
+            "{}: {}x{} is not a multiple of the {}x{} block size",
+            label,
+            size.width,
+            size.height,
+            block_width,
+            block_height
+        );
+        let mip_level_count = (levels.len() as u32).clamp(1, size.max_mips(wgpu::TextureDimension::D2));
+        for (level, data) in levels.iter().take(mip_level_count as usize).enumerate() {
+            ensure!(
+                data.len() >= level_byte_length(size, format, level as u32),
+                "{}: mipmap level {} is truncated",
+                label,
+                level
+            );
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
         });
+        write_levels(queue, &texture, levels.iter().copied().take(mip_level_count as usize));
+
+        Ok(Self::from_texture(device, texture))
+    }
+
+    fn from_texture(device: &wgpu::Device, texture: wgpu::Texture) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = SamplerSettings::default().create_sampler(device);
+        Self {
+            texture: Arc::new(texture),
+            view: Arc::new(view),
+            sampler: Arc::new(sampler),
+        }
+    }
 
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-        })
+    /// Replaces the sampler, e.g. with the one a glTF file asks for.
+    pub fn set_sampler(&mut self, device: &wgpu::Device, settings: &SamplerSettings) {
+        self.sampler = Arc::new(settings.create_sampler(device));
     }
 
     pub fn from_color(
@@ -183,22 +429,172 @@ impl Texture {
             size,
         );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Self::from_texture(device, texture))
+    }
+}
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+const KTX2_MAGIC: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as T;
+
+    Some(match format {
+        K::R8G8B8A8_UNORM => T::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => T::Rgba8UnormSrgb,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => T::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => T::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => T::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => T::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => T::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => T::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => T::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => T::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => T::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => T::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => T::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => T::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => T::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => T::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Maps the BCn formats of a DDS file; the legacy DXT1, DXT3 and DXT5 codes come
+/// through as their sRGB variants.
+fn dds_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as T;
+
+    Some(match format {
+        D::BC1_Typeless | D::BC1_UNorm => T::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => T::Bc1RgbaUnormSrgb,
+        D::BC2_Typeless | D::BC2_UNorm => T::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => T::Bc2RgbaUnormSrgb,
+        D::BC3_Typeless | D::BC3_UNorm => T::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => T::Bc3RgbaUnormSrgb,
+        D::BC4_Typeless | D::BC4_UNorm => T::Bc4RUnorm,
+        D::BC4_SNorm => T::Bc4RSnorm,
+        D::BC5_Typeless | D::BC5_UNorm => T::Bc5RgUnorm,
+        D::BC5_SNorm => T::Bc5RgSnorm,
+        D::BC6H_Typeless | D::BC6H_UF16 => T::Bc6hRgbUfloat,
+        D::BC6H_SF16 => T::Bc6hRgbFloat,
+        D::BC7_Typeless | D::BC7_UNorm => T::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => T::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Size of a mipmap level, rounded up to whole blocks.
+fn level_extent(size: wgpu::Extent3d, format: wgpu::TextureFormat, level: u32) -> wgpu::Extent3d {
+    size.mip_level_size(level, wgpu::TextureDimension::D2).physical_size(format)
+}
+
+fn level_byte_length(size: wgpu::Extent3d, format: wgpu::TextureFormat, level: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    let extent = level_extent(size, format, level);
+    ((extent.width / block_width) * (extent.height / block_height) * block_size) as usize
+}
+
+/// Writes tightly packed mipmap levels, starting with the full-size one.
+fn write_levels<'a>(queue: &wgpu::Queue, texture: &wgpu::Texture, levels: impl IntoIterator<Item = &'a [u8]>) {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    for (level, data) in levels.into_iter().enumerate() {
+        let extent = level_extent(texture.size(), format, level as u32);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: level as u32,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(extent.width / block_width * block_size),
+                rows_per_image: Some(extent.height / block_height),
+            },
+            extent,
+        );
+    }
+}
+
+/// Halves the image until it is 1x1, returning every level below the full-size one.
+///
+/// Colors are averaged after decoding them from sRGB, otherwise bright details on a dark
+/// background would darken in the distance.
+fn generate_mipmaps(image: &image::RgbaImage, srgb: bool) -> Vec<image::RgbaImage> {
+    let decode = |value: u8| {
+        let value = value as f32 / 255.0;
+        match srgb {
+            false => value,
+            true if value <= 0.04045 => value / 12.92,
+            true => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    };
+    let encode = |value: f32| {
+        let value = match srgb {
+            false => value,
+            true if value <= 0.0031308 => value * 12.92,
+            true => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+        };
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+    let alpha = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let decoded: Vec<f32> = (0..=255).map(decode).collect();
+
+    let mut level = image::Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        image::Rgba([decoded[r as usize], decoded[g as usize], decoded[b as usize], a as f32 / 255.0])
+    });
+
+    let mut levels = Vec::new();
+    while level.width() > 1 || level.height() > 1 {
+        level = image::imageops::resize(
+            &level,
+            (level.width() / 2).max(1),
+            (level.height() / 2).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        levels.push(image::RgbaImage::from_fn(level.width(), level.height(), |x, y| {
+            let [r, g, b, a] = level.get_pixel(x, y).0;
+            image::Rgba([encode(r), encode(g), encode(b), alpha(a)])
+        }));
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampler_settings_map_to_wgpu() {
+        let settings = SamplerSettings {
+            address_mode_u: AddressMode::MirrorRepeat,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            anisotropy: 4,
+        };
+        let descriptor = settings.descriptor();
+        assert_eq!(descriptor.address_mode_u, wgpu::AddressMode::MirrorRepeat);
+        assert_eq!(descriptor.address_mode_v, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(descriptor.mag_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(descriptor.min_filter, wgpu::FilterMode::Linear);
+        assert_eq!(descriptor.mipmap_filter, wgpu::FilterMode::Linear);
+    }
 
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-        })
+    #[test]
+    fn anisotropy_needs_linear_filters_and_is_clamped() {
+        assert_eq!(SamplerSettings::new().with_anisotropy(64).descriptor().anisotropy_clamp, 16);
+        assert_eq!(SamplerSettings::new().with_anisotropy(0).descriptor().anisotropy_clamp, 1);
+        let nearest = SamplerSettings::new().with_filter(FilterMode::Nearest).with_anisotropy(8);
+        assert_eq!(nearest.descriptor().anisotropy_clamp, 1);
+        assert_eq!(nearest.descriptor().mipmap_filter, wgpu::FilterMode::Nearest);
     }
 }
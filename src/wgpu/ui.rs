@@ -0,0 +1,408 @@
+use std::time::Instant;
+
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::physics::PhysicsWorld;
+use crate::Time;
+use super::scene::SceneManager;
+
+/// Immediate-mode debug UI drawn with egui over the finished frame.
+///
+/// Gears draw their own windows on `GearEvent::Ui`, which carries the egui context; the
+/// built-in stats panel shows frame time, physics step time, body count and draw calls.
+/// While the UI wants the keyboard, key presses are not passed on to the gears.
+///
+/// While the UI is on, the cursor is freed and shown and mouse motion is not passed on to
+/// the gears, whatever `WindowConfig` asks for; the configured grab comes back when it is
+/// turned off.
+///
+/// Scene shaders that fail to hot reload have their error shown in a window of its own,
+/// even while the UI is off, until they compile again.
+pub struct DebugUi {
+    /// Whether the UI is drawn and takes input.
+    pub enabled: bool,
+    /// Whether the built-in stats panel is shown.
+    pub stats: bool,
+    /// Key that toggles `enabled` while the game runs; `None` disables the shortcut.
+    pub toggle_key: Option<KeyCode>,
+    context: egui::Context,
+    input: egui::RawInput,
+    start: Instant,
+    pointer: egui::Pos2,
+    pixels_per_point: f32,
+    output: Option<UiOutput>,
+    resend_fonts: bool,
+    /// Error of the last reloaded scene shaders, copied from the renderer every frame.
+    pub(crate) shader_error: Option<String>,
+}
+
+/// What the last UI frame painted, waiting for the renderer.
+pub(crate) struct UiOutput {
+    pub primitives: Vec<egui::ClippedPrimitive>,
+    pub textures: egui::TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+impl Default for DebugUi {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stats: true,
+            toggle_key: Some(KeyCode::F1),
+            context: egui::Context::default(),
+            input: egui::RawInput::default(),
+            start: Instant::now(),
+            pointer: egui::Pos2::ZERO,
+            pixels_per_point: 1.0,
+            output: None,
+            resend_fonts: false,
+            shader_error: None,
+        }
+    }
+}
+
+impl DebugUi {
+    pub fn context(&self) -> &egui::Context {
+        &self.context
+    }
+
+    /// Turns a window event into egui input. Returns whether the UI uses the event, in
+    /// which case the gears should not see it.
+    pub(crate) fn on_window_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let modifiers = self.input.modifiers;
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+                false
+            }
+            WindowEvent::Focused(focused) => {
+                self.input.events.push(egui::Event::WindowFocused(*focused));
+                false
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                let state = state.state();
+                self.input.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: cfg!(target_os = "macos") && state.super_key(),
+                    command: if cfg!(target_os = "macos") { state.super_key() } else { state.control_key() },
+                };
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = egui::pos2(position.x as f32, position.y as f32) / self.pixels_per_point;
+                self.input.events.push(egui::Event::PointerMoved(self.pointer));
+                self.context.is_using_pointer()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let Some(button) = pointer_button(*button) else {
+                    return false;
+                };
+                self.input.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers,
+                });
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (egui::MouseWheelUnit::Line, egui::vec2(*x, *y)),
+                    MouseScrollDelta::PixelDelta(delta) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(delta.x as f32, delta.y as f32) / self.pixels_per_point,
+                    ),
+                };
+                self.input.events.push(egui::Event::MouseWheel { unit, delta, modifiers });
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    if let Some(key) = key(code) {
+                        self.input.events.push(egui::Event::Key {
+                            key,
+                            physical_key: Some(key),
+                            pressed,
+                            repeat: event.repeat,
+                            modifiers,
+                        });
+                    }
+                }
+                // Shortcuts like ctrl+a are keys, not text
+                if let Some(text) = event.text.as_ref().filter(|_| pressed && !modifiers.command) {
+                    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+                    if !text.is_empty() {
+                        self.input.events.push(egui::Event::Text(text));
+                    }
+                }
+                self.context.wants_keyboard_input()
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts a UI frame with the input gathered since the last one and returns the
+    /// context gears draw into.
+    pub(crate) fn begin_frame(&mut self, size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) -> egui::Context {
+        self.pixels_per_point = scale_factor as f32;
+        // Held modifiers carry over into the next frame's input
+        let mut input = std::mem::take(&mut self.input);
+        self.input.modifiers = input.modifiers;
+        input.time = Some(self.start.elapsed().as_secs_f64());
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(size.width as f32, size.height as f32) / self.pixels_per_point,
+        ));
+        input
+            .viewports
+            .entry(egui::ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(self.pixels_per_point);
+
+        self.context.begin_pass(input);
+        self.context.clone()
+    }
+
+    /// Whether a frame has to be drawn: the UI is on, or there is an error to show.
+    pub(crate) fn wants_frame(&self) -> bool {
+        self.enabled || self.shader_error.is_some()
+    }
+
+    /// Draws the stats panel and the shader error, and finishes the frame begun with
+    /// `begin_frame`.
+    pub(crate) fn end_frame(&mut self, time: &Time, physics: &PhysicsWorld, scene: &SceneManager) {
+        if self.enabled && self.stats {
+            self.stats_panel(time, physics, scene);
+        }
+        if let Some(error) = &self.shader_error {
+            shader_error_window(&self.context, error);
+        }
+
+        let mut output = self.context.end_pass();
+        if std::mem::take(&mut self.resend_fonts) {
+            // Goes before this frame's own font patches, which only apply on top of it
+            let atlas = self.context.fonts(|fonts| fonts.texture_atlas());
+            let atlas = atlas.lock();
+            let delta = egui::epaint::ImageDelta::full(atlas.image().clone(), egui::epaint::TextureAtlas::texture_options());
+            output.textures_delta.set.insert(0, (egui::TextureId::default(), delta));
+        }
+        self.output = Some(UiOutput {
+            primitives: self.context.tessellate(output.shapes, output.pixels_per_point),
+            textures: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        });
+    }
+
+    /// Sends the whole font atlas again with the next frame, for a renderer that was
+    /// rebuilt and holds none of the textures uploaded so far. Textures gears loaded
+    /// through the context are not kept by egui and have to be loaded again.
+    pub(crate) fn reset_textures(&mut self) {
+        // Patches in a frame not yet taken would apply to a texture the renderer lacks
+        self.output = None;
+        self.resend_fonts = true;
+    }
+
+    fn stats_panel(&self, time: &Time, physics: &PhysicsWorld, scene: &SceneManager) {
+        let stats = scene.stats();
+        egui::Window::new("Stats")
+            .default_pos(egui::pos2(8.0, 8.0))
+            .resizable(false)
+            .show(&self.context, |ui| {
+                egui::Grid::new("stats").num_columns(2).show(ui, |ui| {
+                    let mut row = |name: &str, value: String| {
+                        ui.label(name);
+                        ui.label(value);
+                        ui.end_row();
+                    };
+                    row("FPS", format!("{:.0}", time.fps()));
+                    row("Frame time", format!("{:.2} ms", time.delta_time() * 1000.0));
+                    row("Physics step", format!("{:.2} ms", physics.update_time().as_secs_f32() * 1000.0));
+                    row("Bodies", physics.body_count().to_string());
+                    row("Draw calls", stats.draw_calls.to_string());
+                    row("Shadow draw calls", stats.shadow_draw_calls.to_string());
+                    row("Triangles", stats.triangles.to_string());
+                    row("Instances", format!("{} visible, {} culled", stats.visible_instances, stats.culled_instances));
+                });
+            });
+    }
+
+    /// Takes what the last frame painted, or `None` if it was already taken or the UI is off.
+    pub(crate) fn take_output(&mut self) -> Option<UiOutput> {
+        self.output.take()
+    }
+}
+
+fn shader_error_window(context: &egui::Context, error: &str) {
+    egui::Window::new("Shader error")
+        .default_pos(egui::pos2(8.0, 200.0))
+        .default_width(520.0)
+        .show(context, |ui| {
+            ui.label("The scene shaders failed to reload; the last ones that compiled are in use.");
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                ui.label(egui::RichText::new(error).monospace().color(ui.visuals().error_fg_color));
+            });
+        });
+}
+
+fn pointer_button(button: MouseButton) -> Option<egui::PointerButton> {
+    match button {
+        MouseButton::Left => Some(egui::PointerButton::Primary),
+        MouseButton::Right => Some(egui::PointerButton::Secondary),
+        MouseButton::Middle => Some(egui::PointerButton::Middle),
+        MouseButton::Back => Some(egui::PointerButton::Extra1),
+        MouseButton::Forward => Some(egui::PointerButton::Extra2),
+        MouseButton::Other(_) => None,
+    }
+}
+
+/// The egui key for the keys a UI uses, by their position on the keyboard.
+fn key(code: KeyCode) -> Option<egui::Key> {
+    use egui::Key;
+
+    Some(match code {
+        KeyCode::ArrowDown => Key::ArrowDown,
+        KeyCode::ArrowLeft => Key::ArrowLeft,
+        KeyCode::ArrowRight => Key::ArrowRight,
+        KeyCode::ArrowUp => Key::ArrowUp,
+        KeyCode::Escape => Key::Escape,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Enter | KeyCode::NumpadEnter => Key::Enter,
+        KeyCode::Space => Key::Space,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Minus | KeyCode::NumpadSubtract => Key::Minus,
+        KeyCode::Equal => Key::Equals,
+        KeyCode::NumpadAdd => Key::Plus,
+        KeyCode::Digit0 | KeyCode::Numpad0 => Key::Num0,
+        KeyCode::Digit1 | KeyCode::Numpad1 => Key::Num1,
+        KeyCode::Digit2 | KeyCode::Numpad2 => Key::Num2,
+        KeyCode::Digit3 | KeyCode::Numpad3 => Key::Num3,
+        KeyCode::Digit4 | KeyCode::Numpad4 => Key::Num4,
+        KeyCode::Digit5 | KeyCode::Numpad5 => Key::Num5,
+        KeyCode::Digit6 | KeyCode::Numpad6 => Key::Num6,
+        KeyCode::Digit7 | KeyCode::Numpad7 => Key::Num7,
+        KeyCode::Digit8 | KeyCode::Numpad8 => Key::Num8,
+        KeyCode::Digit9 | KeyCode::Numpad9 => Key::Num9,
+        KeyCode::KeyA => Key::A,
+        KeyCode::KeyB => Key::B,
+        KeyCode::KeyC => Key::C,
+        KeyCode::KeyD => Key::D,
+        KeyCode::KeyE => Key::E,
+        KeyCode::KeyF => Key::F,
+        KeyCode::KeyG => Key::G,
+        KeyCode::KeyH => Key::H,
+        KeyCode::KeyI => Key::I,
+        KeyCode::KeyJ => Key::J,
+        KeyCode::KeyK => Key::K,
+        KeyCode::KeyL => Key::L,
+        KeyCode::KeyM => Key::M,
+        KeyCode::KeyN => Key::N,
+        KeyCode::KeyO => Key::O,
+        KeyCode::KeyP => Key::P,
+        KeyCode::KeyQ => Key::Q,
+        KeyCode::KeyR => Key::R,
+        KeyCode::KeyS => Key::S,
+        KeyCode::KeyT => Key::T,
+        KeyCode::KeyU => Key::U,
+        KeyCode::KeyV => Key::V,
+        KeyCode::KeyW => Key::W,
+        KeyCode::KeyX => Key::X,
+        KeyCode::KeyY => Key::Y,
+        KeyCode::KeyZ => Key::Z,
+        _ => return None,
+    })
+}
+
+/// GPU side of the `DebugUi`: egui's textures and the primitives of the last frame,
+/// drawn straight into the surface after post-processing.
+pub(crate) struct UiRenderer {
+    renderer: egui_wgpu::Renderer,
+    primitives: Vec<egui::ClippedPrimitive>,
+    /// Textures the last frame no longer needs, freed once it is drawn.
+    free: Vec<egui::TextureId>,
+    pixels_per_point: f32,
+}
+
+impl UiRenderer {
+    pub(crate) fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
+        Self {
+            renderer: egui_wgpu::Renderer::new(device, surface_format, None, 1, false),
+            primitives: Vec::new(),
+            free: Vec::new(),
+            pixels_per_point: 1.0,
+        }
+    }
+
+    /// Takes over a painted frame, or draws nothing for `None`.
+    pub(crate) fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, output: Option<UiOutput>) {
+        for id in self.free.drain(..) {
+            self.renderer.free_texture(&id);
+        }
+        let Some(output) = output else {
+            self.primitives.clear();
+            return;
+        };
+
+        for (id, delta) in &output.textures.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.free = output.textures.free;
+        self.primitives = output.primitives;
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    pub(crate) fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: [u32; 2],
+    ) {
+        if self.primitives.is_empty() {
+            return;
+        }
+
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: size,
+            pixels_per_point: self.pixels_per_point,
+        };
+        let commands = self.renderer.update_buffers(device, queue, encoder, &self.primitives, &screen);
+        if !commands.is_empty() {
+            queue.submit(commands);
+        }
+
+        let pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("UI Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        self.renderer.render(&mut pass.forget_lifetime(), &self.primitives, &screen);
+    }
+}
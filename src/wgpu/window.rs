@@ -1,95 +1,10 @@
-use cgmath::prelude::*;
-use rayon::prelude::*;
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 use super::*;
 use model::Vertex;
+use std::borrow::Cow;
 use std::sync::{Arc, Mutex};
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-
-pub(crate) struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
-    scale: cgmath::Vector3<f32>
-}
-
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation)
-                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
-            .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-        }
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-#[allow(dead_code)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
-    normal: [[f32; 3]; 3],
-}
-
-impl model::Vertex for InstanceRaw {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
-                    shader_location: 9,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
-                    shader_location: 10,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
-                    shader_location: 11,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
-        }
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub(crate) struct LightUniform {
-    position: [f32; 3],
-    _padding: u32,
-    color: [f32; 3],
-    _padding2: u32,
-}
-
 #[allow(dead_code)]
 struct Scene {
 
@@ -97,63 +12,89 @@ struct Scene {
 
 #[allow(dead_code)]
 pub(crate) struct RenderData {
-    pub instance_buffer: wgpu::Buffer,
     pub depth_texture: texture::Texture,
+    /// Multisampled color target resolved into the HDR target, when MSAA is on.
+    pub msaa_texture: Option<wgpu::TextureView>,
+    pub sample_count: u32,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub obj_light: model::Model,
-    pub light_uniform: LightUniform,
-    pub light_buffer: wgpu::Buffer,
-    pub light_bind_group: wgpu::BindGroup,
+    pub lights: LightBuffers,
+    pub light_bind_group_layout: wgpu::BindGroupLayout,
+    pub texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub light_render_pipeline: wgpu::RenderPipeline,
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub obj_model: model::Model,
+    pub render_pipelines: BlendPipelines,
+    /// Sources `render_pipelines` and `light_render_pipeline` were built from.
+    pub shaders: SceneShaders,
     pub projection: camera::Projection,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
-    pub instances: Vec<Instance>,
+    /// Generation of the joint buffer bound by `camera_bind_group`.
+    pub camera_joint_generation: u64,
+    pub scene: SceneRenderer,
+    pub materials: MaterialRenderer,
+    pub debug_lines: DebugLinesRenderer,
+    pub environment: EnvironmentBuffers,
+    pub post: PostProcessor,
+    pub ui: UiRenderer,
 }
 
 pub(crate) struct State<'a> {
     pub window: &'a Window,
     pub surface: wgpu::Surface<'a>,
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
     pub config: wgpu::SurfaceConfiguration,
+    pub adapter: wgpu::Adapter,
+    /// Settings the renderer was last set up with, as requested by the game.
+    pub settings: RenderSettings,
+    pub assets: AssetServer,
     pub render_data: RenderData,
+    /// Error of the last reloaded scene shaders, shown in the debug UI until they compile.
+    pub shader_error: Option<String>,
 }
 
+/// Sources of the model and light shaders: the built-in ones, or the ones last reloaded
+/// from the engine's source directory. The model shader is appended to the prelude it
+/// shares with custom material shaders.
+#[derive(Clone)]
+pub(crate) struct SceneShaders {
+    pub prelude: Cow<'static, str>,
+    pub scene: Cow<'static, str>,
+    pub light: Cow<'static, str>,
+}
 
-impl<'a> State<'a> {
-    pub(crate) async fn new(window: &'a Window, camera: Arc<Mutex<Camera>>) -> State<'a> {
-        let size = window.inner_size();
+impl Default for SceneShaders {
+    fn default() -> Self {
+        Self {
+            prelude: Cow::Borrowed(include_str!("scene_prelude.wgsl")),
+            scene: Cow::Borrowed(include_str!("shader.wgsl")),
+            light: Cow::Borrowed(include_str!("light.wgsl")),
+        }
+    }
+}
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
-            ..Default::default()
-        });
 
-        let surface = instance.create_surface(window).unwrap();
+impl<'a> State<'a> {
+    pub(crate) async fn new(
+        window: &'a Window,
+        camera: Arc<Mutex<Camera>>,
+        settings: &RenderSettings,
+        assets: &AssetServer,
+        shaders: SceneShaders,
+    ) -> anyhow::Result<State<'a>> {
+        let size = window.inner_size();
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-        .await
-            .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::default(),
-                    memory_hints: Default::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (surface, adapter, device, queue) = match Self::create_device(window, settings).await {
+            Ok(device) => device,
+            Err(e) if settings.needs_new_device(&RenderSettings::default()) => {
+                log::warn!("{e:#}, using the default backend and adapter");
+                Self::create_device(window, &RenderSettings::default()).await?
+            }
+            Err(e) => return Err(e),
+        };
+        // Shared with the tasks loading models in the background
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -167,50 +108,13 @@ impl<'a> State<'a> {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: settings.surface_present_mode(&surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             desired_maximum_frame_latency: 2,
             view_formats: vec![],
         };
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = Arc::new(model::Material::create_bind_group_layout(&device));
 
         let projection = camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 1000.0);
 
@@ -222,177 +126,88 @@ impl<'a> State<'a> {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        const SPACE_BETWEEN: f32 = 30.0;
-        let iter = (0..NUM_INSTANCES_PER_ROW)
-            .into_par_iter();
-
-        let instances = iter
-            .clone()
-            .flat_map(|z| {
-                iter.clone().map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-
-                    let scale = cgmath::Vector3 { x: 1.0, y: 1.0, z: 1.0 };
-
-                    Instance { position, rotation, scale }
-                })
-            })
-        .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        let scene = SceneRenderer::new(&device, assets, &texture_bind_group_layout);
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    SceneRenderer::joint_layout_entry(1),
+                ],
                 label: Some("camera_bind_group_layout"),
             });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let obj_model =
-            resources::load_model("semi.obj", &device, &queue, &texture_bind_group_layout)
-            .await
-            .unwrap();
+        let camera_bind_group =
+            create_camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer, scene.joint_buffer());
 
         let obj_light =
-            resources::load_model("ball.obj", &device, &queue, &texture_bind_group_layout)
-            .await
-            .unwrap();
+            resources::load_model("ball.obj", assets, &device, &queue, &texture_bind_group_layout).await?;
 
-        let light_uniform = LightUniform {
-            position: [0.0, 15.0, 10.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
+        let light_bind_group_layout = LightBuffers::create_bind_group_layout(&device);
+        let lights = LightBuffers::new(&device, &light_bind_group_layout, &texture_bind_group_layout);
 
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: None,
-            });
+        let sample_count = settings.supported_sample_count(
+            &adapter,
+            device.features(),
+            &[post::HDR_FORMAT, texture::Texture::DEPTH_FORMAT],
+        );
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
+        let environment = EnvironmentBuffers::new(&device, &queue, post::HDR_FORMAT, &camera_bind_group_layout, sample_count);
 
+        let (width, height) = settings.scaled_size(config.width, config.height);
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
+            texture::Texture::create_depth_texture(&device, width, height, sample_count, "depth_texture");
+        let msaa_texture = create_msaa_texture(&device, width, height, sample_count);
+        let post = PostProcessor::new(&device, &queue, config.format, &depth_texture.view, sample_count, width, height);
+
+        let (render_pipelines, light_render_pipeline) = create_scene_pipelines(
+            &device,
+            &texture_bind_group_layout,
+            &camera_bind_group_layout,
+            &light_bind_group_layout,
+            environment.layout(),
+            &shaders,
+            sample_count,
+        );
 
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let materials = MaterialRenderer::new(&device, &queue, shaders.prelude.clone(), sample_count);
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
-        };
+        let debug_lines = DebugLinesRenderer::new(&device, post::HDR_FORMAT, &camera_bind_group_layout, sample_count);
+
+        let ui = UiRenderer::new(&device, config.format);
 
         let render_data = RenderData {
-            render_pipeline,
-            obj_model,
+            render_pipelines,
+            shaders,
             projection,
             camera_buffer,
             camera_bind_group,
-            instances,
-            instance_buffer,
+            camera_joint_generation: 0,
+            scene,
+            materials,
             depth_texture,
+            msaa_texture,
+            sample_count,
             size,
             obj_light,
-            light_uniform,
-            light_buffer,
-            light_bind_group,
+            lights,
+            light_bind_group_layout,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
             light_render_pipeline,
+            debug_lines,
+            environment,
+            post,
+            ui,
         };
 
         let state = State {
@@ -401,10 +216,58 @@ impl<'a> State<'a> {
             device,
             queue,
             config,
+            adapter,
+            settings: *settings,
+            assets: assets.clone(),
             render_data,
+            shader_error: None,
         };
 
-        state
+        Ok(state)
+    }
+
+    /// Creates the surface, picks an adapter for it and opens the device. Fails when
+    /// `settings` ask for a backend or adapter this machine does not have, or the device
+    /// can't be created on it.
+    async fn create_device(
+        window: &'a Window,
+        settings: &RenderSettings,
+    ) -> anyhow::Result<(wgpu::Surface<'a>, wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: settings.backend.to_wgpu(),
+            ..Default::default()
+        });
+
+        let surface = instance.create_surface(window)?;
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: settings.adapter.power_preference(),
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: settings.adapter == AdapterPreference::Software,
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no {:?} adapter for the {:?} backend", settings.adapter, settings.backend))?;
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    // Lets the supported MSAA sample counts go beyond what every GPU supports, and
+                    // BCn compressed textures load where the GPU can sample them
+                    required_features: adapter.features()
+                        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                            | wgpu::Features::TEXTURE_COMPRESSION_BC),
+                    // Software and GL adapters often fall short of the default limits
+                    required_limits: adapter.limits(),
+                    memory_hints: Default::default(),
+                },
+                None,
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("could not create a device on {}: {e}", adapter.get_info().name))?;
+
+        Ok((surface, adapter, device, queue))
     }
 
     pub(crate) fn window(&self) -> &Window {
@@ -418,7 +281,135 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.render_data.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.recreate_targets();
+        }
+    }
+
+    /// Recreates the size and sample count dependent render targets.
+    fn recreate_targets(&mut self) {
+        let render_data = &mut self.render_data;
+        let (width, height) = self.settings.scaled_size(self.config.width, self.config.height);
+        render_data.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, width, height, render_data.sample_count, "depth_texture");
+        render_data.msaa_texture = create_msaa_texture(&self.device, width, height, render_data.sample_count);
+        render_data.post.resize(&self.device, &render_data.depth_texture.view, render_data.sample_count, width, height);
+    }
+
+    /// Applies changed settings by recreating the affected pipelines and render targets.
+    /// Returns `false` when the change needs a new device, which the caller has to create
+    /// with `State::new`.
+    pub(crate) fn apply_render_settings(&mut self, settings: &RenderSettings) -> bool {
+        if self.settings == *settings {
+            return true;
+        }
+        if self.settings.needs_new_device(settings) {
+            return false;
+        }
+
+        if self.settings.present_mode != settings.present_mode {
+            let present_modes = self.surface.get_capabilities(&self.adapter).present_modes;
+            self.config.present_mode = settings.surface_present_mode(&present_modes);
+            self.surface.configure(&self.device, &self.config);
+        }
+
+        let resized = self.settings.resolution_scale != settings.resolution_scale;
+        let sample_count = if self.settings.sample_count != settings.sample_count {
+            settings.supported_sample_count(
+                &self.adapter,
+                self.device.features(),
+                &[post::HDR_FORMAT, texture::Texture::DEPTH_FORMAT],
+            )
+        } else {
+            self.render_data.sample_count
+        };
+        self.settings = *settings;
+
+        let render_data = &mut self.render_data;
+        if sample_count != render_data.sample_count {
+            render_data.sample_count = sample_count;
+            (render_data.render_pipelines, render_data.light_render_pipeline) = create_scene_pipelines(
+                &self.device,
+                &render_data.texture_bind_group_layout,
+                &render_data.camera_bind_group_layout,
+                &render_data.light_bind_group_layout,
+                render_data.environment.layout(),
+                &render_data.shaders,
+                sample_count,
+            );
+            render_data.materials.set_sample_count(sample_count);
+            render_data.debug_lines =
+                DebugLinesRenderer::new(&self.device, post::HDR_FORMAT, &render_data.camera_bind_group_layout, sample_count);
+            render_data.environment.set_sample_count(
+                &self.device,
+                post::HDR_FORMAT,
+                &render_data.camera_bind_group_layout,
+                sample_count,
+            );
+            self.recreate_targets();
+        } else if resized {
+            self.recreate_targets();
+        }
+        true
+    }
+
+    /// Reloads what changed on disk while the asset server hot reloads. Scene shaders that
+    /// fail to compile keep the previous pipelines, and their error is kept in `shader_error`
+    /// until they are fixed.
+    pub(crate) fn hot_reload(&mut self, scene: &SceneManager) {
+        let changes = self.assets.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+
+        let render_data = &mut self.render_data;
+        render_data.scene.reload(scene, &changes.files);
+        render_data.environment.reload(&changes.files);
+        render_data.post.reload(&changes.files);
+        render_data.materials.reload(&changes.files);
+        if ["scene_prelude.wgsl", "shader.wgsl", "light.wgsl"].iter().any(|file_name| changes.shaders.contains(*file_name)) {
+            self.reload_shaders();
+        }
+    }
+
+    fn reload_shaders(&mut self) {
+        let Some(shader_dir) = self.assets.shader_dir() else {
+            return;
+        };
+        let read = |file_name| std::fs::read_to_string(shader_dir.join(file_name));
+        let shaders = match (read("scene_prelude.wgsl"), read("shader.wgsl"), read("light.wgsl")) {
+            (Ok(prelude), Ok(scene), Ok(light)) => SceneShaders {
+                prelude: prelude.into(),
+                scene: scene.into(),
+                light: light.into(),
+            },
+            // Editors that save by replacing the file leave it missing for a moment
+            _ => return,
+        };
+
+        let render_data = &mut self.render_data;
+        let pipelines = post::catch_validation(&self.device, || {
+            create_scene_pipelines(
+                &self.device,
+                &render_data.texture_bind_group_layout,
+                &render_data.camera_bind_group_layout,
+                &render_data.light_bind_group_layout,
+                render_data.environment.layout(),
+                &shaders,
+                render_data.sample_count,
+            )
+        });
+        match pipelines {
+            Ok(pipelines) => {
+                (render_data.render_pipelines, render_data.light_render_pipeline) = pipelines;
+                render_data.materials.set_prelude(shaders.prelude.clone());
+                render_data.shaders = shaders;
+                self.shader_error = None;
+            }
+            Err(error) => {
+                let error = error.to_string();
+                log::error!("failed to reload the scene shaders: {}", error);
+                self.shader_error = Some(error);
+            }
         }
     }
 
@@ -429,48 +420,255 @@ impl<'a> State<'a> {
             0,
             &camera.lock().unwrap().get_uniform(),
         );
+    }
 
-        let old_position: cgmath::Vector3<_> = self.render_data.light_uniform.position.into();
-        self.render_data.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-             * old_position)
-            .into();
-        self.queue.write_buffer(
-            &self.render_data.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.render_data.light_uniform]),
+    pub(crate) fn update_lights(&mut self, lights: &LightManager, camera: &Camera) {
+        let render_data = &mut self.render_data;
+        render_data.lights.upload(
+            &self.device,
+            &self.queue,
+            &render_data.light_bind_group_layout,
+            lights,
+            camera,
+            &render_data.projection,
+        );
+    }
+
+    pub(crate) fn update_scene(&mut self, scene: &mut SceneManager, camera: &Camera) {
+        let render_data = &mut self.render_data;
+        let mut stats = render_data.scene.upload(
+            &self.device,
+            &self.queue,
+            scene,
+            &render_data.materials,
+            camera,
+            &render_data.projection,
+        );
+        for (handle, state) in render_data.scene.load_states() {
+            scene.set_load_state(handle, state);
+        }
+        stats.shadow_draw_calls = render_data.scene.shadow_draw_calls() * render_data.lights.shadows.layer_count();
+        scene.set_stats(stats);
+
+        if render_data.camera_joint_generation != render_data.scene.joint_generation {
+            render_data.camera_joint_generation = render_data.scene.joint_generation;
+            render_data.camera_bind_group = create_camera_bind_group(
+                &self.device,
+                &render_data.camera_bind_group_layout,
+                &render_data.camera_buffer,
+                render_data.scene.joint_buffer(),
+            );
+        }
+    }
+
+    pub(crate) fn update_materials(&mut self, materials: &MaterialManager) {
+        let render_data = &mut self.render_data;
+        render_data.materials.upload(
+            &self.device,
+            &self.queue,
+            materials,
+            [
+                &render_data.camera_bind_group_layout,
+                &render_data.light_bind_group_layout,
+                render_data.environment.layout(),
+            ],
+            &self.assets,
         );
     }
+
+    pub(crate) fn update_environment(&mut self, environment: &Environment) {
+        self.render_data.environment.upload(&self.device, &self.queue, environment, &self.assets);
+    }
+
+    pub(crate) fn update_post_processing(&mut self, post_processing: &PostProcessing, time: f32) {
+        let render_data = &mut self.render_data;
+        render_data.post.upload(&self.device, &self.queue, post_processing, &render_data.projection, time, &self.assets);
+    }
+
+    pub(crate) fn update_debug_lines(&mut self, lines: &DebugLines) {
+        self.render_data.debug_lines.upload(&self.device, &self.queue, lines);
+    }
+
+    pub(crate) fn update_ui(&mut self, ui: &mut DebugUi) {
+        self.render_data.ui.upload(&self.device, &self.queue, ui.take_output());
+    }
 }
 
-fn create_render_pipeline(
+/// Builds the model pipelines for every blend mode and the light pipeline, which draw
+/// into the HDR target.
+fn create_scene_pipelines(
+    device: &wgpu::Device,
+    texture_bind_group_layout: &wgpu::BindGroupLayout,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+    light_bind_group_layout: &wgpu::BindGroupLayout,
+    environment_bind_group_layout: &wgpu::BindGroupLayout,
+    shaders: &SceneShaders,
+    sample_count: u32,
+) -> (BlendPipelines, wgpu::RenderPipeline) {
+    let render_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[
+                texture_bind_group_layout,
+                camera_bind_group_layout,
+                light_bind_group_layout,
+                environment_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+    let render_pipelines = BlendPipelines::new(
+        device,
+        &render_pipeline_layout,
+        wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Shader"),
+            source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", shaders.prelude, shaders.scene).into()),
+        },
+        sample_count,
+    );
+
+    let light_render_pipeline = {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders.light.clone()),
+        });
+        create_render_pipeline(
+            device,
+            &layout,
+            &[model::ModelVertex::desc()],
+            &shader,
+            BlendMode::Opaque,
+            sample_count,
+        )
+    };
+
+    (render_pipelines, light_render_pipeline)
+}
+
+/// Binds the camera uniform together with the skinning matrices of the scene.
+fn create_camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    joint_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: joint_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("camera_bind_group"),
+    })
+}
+
+/// Color target the scene is drawn into before being resolved, or `None` without MSAA.
+fn create_msaa_texture(device: &wgpu::Device, width: u32, height: u32, sample_count: u32) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Target"),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: post::HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+/// Pipelines drawing scene meshes with one shader, one for each way of blending them.
+/// Cutout materials discard their fragments in the shader and share the opaque one.
+pub(crate) struct BlendPipelines {
+    opaque: wgpu::RenderPipeline,
+    alpha: wgpu::RenderPipeline,
+    premultiplied: wgpu::RenderPipeline,
+    additive: wgpu::RenderPipeline,
+    multiply: wgpu::RenderPipeline,
+}
+
+impl BlendPipelines {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: wgpu::ShaderModuleDescriptor,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(shader);
+        let create = |blend_mode| {
+            create_render_pipeline(
+                device,
+                layout,
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                &shader,
+                blend_mode,
+                sample_count,
+            )
+        };
+        Self {
+            opaque: create(BlendMode::Opaque),
+            alpha: create(BlendMode::Alpha),
+            premultiplied: create(BlendMode::Premultiplied),
+            additive: create(BlendMode::Additive),
+            multiply: create(BlendMode::Multiply),
+        }
+    }
+
+    pub(crate) fn get(&self, blend_mode: BlendMode) -> &wgpu::RenderPipeline {
+        match blend_mode {
+            BlendMode::Opaque | BlendMode::Cutout(_) => &self.opaque,
+            BlendMode::Alpha => &self.alpha,
+            BlendMode::Premultiplied => &self.premultiplied,
+            BlendMode::Additive => &self.additive,
+            BlendMode::Multiply => &self.multiply,
+        }
+    }
+}
+
+/// Builds a pipeline drawing into the HDR target with depth testing. Transparent blend
+/// modes leave the depth buffer as it is.
+pub(crate) fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
+    shader: &wgpu::ShaderModule,
+    blend_mode: BlendMode,
+    sample_count: u32,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some(&format!("{:?}", shader)),
         layout: Some(layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             buffers: vertex_layouts,
             compilation_options: Default::default(),
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                format: post::HDR_FORMAT,
+                blend: Some(blend_mode.blend_state()),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
             compilation_options: Default::default(),
@@ -484,15 +682,15 @@ fn create_render_pipeline(
             unclipped_depth: false,
             conservative: false,
         },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: !blend_mode.is_transparent(),
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -1,2 +1,5 @@
+mod window;
+
 pub use winit::event::*;
 pub use winit::keyboard::*;
+pub use window::{CursorGrab, FullscreenMode, WindowConfig};
@@ -0,0 +1,222 @@
+use winit::dpi::PhysicalSize;
+use winit::event_loop::EventLoop;
+use winit::window::{CursorGrabMode, Fullscreen, Icon, Window, WindowBuilder};
+
+use crate::wgpu::AssetServer;
+
+/// How the window covers the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    #[default]
+    Windowed,
+    /// A borderless window the size of the current monitor.
+    Borderless,
+    /// Takes over the monitor with the video mode closest to the window size.
+    Exclusive,
+}
+
+/// How the cursor is kept inside the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorGrab {
+    /// The cursor moves freely, e.g. while a menu is open.
+    #[default]
+    None,
+    /// The cursor can move, but not leave the window.
+    Confined,
+    /// The cursor stays in place, for mouse look.
+    Locked,
+}
+
+/// Window settings, applied when the game starts and again whenever a gear changes them.
+///
+/// Not every platform supports every cursor grab mode: `Locked` falls back to `Confined`
+/// and the other way around, and the cursor is left free when neither works.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowConfig {
+    pub title: String,
+    /// Inner size in physical pixels, or `None` for the platform default.
+    pub size: Option<(u32, u32)>,
+    pub fullscreen: FullscreenMode,
+    pub resizable: bool,
+    /// Icon image, relative to the `res` directory.
+    pub icon: Option<String>,
+    pub cursor_grab: CursorGrab,
+    pub cursor_visible: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: env!("CARGO_PKG_NAME").to_string(),
+            size: None,
+            fullscreen: FullscreenMode::default(),
+            resizable: true,
+            icon: None,
+            cursor_grab: CursorGrab::Locked,
+            cursor_visible: false,
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn with_fullscreen(mut self, fullscreen: FullscreenMode) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_string());
+        self
+    }
+
+    pub fn with_cursor(mut self, grab: CursorGrab, visible: bool) -> Self {
+        self.cursor_grab = grab;
+        self.cursor_visible = visible;
+        self
+    }
+
+    /// Switches between a window and borderless fullscreen.
+    pub fn toggle_fullscreen(&mut self) {
+        self.fullscreen = match self.fullscreen {
+            FullscreenMode::Windowed => FullscreenMode::Borderless,
+            _ => FullscreenMode::Windowed,
+        };
+    }
+
+    /// Frees and shows the cursor.
+    pub fn release_cursor(&mut self) {
+        self.cursor_grab = CursorGrab::None;
+        self.cursor_visible = true;
+    }
+
+    /// Locks and hides the cursor for mouse look.
+    pub fn capture_cursor(&mut self) {
+        self.cursor_grab = CursorGrab::Locked;
+        self.cursor_visible = false;
+    }
+
+    pub fn is_cursor_captured(&self) -> bool {
+        self.cursor_grab != CursorGrab::None
+    }
+
+    pub(crate) fn build<T>(&self, event_loop: &EventLoop<T>, assets: &AssetServer) -> Window {
+        let mut builder = WindowBuilder::new()
+            .with_title(&self.title)
+            .with_resizable(self.resizable)
+            .with_window_icon(self.load_icon(assets));
+        if let Some((width, height)) = self.size {
+            builder = builder.with_inner_size(PhysicalSize::new(width, height));
+        }
+
+        let window = builder.build(event_loop).unwrap();
+        window.set_fullscreen(self.fullscreen(&window));
+        self.apply_cursor(&window);
+        window
+    }
+
+    /// Applies what changed since `previous` to `window`.
+    pub(crate) fn apply(&self, window: &Window, previous: &WindowConfig, assets: &AssetServer) {
+        if self.title != previous.title {
+            window.set_title(&self.title);
+        }
+        if self.resizable != previous.resizable {
+            window.set_resizable(self.resizable);
+        }
+        if self.icon != previous.icon {
+            window.set_window_icon(self.load_icon(assets));
+        }
+        if self.size != previous.size {
+            if let Some((width, height)) = self.size {
+                let _ = window.request_inner_size(PhysicalSize::new(width, height));
+            }
+        }
+        if self.fullscreen != previous.fullscreen {
+            window.set_fullscreen(self.fullscreen(window));
+        }
+        if self.cursor_grab != previous.cursor_grab || self.cursor_visible != previous.cursor_visible {
+            self.apply_cursor(window);
+        }
+    }
+
+    fn fullscreen(&self, window: &Window) -> Option<Fullscreen> {
+        match self.fullscreen {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+            FullscreenMode::Exclusive => {
+                let (width, height) = self.size.unwrap_or_else(|| window.inner_size().into());
+                let mode = window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().min_by_key(|mode| {
+                        let size = mode.size();
+                        (
+                            size.width.abs_diff(width) + size.height.abs_diff(height),
+                            u32::MAX - mode.refresh_rate_millihertz(),
+                        )
+                    })
+                });
+                match mode {
+                    Some(mode) => Some(Fullscreen::Exclusive(mode)),
+                    None => {
+                        log::warn!("no video mode for exclusive fullscreen, using borderless");
+                        Some(Fullscreen::Borderless(None))
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_cursor(&self, window: &Window) {
+        let fallback = match self.cursor_grab {
+            CursorGrab::None => CursorGrabMode::None,
+            CursorGrab::Confined => CursorGrabMode::Locked,
+            CursorGrab::Locked => CursorGrabMode::Confined,
+        };
+        let mode = match self.cursor_grab {
+            CursorGrab::None => CursorGrabMode::None,
+            CursorGrab::Confined => CursorGrabMode::Confined,
+            CursorGrab::Locked => CursorGrabMode::Locked,
+        };
+
+        if window.set_cursor_grab(mode).is_err() {
+            if let Err(error) = window.set_cursor_grab(fallback) {
+                log::warn!("cursor grab {:?} is not supported: {}", self.cursor_grab, error);
+            }
+        }
+        window.set_cursor_visible(self.cursor_visible);
+    }
+
+    fn load_icon(&self, assets: &AssetServer) -> Option<Icon> {
+        let file_name = self.icon.as_ref()?;
+        let icon = assets
+            .resolve(file_name)
+            .and_then(|path| Ok(image::open(path)?.into_rgba8()))
+            .and_then(|image| {
+                let (width, height) = image.dimensions();
+                Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+            });
+
+        match icon {
+            Ok(icon) => Some(icon),
+            Err(error) => {
+                log::warn!("failed to load window icon {}: {}", file_name, error);
+                None
+            }
+        }
+    }
+}